@@ -0,0 +1,607 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::{CacheKey, SwashCache, SwashContent};
+
+// Empty pixels between glyphs, so that filtering does not sample neighbors
+const PADDING: u32 = 1;
+
+/// Kind of images stored in an atlas page
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum AtlasKind {
+    /// Grayscale masks, with one byte per pixel
+    Mask,
+    /// Subpixel masks or multi-channel distance fields, with four bytes per pixel
+    SubpixelMask,
+    /// Color images, with RGBA bytes per pixel
+    Color,
+}
+
+impl AtlasKind {
+    /// Bytes per pixel of this kind of page
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            Self::Mask => 1,
+            Self::SubpixelMask | Self::Color => 4,
+        }
+    }
+
+    fn from_content(content: SwashContent) -> Self {
+        match content {
+            SwashContent::Mask => Self::Mask,
+            SwashContent::SubpixelMask => Self::SubpixelMask,
+            SwashContent::Color => Self::Color,
+        }
+    }
+}
+
+/// Rectangle in an atlas page, in pixels
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct AtlasRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl AtlasRect {
+    /// Smallest rectangle containing both rectangles
+    pub fn union(&self, other: &Self) -> Self {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Self {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+}
+
+/// Location of a glyph image in a [GlyphAtlas]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AtlasEntry {
+    /// Kind of the page
+    pub kind: AtlasKind,
+    /// Index of the page in [GlyphAtlas::pages]
+    pub page: usize,
+    /// Pixels of the image in the page
+    pub rect: AtlasRect,
+    /// Texture coordinates of the image, as left, top, right, and bottom from 0.0 to 1.0
+    pub uv: [f32; 4],
+    /// Horizontal offset of the image from the glyph origin
+    pub left: i32,
+    /// Vertical offset of the top of the image above the glyph origin
+    pub top: i32,
+}
+
+/// Errors from adding glyphs to a [GlyphAtlas]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AtlasError {
+    /// The glyph image is larger than a page
+    TooLarge,
+    /// All pages are full of glyphs used in the current frame
+    Full,
+}
+
+impl fmt::Display for AtlasError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::TooLarge => write!(f, "glyph image is larger than an atlas page"),
+            Self::Full => write!(f, "atlas pages are full of glyphs used in the current frame"),
+        }
+    }
+}
+
+impl std::error::Error for AtlasError {}
+
+#[derive(Clone, Copy, Debug)]
+struct SkylineNode {
+    x: u32,
+    y: u32,
+    width: u32,
+}
+
+// Skyline bottom-left packer, tracking the height of the allocated area at each x position
+#[derive(Clone, Debug)]
+struct Skyline {
+    width: u32,
+    height: u32,
+    nodes: Vec<SkylineNode>,
+}
+
+impl Skyline {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            nodes: vec![SkylineNode { x: 0, y: 0, width }],
+        }
+    }
+
+    // Top of a rectangle placed at the start of a node, if it fits
+    fn fit(&self, i: usize, width: u32, height: u32) -> Option<u32> {
+        let x = self.nodes[i].x;
+        if x + width > self.width {
+            return None;
+        }
+
+        let mut y = 0;
+        let mut remaining = width;
+        for node in self.nodes[i..].iter() {
+            if remaining == 0 {
+                break;
+            }
+            y = y.max(node.y);
+            if y + height > self.height {
+                return None;
+            }
+            remaining = remaining.saturating_sub(node.width);
+        }
+        Some(y)
+    }
+
+    // Skyline above rectangles that stay in place, including their padding
+    fn with_rects(width: u32, height: u32, rects: &[AtlasRect]) -> Self {
+        let mut heights = vec![0; width as usize];
+        for rect in rects {
+            let end = (rect.x + rect.width + PADDING).min(width);
+            for y in heights[rect.x as usize..end as usize].iter_mut() {
+                *y = (*y).max(rect.y + rect.height + PADDING);
+            }
+        }
+
+        let mut nodes: Vec<SkylineNode> = Vec::new();
+        for (x, y) in heights.into_iter().enumerate() {
+            match nodes.last_mut() {
+                Some(node) if node.y == y => node.width += 1,
+                _ => nodes.push(SkylineNode { x: x as u32, y, width: 1 }),
+            }
+        }
+        if nodes.is_empty() {
+            return Self::new(width, height);
+        }
+        Self { width, height, nodes }
+    }
+
+    fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        // Choose the lowest bottom edge, then the narrowest node
+        let mut best_opt: Option<(usize, u32, u32)> = None;
+        for i in 0..self.nodes.len() {
+            if let Some(y) = self.fit(i, width, height) {
+                let better = match best_opt {
+                    Some((_, best_y, best_width)) => {
+                        y < best_y || (y == best_y && self.nodes[i].width < best_width)
+                    },
+                    None => true,
+                };
+                if better {
+                    best_opt = Some((i, y, self.nodes[i].width));
+                }
+            }
+        }
+        let (i, y, _) = best_opt?;
+        let x = self.nodes[i].x;
+
+        // Raise the skyline under the new rectangle
+        self.nodes.insert(i, SkylineNode { x, y: y + height, width });
+        let end = x + width;
+        while i + 1 < self.nodes.len() {
+            let node = &mut self.nodes[i + 1];
+            if node.x >= end {
+                break;
+            }
+            let shrink = end - node.x;
+            if node.width <= shrink {
+                self.nodes.remove(i + 1);
+            } else {
+                node.x += shrink;
+                node.width -= shrink;
+                break;
+            }
+        }
+
+        // Merge neighbors at the same height
+        let mut j = 0;
+        while j + 1 < self.nodes.len() {
+            if self.nodes[j].y == self.nodes[j + 1].y {
+                self.nodes[j].width += self.nodes[j + 1].width;
+                self.nodes.remove(j + 1);
+            } else {
+                j += 1;
+            }
+        }
+
+        Some((x, y))
+    }
+}
+
+/// Page of a [GlyphAtlas], to be uploaded as a texture
+pub struct AtlasPage {
+    kind: AtlasKind,
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+    skyline: Skyline,
+    dirty_opt: Option<AtlasRect>,
+}
+
+impl AtlasPage {
+    fn new(kind: AtlasKind, width: u32, height: u32) -> Self {
+        Self {
+            kind,
+            width,
+            height,
+            data: vec![0; width as usize * height as usize * kind.bytes_per_pixel()],
+            skyline: Skyline::new(width, height),
+            dirty_opt: None,
+        }
+    }
+
+    /// Kind of images in the page
+    pub fn kind(&self) -> AtlasKind {
+        self.kind
+    }
+
+    /// Width of the page in pixels
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Height of the page in pixels
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Pixel data of the page, in rows of [AtlasKind::bytes_per_pixel] times width bytes
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Area changed since the last call to [GlyphAtlas::take_dirty]
+    pub fn dirty(&self) -> Option<AtlasRect> {
+        self.dirty_opt
+    }
+
+    fn mark_dirty(&mut self, rect: AtlasRect) {
+        self.dirty_opt = Some(match self.dirty_opt {
+            Some(dirty) => dirty.union(&rect),
+            None => rect,
+        });
+    }
+
+    fn uv(&self, rect: AtlasRect) -> [f32; 4] {
+        let width = self.width as f32;
+        let height = self.height as f32;
+        [
+            rect.x as f32 / width,
+            rect.y as f32 / height,
+            (rect.x + rect.width) as f32 / width,
+            (rect.y + rect.height) as f32 / height,
+        ]
+    }
+
+    // Allocate space for an image, without copying data
+    fn allocate(&mut self, width: u32, height: u32) -> Option<AtlasRect> {
+        let (x, y) = self.skyline.allocate(width + PADDING, height + PADDING)?;
+        Some(AtlasRect { x, y, width, height })
+    }
+
+    // Copy rows of pixels into a rectangle of the page
+    fn write(&mut self, rect: AtlasRect, data: &[u8], stride: usize) {
+        let bpp = self.kind.bytes_per_pixel();
+        let row_len = rect.width as usize * bpp;
+        for row in 0..rect.height as usize {
+            let src = row * stride;
+            let dst = ((rect.y as usize + row) * self.width as usize + rect.x as usize) * bpp;
+            self.data[dst..dst + row_len].copy_from_slice(&data[src..src + row_len]);
+        }
+        self.mark_dirty(rect);
+    }
+}
+
+struct AtlasSlot {
+    entry_opt: Option<AtlasEntry>,
+    frame: u64,
+}
+
+/// Packs rasterized glyphs into fixed-size pages, for use as GPU textures
+///
+/// Masks, subpixel masks, and color images are stored in separate pages. Images are placed with
+/// skyline packing, and looked up by [CacheKey]. Rendering settings are not part of the key, so
+/// call [GlyphAtlas::clear] after changing [SwashCache::settings].
+///
+/// When pages are full, glyphs not used in the current frame are evicted to free their space.
+/// Glyphs used in the current frame are never moved or evicted, so their entries stay valid until
+/// the next [GlyphAtlas::begin_frame], and [AtlasError::Full] is returned if there is no space
+/// left. Look up entries again each frame, and upload the areas returned by
+/// [GlyphAtlas::take_dirty] before drawing.
+pub struct GlyphAtlas {
+    page_size: u32,
+    max_pages_opt: Option<usize>,
+    pages: Vec<AtlasPage>,
+    slots: HashMap<CacheKey, AtlasSlot>,
+    frame: u64,
+}
+
+impl GlyphAtlas {
+    /// Create an atlas with square pages of `page_size` pixels, and at most `max_pages_opt` pages
+    /// of each kind
+    pub fn new(page_size: u32, max_pages_opt: Option<usize>) -> Self {
+        Self {
+            page_size,
+            max_pages_opt,
+            pages: Vec::new(),
+            slots: HashMap::new(),
+            frame: 0,
+        }
+    }
+
+    /// Get the pages of the atlas, indexed by [AtlasEntry::page]
+    pub fn pages(&self) -> &[AtlasPage] {
+        &self.pages
+    }
+
+    /// Start a new frame, allowing glyphs used in earlier frames to be evicted
+    pub fn begin_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    /// Get the areas of pages that changed since the last call, and mark them clean
+    pub fn take_dirty(&mut self) -> Vec<(usize, AtlasRect)> {
+        self.pages.iter_mut().enumerate().filter_map(|(page_i, page)| {
+            page.dirty_opt.take().map(|rect| (page_i, rect))
+        }).collect()
+    }
+
+    /// Remove all glyphs and pages
+    pub fn clear(&mut self) {
+        self.pages.clear();
+        self.slots.clear();
+    }
+
+    /// Get the location of a glyph, rasterizing and adding it if needed
+    ///
+    /// Returns `Ok(None)` for glyphs without an image, such as spaces. Distance fields are stored
+    /// once for every font size, and drawn scaled by [SwashCache::image_scale].
+    pub fn get(&mut self, cache: &mut SwashCache, cache_key: CacheKey) -> Result<Option<AtlasEntry>, AtlasError> {
+        let cache_key = cache.image_key(cache_key);
+        if let Some(slot) = self.slots.get_mut(&cache_key) {
+            slot.frame = self.frame;
+            return Ok(slot.entry_opt);
+        }
+
+        let entry_opt = match cache.get_image(cache_key) {
+            Some(image) if image.placement.width > 0 && image.placement.height > 0 => {
+                Some(self.insert(
+                    AtlasKind::from_content(image.content),
+                    image.placement.width,
+                    image.placement.height,
+                    &image.data,
+                    image.placement.left,
+                    image.placement.top,
+                )?)
+            },
+            _ => None,
+        };
+        self.slots.insert(cache_key, AtlasSlot {
+            entry_opt,
+            frame: self.frame,
+        });
+        Ok(entry_opt)
+    }
+
+    fn insert(
+        &mut self,
+        kind: AtlasKind,
+        width: u32,
+        height: u32,
+        data: &[u8],
+        left: i32,
+        top: i32,
+    ) -> Result<AtlasEntry, AtlasError> {
+        if width + PADDING > self.page_size || height + PADDING > self.page_size {
+            return Err(AtlasError::TooLarge);
+        }
+
+        let (page_i, rect) = match self.allocate(kind, width, height) {
+            Some(some) => some,
+            None => {
+                self.evict(kind);
+                self.allocate(kind, width, height).ok_or(AtlasError::Full)?
+            }
+        };
+
+        let page = &mut self.pages[page_i];
+        page.write(rect, data, width as usize * kind.bytes_per_pixel());
+        Ok(AtlasEntry {
+            kind,
+            page: page_i,
+            rect,
+            uv: page.uv(rect),
+            left,
+            top,
+        })
+    }
+
+    // Allocate space in a page of a kind, adding a page if allowed
+    fn allocate(&mut self, kind: AtlasKind, width: u32, height: u32) -> Option<(usize, AtlasRect)> {
+        let mut pages_of_kind = 0;
+        for (page_i, page) in self.pages.iter_mut().enumerate() {
+            if page.kind == kind {
+                if let Some(rect) = page.allocate(width, height) {
+                    return Some((page_i, rect));
+                }
+                pages_of_kind += 1;
+            }
+        }
+
+        if let Some(max_pages) = self.max_pages_opt {
+            if pages_of_kind >= max_pages {
+                return None;
+            }
+        }
+        let mut page = AtlasPage::new(kind, self.page_size, self.page_size);
+        let rect = page.allocate(width, height)?;
+        self.pages.push(page);
+        Some((self.pages.len() - 1, rect))
+    }
+
+    // Evict glyphs of a kind not used in the current frame, and free their space
+    fn evict(&mut self, kind: AtlasKind) {
+        let frame = self.frame;
+        let mut evicted_pages = Vec::new();
+        self.slots.retain(|_, slot| match slot.entry_opt {
+            Some(entry) if entry.kind == kind && slot.frame != frame => {
+                if !evicted_pages.contains(&entry.page) {
+                    evicted_pages.push(entry.page);
+                }
+                false
+            },
+            _ => true,
+        });
+
+        for page_i in evicted_pages {
+            self.reclaim(page_i);
+        }
+    }
+
+    // Clear a page except for its remaining glyphs, which stay in place, so that the space above
+    // them can be allocated again
+    fn reclaim(&mut self, page_i: usize) {
+        let rects: Vec<AtlasRect> = self.slots.values().filter_map(|slot| {
+            slot.entry_opt.filter(|entry| entry.page == page_i).map(|entry| entry.rect)
+        }).collect();
+
+        let old = &self.pages[page_i];
+        let mut page = AtlasPage::new(old.kind, old.width, old.height);
+        page.skyline = Skyline::with_rects(old.width, old.height, &rects);
+        let bpp = old.kind.bytes_per_pixel();
+        let stride = old.width as usize * bpp;
+        for rect in rects {
+            let start = rect.y as usize * stride + rect.x as usize * bpp;
+            page.write(rect, &old.data[start..], stride);
+        }
+
+        // All of the page needs to be uploaded again
+        page.dirty_opt = Some(AtlasRect { x: 0, y: 0, width: page.width, height: page.height });
+        self.pages[page_i] = page;
+    }
+}
+
+#[test]
+fn test_skyline() {
+    let mut skyline = Skyline::new(10, 10);
+    assert_eq!(skyline.allocate(4, 3), Some((0, 0)));
+    assert_eq!(skyline.allocate(4, 5), Some((4, 0)));
+    assert_eq!(skyline.allocate(2, 2), Some((8, 0)));
+    // Lowest position is on top of the first rectangle
+    assert_eq!(skyline.allocate(4, 2), Some((0, 3)));
+    assert_eq!(skyline.allocate(6, 6), None);
+    assert_eq!(skyline.allocate(1, 1), Some((8, 2)));
+    assert_eq!(skyline.allocate(10, 5), Some((0, 5)));
+    assert_eq!(skyline.allocate(1, 1), None);
+
+    // Space above rectangles that stay in place can be allocated, including their padding
+    let rects = [AtlasRect { x: 2, y: 4, width: 3, height: 2 }];
+    let mut skyline = Skyline::with_rects(10, 10, &rects);
+    assert_eq!(skyline.allocate(2, 10), Some((0, 0)));
+    assert_eq!(skyline.allocate(4, 7), Some((6, 0)));
+    assert_eq!(skyline.allocate(4, 3), Some((2, 7)));
+}
+
+#[cfg(feature = "test-fonts")]
+#[test]
+fn test_glyph_atlas() {
+    use crate::FontSystem;
+
+    let font_system = FontSystem::new_test();
+    let cache_keys = crate::buffer::test_cache_keys(&font_system, 16, "abcdefgh 💁");
+
+    let mut cache = SwashCache::new(&font_system);
+    let mut atlas = GlyphAtlas::new(24, Some(1));
+
+    let a = atlas.get(&mut cache, cache_keys[0]).unwrap().unwrap();
+    assert_eq!((a.kind, a.page, a.rect.x, a.rect.y), (AtlasKind::Mask, 0, 0, 0));
+    assert_eq!(a.uv[0], 0.0);
+    assert_eq!(a.uv[2], a.rect.width as f32 / 24.0);
+    assert_eq!(atlas.take_dirty(), [(0, a.rect)]);
+    assert!(atlas.take_dirty().is_empty());
+
+    // Spaces have no image
+    assert_eq!(atlas.get(&mut cache, cache_keys[8]), Ok(None));
+
+    // Color glyphs use their own pages
+    let emoji = atlas.get(&mut cache, cache_keys[9]).unwrap().unwrap();
+    assert_eq!((emoji.kind, emoji.page), (AtlasKind::Color, 1));
+    assert_eq!(atlas.pages()[1].data().len(), 24 * 24 * 4);
+
+    // Glyphs from the current frame are not evicted
+    let full_key = cache_keys[..8].iter().cycle().take(64).copied().find(|&cache_key| {
+        atlas.get(&mut cache, cache_key) == Err(AtlasError::Full)
+    }).unwrap();
+    atlas.take_dirty();
+
+    // After a new frame, glyphs are evicted and the page is cleared
+    atlas.begin_frame();
+    atlas.get(&mut cache, full_key).unwrap().unwrap();
+    assert_eq!(atlas.take_dirty(), [(0, AtlasRect { x: 0, y: 0, width: 24, height: 24 })]);
+    let a = atlas.get(&mut cache, cache_keys[0]).unwrap().unwrap();
+    assert_eq!(atlas.pages().len(), 2);
+    let page = &atlas.pages()[0];
+    let row = a.rect.y as usize * page.width() as usize + a.rect.x as usize;
+    let image = cache.get_image(cache_keys[0]).as_ref().unwrap();
+    assert_eq!(&page.data()[row..row + a.rect.width as usize], &image.data[..a.rect.width as usize]);
+}
+
+#[cfg(feature = "test-fonts")]
+#[test]
+fn test_glyph_atlas_frame() {
+    use crate::FontSystem;
+
+    // Check that the pixels of an entry are those of its glyph image
+    fn assert_pixels(atlas: &GlyphAtlas, cache: &mut SwashCache, cache_key: CacheKey, entry: AtlasEntry) {
+        let page = &atlas.pages()[entry.page];
+        let image = cache.get_image(cache_key).as_ref().unwrap();
+        let width = entry.rect.width as usize;
+        for row in 0..entry.rect.height as usize {
+            let start = (entry.rect.y as usize + row) * page.width() as usize + entry.rect.x as usize;
+            assert_eq!(&page.data()[start..start + width], &image.data[row * width..(row + 1) * width]);
+        }
+    }
+
+    let font_system = FontSystem::new_test();
+    let cache_keys = crate::buffer::test_cache_keys(&font_system, 16, "abcdefgh");
+
+    let mut cache = SwashCache::new(&font_system);
+    let mut atlas = GlyphAtlas::new(24, Some(1));
+
+    // Fill the page in the first frame
+    let full_key = cache_keys.iter().copied().find(|&cache_key| {
+        atlas.get(&mut cache, cache_key) == Err(AtlasError::Full)
+    }).unwrap();
+
+    // Entries from the current frame stay valid when other glyphs are evicted for a new one
+    atlas.begin_frame();
+    let b = atlas.get(&mut cache, cache_keys[1]).unwrap().unwrap();
+    let full = atlas.get(&mut cache, full_key).unwrap().unwrap();
+    assert_eq!(atlas.get(&mut cache, cache_keys[1]), Ok(Some(b)));
+    assert_pixels(&atlas, &mut cache, cache_keys[1], b);
+    assert_pixels(&atlas, &mut cache, full_key, full);
+    // Repacking would have moved the glyph to the corner
+    assert_ne!(b.rect.x, 0);
+    let (b_rect, full_rect) = (b.rect, full.rect);
+    assert!(
+        full_rect.x >= b_rect.x + b_rect.width + PADDING || b_rect.x >= full_rect.x + full_rect.width + PADDING
+        || full_rect.y >= b_rect.y + b_rect.height + PADDING || b_rect.y >= full_rect.y + full_rect.height + PADDING
+    );
+
+    // Glyphs that do not fit next to the current frame's glyphs are not added
+    let rest: Vec<_> = cache_keys.iter().map(|&cache_key| atlas.get(&mut cache, cache_key)).collect();
+    assert!(rest.contains(&Err(AtlasError::Full)));
+    assert_eq!(atlas.get(&mut cache, cache_keys[1]), Ok(Some(b)));
+    assert_eq!(atlas.get(&mut cache, full_key), Ok(Some(full)));
+    assert_pixels(&atlas, &mut cache, cache_keys[1], b);
+}
@@ -1,9 +1,11 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
-use std::ops::Range;
+use std::ops::{BitOr, BitOrAssign, Range};
 
 pub use fontdb::{Family, Stretch, Style, Weight};
 
+use crate::CacheKeyFlags;
+
 /// Text color
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct Color(pub u32);
@@ -51,13 +53,70 @@ impl Color {
     }
 }
 
+/// Lines drawn under or through text
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Decoration(u8);
+
+impl Decoration {
+    /// Line under the text, at the underline position of the font
+    pub const UNDERLINE: Self = Self(1 << 0);
+    /// Line through the text, at the strikeout position of the font
+    pub const STRIKETHROUGH: Self = Self(1 << 1);
+
+    /// No decoration
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Check if no decorations are set
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Check if all of the decorations in `other` are set
+    pub const fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Decoration {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for Decoration {
+    fn bitor_assign(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
+/// Color palette used by color fonts, from their CPAL table
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Palette {
+    /// The first palette of the font
+    #[default]
+    Default,
+    /// A palette by index, or the first palette if the font does not have it
+    Index(u16),
+    /// The first palette made for light backgrounds, or the first palette
+    Light,
+    /// The first palette made for dark backgrounds, or the first palette
+    Dark,
+}
+
 /// Text attributes
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct Attrs<'a> {
     //TODO: should this be an option?
     pub color_opt: Option<Color>,
+    pub decoration: Decoration,
     pub family: Family<'a>,
+    pub language_opt: Option<&'a str>,
     pub monospaced: bool,
+    pub palette: Palette,
     pub stretch: Stretch,
     pub style: Style,
     pub weight: Weight,
@@ -70,8 +129,11 @@ impl<'a> Attrs<'a> {
     pub fn new() -> Self {
         Self {
             color_opt: None,
+            decoration: Decoration::empty(),
             family: Family::SansSerif,
+            language_opt: None,
             monospaced: false,
+            palette: Palette::Default,
             stretch: Stretch::Normal,
             style: Style::Normal,
             weight: Weight::NORMAL,
@@ -84,18 +146,38 @@ impl<'a> Attrs<'a> {
         self
     }
 
+    /// Set [Decoration]
+    pub fn decoration(mut self, decoration: Decoration) -> Self {
+        self.decoration = decoration;
+        self
+    }
+
     /// Set [Family]
     pub fn family(mut self, family: Family<'a>) -> Self {
         self.family = family;
         self
     }
 
+    /// Set language, as a BCP 47 tag like `ja` or `zh-HK`
+    ///
+    /// This is used for shaping and for choosing fonts, in place of the system locale
+    pub fn language(mut self, language: &'a str) -> Self {
+        self.language_opt = Some(language);
+        self
+    }
+
     /// Set monospaced
     pub fn monospaced(mut self, monospaced: bool) -> Self {
         self.monospaced = monospaced;
         self
     }
 
+    /// Set [Palette] of color fonts
+    pub fn palette(mut self, palette: Palette) -> Self {
+        self.palette = palette;
+        self
+    }
+
     /// Set [Stretch]
     pub fn stretch(mut self, stretch: Stretch) -> Self {
         self.stretch = stretch;
@@ -114,21 +196,40 @@ impl<'a> Attrs<'a> {
         self
     }
 
+    /// Get only the attributes used to match fonts, with defaults for the others
+    pub fn match_attrs(&self) -> Self {
+        Self::new()
+            .family(self.family)
+            .monospaced(self.monospaced)
+            .stretch(self.stretch)
+            .style(self.style)
+            .weight(self.weight)
+    }
+
     /// Check if font matches
     pub fn matches(&self, face: &fontdb::FaceInfo) -> bool {
-        //TODO: smarter way of including emoji
-        face.post_script_name.contains("Emoji") ||
-        (
-            face.style == self.style &&
-            face.weight == self.weight &&
-            face.stretch == self.stretch &&
-            face.monospaced == self.monospaced
-        )
+        face.style == self.style &&
+        face.weight == self.weight &&
+        face.stretch == self.stretch &&
+        face.monospaced == self.monospaced
+    }
+
+    /// Get the style synthesis needed to render these attributes with a face
+    pub fn synthesis(&self, face: &fontdb::FaceInfo) -> CacheKeyFlags {
+        let mut flags = CacheKeyFlags::empty();
+        if self.weight >= Weight::SEMIBOLD && face.weight < Weight::SEMIBOLD {
+            flags |= CacheKeyFlags::FAKE_BOLD;
+        }
+        if self.style != Style::Normal && face.style == Style::Normal {
+            flags |= CacheKeyFlags::FAKE_ITALIC;
+        }
+        flags
     }
 
     /// Check if this set of attributes can be shaped with another
     pub fn compatible(&self, other: &Self) -> bool {
         self.family == other.family
+        && self.language_opt == other.language_opt
         && self.monospaced == other.monospaced
         && self.stretch == other.stretch
         && self.style == other.style
@@ -3,11 +3,12 @@
 use std::{
     cmp,
     fmt,
+    ops::Range,
     time::Instant,
 };
 use unicode_segmentation::UnicodeSegmentation;
 
-use crate::{Attrs, AttrsList, Color, FontSystem, LayoutGlyph, TextBufferLine};
+use crate::{Attrs, AttrsList, Color, Decoration, FontSystem, LayoutDecoration, LayoutGlyph, ShapeFallback, TextBufferLine};
 
 /// An action to perform on a [TextBuffer]
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -90,6 +91,90 @@ pub struct TextLayoutRun<'a> {
     pub line_y: i32,
 }
 
+impl<'a> TextLayoutRun<'a> {
+    /// Get the underlines and strikethroughs of the glyphs, using the metrics of their fonts
+    ///
+    /// Lines of neighboring glyphs with the same position, thickness, and color are merged.
+    pub fn decorations<'b>(&self, font_system: &'b FontSystem<'b>) -> Vec<LayoutDecoration> {
+        let mut decorations: Vec<LayoutDecoration> = Vec::new();
+        for glyph in self.glyphs.iter().filter(|glyph| !glyph.decoration.is_empty()) {
+            let font = match font_system.get_font(glyph.cache_key.font_id) {
+                Some(some) => some,
+                None => continue,
+            };
+            let metrics = font.metrics(glyph.cache_key.font_size);
+            let lines = [
+                (Decoration::UNDERLINE, metrics.underline_position, metrics.underline_thickness),
+                (Decoration::STRIKETHROUGH, metrics.strikeout_position, metrics.strikeout_thickness),
+            ];
+            for (kind, position, thickness) in lines {
+                if !glyph.decoration.contains(kind) {
+                    continue;
+                }
+
+                let decoration = LayoutDecoration {
+                    kind,
+                    x: glyph.x,
+                    y: -position,
+                    w: glyph.w,
+                    h: thickness,
+                    color_opt: glyph.color_opt,
+                };
+                let merged = decorations.iter_mut().rev().find(|other| {
+                    other.kind == kind &&
+                    other.y == decoration.y &&
+                    other.h == decoration.h &&
+                    other.color_opt == decoration.color_opt &&
+                    other.x <= decoration.x + decoration.w + 0.5 &&
+                    decoration.x <= other.x + other.w + 0.5
+                });
+                match merged {
+                    Some(other) => {
+                        let end = (other.x + other.w).max(decoration.x + decoration.w);
+                        other.x = other.x.min(decoration.x);
+                        other.w = end - other.x;
+                    },
+                    None => decorations.push(decoration),
+                }
+            }
+        }
+        decorations
+    }
+}
+
+// Item drawn by a buffer, a rectangle or a glyph at a position
+#[cfg(feature = "swash")]
+pub(crate) enum DrawItem {
+    Rect(i32, i32, u32, u32, Color),
+    Glyph(crate::CacheKey, i32, i32, Color),
+}
+
+/// A rectangle or glyph image to draw, see [TextBuffer::draw_commands]
+#[cfg(feature = "swash")]
+pub enum DrawCommand<'a> {
+    /// Fill a rectangle, used for selections, cursors, and decorations
+    Rect {
+        x: i32,
+        y: i32,
+        w: u32,
+        h: u32,
+        color: Color,
+    },
+    /// Draw a glyph image with its origin at `x` and `y`
+    ///
+    /// The top left of the image is at `x + image.placement.left` and `y - image.placement.top`.
+    /// Masks are drawn with `color`, color images with their own colors. Distance fields are drawn
+    /// with the image and its placement multiplied by `scale`, which is 1.0 for other images.
+    Image {
+        cache_key: crate::CacheKey,
+        x: i32,
+        y: i32,
+        image: &'a crate::SwashImage,
+        color: Color,
+        scale: f32,
+    },
+}
+
 /// An iterator of visible text lines, see [TextLayoutRun]
 pub struct TextLayoutRunIter<'a, 'b> {
     buffer: &'b TextBuffer<'a>,
@@ -179,6 +264,7 @@ impl fmt::Display for TextMetrics {
 /// A buffer of text that is shaped and laid out
 pub struct TextBuffer<'a> {
     font_system: &'a FontSystem<'a>,
+    font_generation: usize,
     /// Lines (or paragraphs) of text in the buffer
     pub lines: Vec<TextBufferLine<'a>>,
     metrics: TextMetrics,
@@ -204,6 +290,7 @@ impl<'a> TextBuffer<'a> {
     ) -> Self {
         let mut buffer = Self {
             font_system,
+            font_generation: font_system.generation(),
             lines: Vec::new(),
             metrics,
             width: 0,
@@ -219,8 +306,25 @@ impl<'a> TextBuffer<'a> {
         buffer
     }
 
+    // Reset all lines if fonts were reloaded, as their glyphs refer to old fonts
+    fn check_font_generation(&mut self) {
+        let font_generation = self.font_system.generation();
+        if font_generation != self.font_generation {
+            log::debug!("fonts reloaded, resetting {} lines", self.lines.len());
+            for line in self.lines.iter_mut() {
+                line.reset();
+            }
+            self.font_generation = font_generation;
+            self.redraw = true;
+        }
+    }
+
     /// Pre-shape lines in the buffer, up to `lines`, return actual number of layout lines
+    ///
+    /// If fonts were reloaded with [FontSystem::reload], all lines are reshaped
     pub fn shape_until(&mut self, lines: i32) -> i32 {
+        self.check_font_generation();
+
         let instant = Instant::now();
 
         let mut reshaped = 0;
@@ -252,6 +356,8 @@ impl<'a> TextBuffer<'a> {
 
     /// Shape lines until cursor, also scrolling to include cursor in view
     pub fn shape_until_cursor(&mut self) {
+        self.check_font_generation();
+
         let instant = Instant::now();
 
         let mut reshaped = 0;
@@ -311,6 +417,8 @@ impl<'a> TextBuffer<'a> {
     }
 
     fn relayout(&mut self) {
+        self.check_font_generation();
+
         let instant = Instant::now();
 
         for line in self.lines.iter_mut() {
@@ -867,15 +975,142 @@ impl<'a> TextBuffer<'a> {
         new_cursor_opt
     }
 
+    /// Get the ranges of lines that were shaped with fallback fonts, with their line index
+    ///
+    /// Only shaped lines are included, use [Self::shape_until] to shape more lines
+    pub fn fallbacks(&self) -> Vec<(usize, ShapeFallback)> {
+        let mut fallbacks = Vec::new();
+        for (line_i, line) in self.lines.iter().enumerate() {
+            if let Some(shape) = line.shape_opt() {
+                fallbacks.extend(shape.fallbacks().into_iter().map(|fallback| (line_i, fallback)));
+            }
+        }
+        fallbacks
+    }
+
+    /// Get the ranges of lines with missing glyphs, with their line index
+    ///
+    /// These characters could not be rendered by any font. Only shaped lines are included, use
+    /// [Self::shape_until] to shape more lines
+    pub fn missing(&self) -> Vec<(usize, Range<usize>)> {
+        let mut missing = Vec::new();
+        for (line_i, line) in self.lines.iter().enumerate() {
+            if let Some(shape) = line.shape_opt() {
+                missing.extend(shape.missing().into_iter().map(|range| (line_i, range)));
+            }
+        }
+        missing
+    }
+
     /// Get the visible layout runs for rendering and other tasks
+    ///
+    /// After fonts are reloaded, lines must be reshaped first with [Self::shape_until_scroll]
     pub fn layout_runs<'b>(&'b self) -> TextLayoutRunIter<'a, 'b> {
         TextLayoutRunIter::new(self)
     }
 
-    /// Draw the buffer
+    /// Get the outlines of the visible text, as paths in buffer coordinates
+    ///
+    /// The callback receives one path for each glyph with an outline, and one rectangle for each
+    /// decoration, with the color to fill it with.
+    #[cfg(feature = "swash")]
+    pub fn outlines<F>(&self, cache: &mut crate::SwashCache, color: Color, mut f: F)
+        where F: FnMut(&[crate::PathCommand], Color)
+    {
+        let mut path = Vec::new();
+        for run in self.layout_runs() {
+            let line_y = run.line_y as f32;
+
+            for glyph in run.glyphs.iter() {
+                let commands = match cache.get_outline(glyph.cache_key) {
+                    Some(some) if !some.is_empty() => some,
+                    _ => continue,
+                };
+                let x = glyph.x_int as f32;
+                let y = line_y + glyph.y_int as f32;
+                path.clear();
+                path.extend(commands.into_iter().map(|command| command.translate(x, y)));
+                f(&path, glyph.color_opt.unwrap_or(color));
+            }
+
+            for decoration in run.decorations(self.font_system) {
+                f(
+                    &crate::PathCommand::rect(decoration.x, line_y + decoration.y, decoration.w, decoration.h),
+                    decoration.color_opt.unwrap_or(color),
+                );
+            }
+        }
+    }
+
+    /// Draw the buffer one pixel at a time
+    ///
+    /// Glyph pixels are passed with `w` and `h` of 1, use [TextBuffer::draw_commands] to draw
+    /// whole glyph images instead. Colors are composited with [crate::SwashCache::blend].
     #[cfg(feature = "swash")]
     pub fn draw<F>(&self, cache: &mut crate::SwashCache, color: Color, mut f: F)
         where F: FnMut(i32, i32, u32, u32, Color)
+    {
+        let blend = cache.blend;
+        self.draw_commands(cache, color, |command| match command {
+            DrawCommand::Rect { x, y, w, h, color } => {
+                f(x, y, w, h, blend.output(color.r(), color.g(), color.b(), color.a()))
+            },
+            DrawCommand::Image { x, y, image, color, .. } => {
+                crate::image_pixels(image, color, &blend, |off_x, off_y, color| {
+                    f(x + off_x, y + off_y, 1, 1, color)
+                });
+            },
+        });
+    }
+
+    /// Draw the buffer as rectangles and whole glyph images, in drawing order
+    ///
+    /// Glyphs with empty images, like spaces, are skipped.
+    #[cfg(feature = "swash")]
+    pub fn draw_commands<F>(&self, cache: &mut crate::SwashCache, color: Color, mut f: F)
+        where F: FnMut(DrawCommand)
+    {
+        self.draw_items(color, |item| match item {
+            DrawItem::Rect(x, y, w, h, color) => f(DrawCommand::Rect { x, y, w, h, color }),
+            DrawItem::Glyph(cache_key, x, y, color) => {
+                let scale = cache.image_scale(cache_key);
+                match cache.get_image(cache_key) {
+                    Some(image) if !image.data.is_empty() => {
+                        f(DrawCommand::Image { cache_key, x, y, image, color, scale });
+                    },
+                    _ => (),
+                }
+            },
+        });
+    }
+
+    /// Render the buffer into a [crate::Canvas], with the top left of the buffer at `x` and `y`
+    ///
+    /// Drawing is clipped to the canvas and to the size of the buffer. Color glyphs are combined
+    /// with the text color as set by [crate::SwashCache::blend].
+    #[cfg(feature = "swash")]
+    pub fn render(&self, cache: &mut crate::SwashCache, color: Color, canvas: &mut crate::Canvas, x: i32, y: i32) {
+        let clip = canvas.clip().intersect(&crate::canvas::Clip {
+            x0: x,
+            y0: y,
+            x1: x.saturating_add(self.width),
+            y1: y.saturating_add(self.height),
+        });
+        let color_glyphs = cache.blend.color_glyphs;
+        self.draw_commands(cache, color, |command| match command {
+            DrawCommand::Rect { x: rect_x, y: rect_y, w, h, color } => {
+                canvas.fill_rect_clipped(x + rect_x, y + rect_y, w, h, color, clip);
+            },
+            DrawCommand::Image { x: glyph_x, y: glyph_y, image, color, .. } => {
+                canvas.draw_image_clipped(x + glyph_x, y + glyph_y, image, color, color_glyphs, clip);
+            },
+        });
+    }
+
+    // Enumerate rectangles and glyphs to draw, in order
+    #[cfg(feature = "swash")]
+    pub(crate) fn draw_items<F>(&self, color: Color, mut f: F)
+        where F: FnMut(DrawItem)
     {
         let font_size = self.metrics.font_size;
         let line_height = self.metrics.line_height;
@@ -960,13 +1195,13 @@ impl<'a> TextBuffer<'a> {
                                     ))
                                 };
                             } else if let Some((min, max)) = range_opt.take() {
-                                f(
+                                f(DrawItem::Rect(
                                     min,
                                     line_y - font_size,
                                     cmp::max(0, max - min) as u32,
                                     line_height as u32,
                                     Color::rgba(color.r(), color.g(), color.b(), 0x33)
-                                );
+                                ));
                             }
                             c_x += c_w;
                         }
@@ -986,13 +1221,13 @@ impl<'a> TextBuffer<'a> {
                                 max = self.width;
                             }
                         }
-                        f(
+                        f(DrawItem::Rect(
                             min,
                             line_y - font_size,
                             cmp::max(0, max - min) as u32,
                             line_height as u32,
                             Color::rgba(color.r(), color.g(), color.b(), 0x33)
-                        );
+                        ));
                     }
                 }
             }
@@ -1024,13 +1259,13 @@ impl<'a> TextBuffer<'a> {
                     }
                 };
 
-                f(
+                f(DrawItem::Rect(
                     x,
                     line_y - font_size,
                     1,
                     line_height as u32,
                     color,
-                );
+                ));
             }
 
             for glyph in run.glyphs.iter() {
@@ -1041,10 +1276,35 @@ impl<'a> TextBuffer<'a> {
                     None => color,
                 };
 
-                cache.with_pixels(cache_key, glyph_color, |x, y, color| {
-                    f(x_int + x, line_y + y_int + y, 1, 1, color)
-                });
+                f(DrawItem::Glyph(cache_key, x_int, line_y + y_int, glyph_color));
+            }
+
+            for decoration in run.decorations(self.font_system) {
+                let x = decoration.x.round() as i32;
+                let w = (decoration.x + decoration.w).round() as i32 - x;
+                f(DrawItem::Rect(
+                    x,
+                    line_y + decoration.y.round() as i32,
+                    cmp::max(0, w) as u32,
+                    cmp::max(1, decoration.h.round() as i32) as u32,
+                    decoration.color_opt.unwrap_or(color),
+                ));
             }
         }
     }
 }
+
+/// Lay out a line of text and get the cache keys of its glyphs, for rendering tests
+#[cfg(all(test, feature = "test-fonts"))]
+pub(crate) fn test_cache_keys<'a>(
+    font_system: &'a FontSystem<'a>,
+    font_size: i32,
+    text: &str,
+) -> Vec<crate::CacheKey> {
+    let mut buffer = TextBuffer::new(font_system, TextMetrics::new(font_size, font_size * 5 / 4));
+    buffer.set_size(1000, 1000);
+    buffer.set_text(text, Attrs::new());
+    buffer.layout_runs()
+        .flat_map(|run| run.glyphs.iter().map(|glyph| glyph.cache_key))
+        .collect()
+}
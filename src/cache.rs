@@ -1,5 +1,56 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
+use std::ops::{BitOr, BitOrAssign};
+
+use crate::Palette;
+
+/// Amount a glyph is widened by synthetic bold, as a fraction of the font size
+pub(crate) const FAKE_BOLD_EM: f32 = 1.0 / 32.0;
+
+/// Angle of the skew applied by synthetic italic, in degrees
+#[cfg(feature = "swash")]
+pub(crate) const FAKE_ITALIC_DEGREES: f32 = 14.0;
+
+/// Flags for style synthesis, used when a family lacks the requested face
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct CacheKeyFlags(u8);
+
+impl CacheKeyFlags {
+    /// Embolden the glyph outline
+    pub const FAKE_BOLD: Self = Self(1 << 0);
+    /// Skew the glyph outline
+    pub const FAKE_ITALIC: Self = Self(1 << 1);
+
+    /// No synthesis
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Check if no flags are set
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Check if all of the flags in `other` are set
+    pub const fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for CacheKeyFlags {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for CacheKeyFlags {
+    fn bitor_assign(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
 /// Key for building a glyph cache
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct CacheKey {
@@ -13,6 +64,10 @@ pub struct CacheKey {
     pub x_bin: SubpixelBin,
     /// Binning of fractional Y offset
     pub y_bin: SubpixelBin,
+    /// Style synthesis, see [CacheKeyFlags]
+    pub flags: CacheKeyFlags,
+    /// Palette of color glyphs
+    pub palette: Palette,
 }
 
 impl CacheKey {
@@ -21,6 +76,8 @@ impl CacheKey {
         glyph_id: u16,
         font_size: i32,
         pos: (f32, f32),
+        flags: CacheKeyFlags,
+        palette: Palette,
     ) -> (Self, i32, i32) {
         let (x, x_bin) = SubpixelBin::new(pos.0);
         let (y, y_bin) = SubpixelBin::new(pos.1);
@@ -31,6 +88,8 @@ impl CacheKey {
                 font_size,
                 x_bin,
                 y_bin,
+                flags,
+                palette,
             },
             x,
             y,
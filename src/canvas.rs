@@ -0,0 +1,246 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::{Color, ColorGlyphBlend, SwashContent, SwashImage};
+
+/// Layout of the pixels of a [Canvas]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PixelFormat {
+    /// RGBA with straight alpha, one byte per channel
+    Rgba8,
+    /// RGBA with premultiplied alpha, one byte per channel
+    Rgba8Premultiplied,
+}
+
+// Multiply two values from 0 to 255, as fractions of 255
+#[inline]
+pub(crate) fn mul(a: u8, b: u8) -> u8 {
+    let x = a as u32 * b as u32 + 128;
+    ((x + (x >> 8)) >> 8) as u8
+}
+
+// Area of a canvas that is drawn to, as start and end coordinates
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Clip {
+    pub x0: i32,
+    pub y0: i32,
+    pub x1: i32,
+    pub y1: i32,
+}
+
+impl Clip {
+    pub fn intersect(&self, other: &Self) -> Self {
+        Self {
+            x0: self.x0.max(other.x0),
+            y0: self.y0.max(other.y0),
+            x1: self.x1.min(other.x1),
+            y1: self.y1.min(other.y1),
+        }
+    }
+}
+
+/// RGBA pixels to draw into, see [crate::TextBuffer::render]
+///
+/// Drawing blends with source-over compositing, and is clipped to the canvas.
+pub struct Canvas<'a> {
+    data: &'a mut [u8],
+    width: u32,
+    height: u32,
+    format: PixelFormat,
+}
+
+impl<'a> Canvas<'a> {
+    /// Create a canvas from rows of `width` pixels, with four bytes per pixel
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `data` is smaller than `width * height * 4` bytes
+    pub fn new(data: &'a mut [u8], width: u32, height: u32, format: PixelFormat) -> Self {
+        assert!(
+            data.len() >= width as usize * height as usize * 4,
+            "canvas data has {} bytes, {}x{} pixels need {}",
+            data.len(),
+            width,
+            height,
+            width as usize * height as usize * 4
+        );
+        Self {
+            data,
+            width,
+            height,
+            format,
+        }
+    }
+
+    /// Width in pixels
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Height in pixels
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Format of the pixels
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    /// Get the pixel data
+    pub fn data(&self) -> &[u8] {
+        self.data
+    }
+
+    pub(crate) fn clip(&self) -> Clip {
+        Clip {
+            x0: 0,
+            y0: 0,
+            x1: self.width as i32,
+            y1: self.height as i32,
+        }
+    }
+
+    /// Blend a color over a rectangle
+    pub fn fill_rect(&mut self, x: i32, y: i32, w: u32, h: u32, color: Color) {
+        let clip = self.clip();
+        self.fill_rect_clipped(x, y, w, h, color, clip);
+    }
+
+    pub(crate) fn fill_rect_clipped(&mut self, x: i32, y: i32, w: u32, h: u32, color: Color, clip: Clip) {
+        let rect = clip.intersect(&Clip {
+            x0: x,
+            y0: y,
+            x1: x.saturating_add(w as i32),
+            y1: y.saturating_add(h as i32),
+        });
+        let rgb = [color.r(), color.g(), color.b()];
+        let a = color.a();
+        for py in rect.y0..rect.y1 {
+            for px in rect.x0..rect.x1 {
+                let i = (py as usize * self.width as usize + px as usize) * 4;
+                self.blend(i, rgb, [a; 3], a);
+            }
+        }
+    }
+
+    /// Blend a glyph image over the canvas, with the glyph origin at `x` and `y`
+    ///
+    /// Masks are drawn with `color`, and their coverage is multiplied by its alpha. Color images
+    /// are drawn with their own colors, combined with `color` as set by `color_glyphs`.
+    pub fn draw_image(&mut self, x: i32, y: i32, image: &SwashImage, color: Color, color_glyphs: ColorGlyphBlend) {
+        let clip = self.clip();
+        self.draw_image_clipped(x, y, image, color, color_glyphs, clip);
+    }
+
+    pub(crate) fn draw_image_clipped(
+        &mut self,
+        x: i32,
+        y: i32,
+        image: &SwashImage,
+        color: Color,
+        color_glyphs: ColorGlyphBlend,
+        clip: Clip
+    ) {
+        let left = x + image.placement.left;
+        let top = y - image.placement.top;
+        let width = image.placement.width as i32;
+        let rect = clip.intersect(&Clip {
+            x0: left,
+            y0: top,
+            x1: left + width,
+            y1: top + image.placement.height as i32,
+        });
+
+        let rgb = [color.r(), color.g(), color.b()];
+        let base_a = color.a();
+        for py in rect.y0..rect.y1 {
+            for px in rect.x0..rect.x1 {
+                let i = (py as usize * self.width as usize + px as usize) * 4;
+                let j = ((py - top) * width + (px - left)) as usize;
+                match image.content {
+                    SwashContent::Mask => {
+                        let a = mul(image.data[j], base_a);
+                        if a != 0 {
+                            self.blend(i, rgb, [a; 3], a);
+                        }
+                    },
+                    SwashContent::SubpixelMask => {
+                        let pixel = &image.data[j * 4..j * 4 + 4];
+                        let alpha = [mul(pixel[0], base_a), mul(pixel[1], base_a), mul(pixel[2], base_a)];
+                        let a = mul(pixel[3], base_a);
+                        if alpha != [0; 3] {
+                            self.blend(i, rgb, alpha, a);
+                        }
+                    },
+                    SwashContent::Color => {
+                        let [r, g, b, a] = color_glyphs.apply(&image.data[j * 4..j * 4 + 4], color);
+                        if a != 0 {
+                            self.blend(i, [r, g, b], [a; 3], a);
+                        }
+                    },
+                }
+            }
+        }
+    }
+
+    // Blend a straight color over the pixel at byte index `i`, with alpha for each color channel
+    #[inline]
+    fn blend(&mut self, i: usize, rgb: [u8; 3], alpha: [u8; 3], a: u8) {
+        let pixel = &mut self.data[i..i + 4];
+        let dst_a = pixel[3];
+        for c in 0..3 {
+            let dst = match self.format {
+                PixelFormat::Rgba8 => mul(pixel[c], dst_a),
+                PixelFormat::Rgba8Premultiplied => pixel[c],
+            };
+            pixel[c] = mul(rgb[c], alpha[c]).saturating_add(mul(dst, 255 - alpha[c]));
+        }
+        let out_a = a.saturating_add(mul(dst_a, 255 - a));
+        pixel[3] = out_a;
+
+        if self.format == PixelFormat::Rgba8 {
+            for value in pixel[..3].iter_mut() {
+                *value = if out_a == 0 {
+                    0
+                } else {
+                    ((*value as u32 * 255 + out_a as u32 / 2) / out_a as u32).min(255) as u8
+                };
+            }
+        }
+    }
+}
+
+#[test]
+fn test_canvas_blend() {
+    let mut data = [0; 2 * 2 * 4];
+    let mut canvas = Canvas::new(&mut data, 2, 2, PixelFormat::Rgba8);
+    canvas.fill_rect(-1, -1, 2, 2, Color::rgb(0, 0, 0xFF));
+    canvas.fill_rect(0, 0, 10, 1, Color::rgba(0xFF, 0, 0, 0x80));
+    // Half red over blue, half red over transparent, and clipped pixels
+    assert_eq!(data[..8], [0x80, 0, 0x7F, 0xFF, 0xFF, 0, 0, 0x80]);
+    assert_eq!(data[8..], [0; 8]);
+
+    let mut data = [0; 2 * 4];
+    let mut canvas = Canvas::new(&mut data, 2, 1, PixelFormat::Rgba8Premultiplied);
+    canvas.fill_rect(0, 0, 1, 1, Color::rgb(0, 0, 0xFF));
+    canvas.fill_rect(0, 0, 2, 1, Color::rgba(0xFF, 0, 0, 0x80));
+    assert_eq!(data, [0x80, 0, 0x7F, 0xFF, 0x80, 0, 0, 0x80]);
+
+    // Masks use the alpha of the color, subpixel masks blend channels separately
+    let mut image = SwashImage::new();
+    image.placement.left = 1;
+    image.placement.top = 0;
+    image.placement.width = 1;
+    image.placement.height = 1;
+    image.content = SwashContent::SubpixelMask;
+    image.data = vec![0xFF, 0x80, 0, 0x55];
+    let mut data = [0xFF; 3 * 4];
+    let mut canvas = Canvas::new(&mut data, 3, 1, PixelFormat::Rgba8);
+    canvas.draw_image(0, 0, &image, Color::rgb(0, 0, 0), ColorGlyphBlend::Opacity);
+    assert_eq!(data[4..8], [0, 0x7F, 0xFF, 0xFF]);
+    image.content = SwashContent::Mask;
+    image.data = vec![0xFF];
+    let mut canvas = Canvas::new(&mut data, 3, 1, PixelFormat::Rgba8);
+    canvas.draw_image(1, 0, &image, Color::rgba(0, 0, 0, 0x80), ColorGlyphBlend::Opacity);
+    assert_eq!(data[8..], [0x7F, 0x7F, 0x7F, 0xFF]);
+}
@@ -0,0 +1,309 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use skrifa::color::{Brush, ColorGlyphFormat, ColorPainter, ColorStop, CompositeMode};
+use skrifa::instance::{Location, Size};
+use skrifa::outline::{DrawSettings, OutlineGlyphCollection, OutlinePen};
+use skrifa::raw::TableProvider;
+use skrifa::raw::types::BoundingBox;
+use skrifa::{GlyphId, MetadataProvider, Tag};
+use swash::scale::image::Image;
+use swash::zeno::{Bounds, Command, Fill, PathBuilder, Point, Transform, Vector};
+
+use crate::{CacheKey, Font, TextBrightness};
+use super::paint::{Composite, Extend, Gradient, Paint, Raster, Shape, Stop};
+use super::{concat, font_transform, image_rect, image_transform, intersect, unite, PaletteColors};
+
+// Builds an outline in font units
+struct OutlineBuilder(Vec<Command>);
+
+impl OutlinePen for OutlineBuilder {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.0.move_to([x, y]);
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.0.line_to([x, y]);
+    }
+
+    fn quad_to(&mut self, cx0: f32, cy0: f32, x: f32, y: f32) {
+        self.0.quad_to([cx0, cy0], [x, y]);
+    }
+
+    fn curve_to(&mut self, cx0: f32, cy0: f32, cx1: f32, cy1: f32, x: f32, y: f32) {
+        self.0.curve_to([cx0, cy0], [cx1, cy1], [x, y]);
+    }
+
+    fn close(&mut self) {
+        self.0.close();
+    }
+}
+
+// Outlines of the glyphs used as clips, in font units
+struct Outlines<'a> {
+    glyphs: OutlineGlyphCollection<'a>,
+    location: &'a Location,
+}
+
+impl<'a> Outlines<'a> {
+    fn get(&self, glyph_id: GlyphId) -> Vec<Command> {
+        let mut builder = OutlineBuilder(Vec::new());
+        if let Some(glyph) = self.glyphs.get(glyph_id) {
+            let settings = DrawSettings::unhinted(Size::unscaled(), self.location);
+            if let Err(err) = glyph.draw(settings, &mut builder) {
+                log::warn!("failed to draw outline of glyph {}: {}", glyph_id, err);
+            }
+        }
+        builder.0
+    }
+}
+
+// Convert a transform of COLRv1, which names the factors differently
+fn transform(t: skrifa::color::Transform) -> Transform {
+    Transform::new(t.xx, t.yx, t.xy, t.yy, t.dx, t.dy)
+}
+
+fn clip_box_path(clip_box: BoundingBox<f32>) -> Vec<Command> {
+    let mut path = Vec::new();
+    path.add_rect(
+        [clip_box.x_min, clip_box.y_min],
+        clip_box.x_max - clip_box.x_min,
+        clip_box.y_max - clip_box.y_min,
+    );
+    path
+}
+
+fn composite(mode: CompositeMode) -> Composite {
+    match mode {
+        CompositeMode::Clear => Composite::Clear,
+        CompositeMode::Src => Composite::Src,
+        CompositeMode::Dest => Composite::Dest,
+        CompositeMode::DestOver => Composite::DestOver,
+        CompositeMode::SrcIn => Composite::SrcIn,
+        CompositeMode::DestIn => Composite::DestIn,
+        CompositeMode::SrcOut => Composite::SrcOut,
+        CompositeMode::DestOut => Composite::DestOut,
+        CompositeMode::SrcAtop => Composite::SrcAtop,
+        CompositeMode::DestAtop => Composite::DestAtop,
+        CompositeMode::Xor => Composite::Xor,
+        CompositeMode::Plus => Composite::Plus,
+        CompositeMode::Screen => Composite::Screen,
+        CompositeMode::Overlay => Composite::Overlay,
+        CompositeMode::Darken => Composite::Darken,
+        CompositeMode::Lighten => Composite::Lighten,
+        CompositeMode::ColorDodge => Composite::ColorDodge,
+        CompositeMode::ColorBurn => Composite::ColorBurn,
+        CompositeMode::HardLight => Composite::HardLight,
+        CompositeMode::SoftLight => Composite::SoftLight,
+        CompositeMode::Difference => Composite::Difference,
+        CompositeMode::Exclusion => Composite::Exclusion,
+        CompositeMode::Multiply => Composite::Multiply,
+        CompositeMode::HslHue => Composite::Hue,
+        CompositeMode::HslSaturation => Composite::Saturation,
+        CompositeMode::HslColor => Composite::Color,
+        CompositeMode::HslLuminosity => Composite::Luminosity,
+        _ => Composite::SrcOver,
+    }
+}
+
+fn extend(extend: skrifa::color::Extend) -> Extend {
+    match extend {
+        skrifa::color::Extend::Repeat => Extend::Repeat,
+        skrifa::color::Extend::Reflect => Extend::Reflect,
+        _ => Extend::Pad,
+    }
+}
+
+// Finds the area painted by a glyph, in pixels increasing upward
+struct BoundsPainter<'a> {
+    outlines: &'a Outlines<'a>,
+    transforms: Vec<Transform>,
+    // Bounds of each clip, intersected with the clips below it
+    clips: Vec<Bounds>,
+    bounds_opt: Option<Bounds>,
+}
+
+impl<'a> BoundsPainter<'a> {
+    fn push_clip_path(&mut self, path: &[Command]) {
+        let mut bounds = swash::zeno::bounds(path, Fill::NonZero, Some(*self.transforms.last().unwrap()));
+        if let Some(clip) = self.clips.last() {
+            bounds = intersect(bounds, *clip);
+        }
+        self.clips.push(bounds);
+    }
+}
+
+impl<'a> ColorPainter for BoundsPainter<'a> {
+    fn push_transform(&mut self, t: skrifa::color::Transform) {
+        let t = concat(&transform(t), self.transforms.last().unwrap());
+        self.transforms.push(t);
+    }
+
+    fn pop_transform(&mut self) {
+        self.transforms.pop();
+    }
+
+    fn push_clip_glyph(&mut self, glyph_id: GlyphId) {
+        let path = self.outlines.get(glyph_id);
+        self.push_clip_path(&path);
+    }
+
+    fn push_clip_box(&mut self, clip_box: BoundingBox<f32>) {
+        self.push_clip_path(&clip_box_path(clip_box));
+    }
+
+    fn pop_clip(&mut self) {
+        self.clips.pop();
+    }
+
+    fn fill(&mut self, _brush: Brush<'_>) {
+        // Fills are unbounded without a clip
+        if let Some(clip) = self.clips.last() {
+            self.bounds_opt = unite(self.bounds_opt, *clip);
+        }
+    }
+
+    fn push_layer(&mut self, _composite_mode: CompositeMode) {}
+
+    fn pop_layer(&mut self) {}
+}
+
+// Paints a glyph into a raster
+struct RasterPainter<'a> {
+    outlines: &'a Outlines<'a>,
+    colors: &'a PaletteColors<'a>,
+    transforms: Vec<Transform>,
+    raster: Raster,
+}
+
+impl<'a> RasterPainter<'a> {
+    fn push_clip_path(&mut self, path: &[Command]) {
+        let shape = Shape::fill(Fill::NonZero, *self.transforms.last().unwrap());
+        self.raster.push_clip(path, &shape);
+    }
+
+    fn stops(&self, color_stops: &[ColorStop]) -> Vec<Stop> {
+        let mut stops: Vec<Stop> = color_stops.iter().map(|stop| Stop {
+            offset: stop.offset,
+            color: self.colors.color(stop.palette_index, stop.alpha),
+        }).collect();
+        // Stops are not required to be sorted
+        stops.sort_by(|a, b| a.offset.total_cmp(&b.offset));
+        stops
+    }
+}
+
+impl<'a> ColorPainter for RasterPainter<'a> {
+    fn push_transform(&mut self, t: skrifa::color::Transform) {
+        let t = concat(&transform(t), self.transforms.last().unwrap());
+        self.transforms.push(t);
+    }
+
+    fn pop_transform(&mut self) {
+        self.transforms.pop();
+    }
+
+    fn push_clip_glyph(&mut self, glyph_id: GlyphId) {
+        let path = self.outlines.get(glyph_id);
+        self.push_clip_path(&path);
+    }
+
+    fn push_clip_box(&mut self, clip_box: BoundingBox<f32>) {
+        self.push_clip_path(&clip_box_path(clip_box));
+    }
+
+    fn pop_clip(&mut self) {
+        self.raster.pop_clip();
+    }
+
+    fn fill(&mut self, brush: Brush<'_>) {
+        let point = |p: skrifa::raw::types::Point<f32>| Point::new(p.x, p.y);
+        let (gradient, color_stops, color_extend) = match brush {
+            Brush::Solid { palette_index, alpha } => {
+                let paint = Paint::Solid(self.colors.color(palette_index, alpha));
+                self.raster.fill(&paint);
+                return;
+            },
+            Brush::LinearGradient { p0, p1, color_stops, extend } => (
+                Gradient::Linear { p0: point(p0), p1: point(p1) },
+                color_stops,
+                extend,
+            ),
+            Brush::RadialGradient { c0, r0, c1, r1, color_stops, extend } => (
+                Gradient::Radial { c0: point(c0), r0, c1: point(c1), r1 },
+                color_stops,
+                extend,
+            ),
+            Brush::SweepGradient { c0, start_angle, end_angle, color_stops, extend } => (
+                Gradient::Sweep { c: point(c0), start: start_angle, end: end_angle },
+                color_stops,
+                extend,
+            ),
+        };
+        let paint = Paint::Gradient {
+            gradient,
+            stops: self.stops(color_stops),
+            extend: extend(color_extend),
+            transform: *self.transforms.last().unwrap(),
+        };
+        self.raster.fill(&paint);
+    }
+
+    fn push_layer(&mut self, composite_mode: CompositeMode) {
+        self.raster.push_layer(composite(composite_mode), 1.0);
+    }
+
+    fn pop_layer(&mut self) {
+        self.raster.pop_layer();
+    }
+}
+
+// Render a glyph from the paint graph of a COLRv1 table, if it has one
+pub(crate) fn colr_image(
+    font: &Font,
+    cache_key: CacheKey,
+    offset: Vector,
+    transform_opt: Option<Transform>,
+    palette: u16,
+    brightness: TextBrightness,
+) -> Option<Image> {
+    let font_ref = skrifa::FontRef::from_index(font.data, font.info.index).ok()?;
+    let glyph_id = GlyphId::from(cache_key.glyph_id);
+    let glyph = font_ref.color_glyphs().get_with_format(glyph_id, ColorGlyphFormat::ColrV1)?;
+    let units_per_em = font_ref.head().ok()?.units_per_em() as f32;
+
+    // Use the axis values of named instances
+    let location = match font.instance_opt {
+        Some(instance) => font_ref.axes().location(instance.variations.iter().map(|variation| {
+            (Tag::from_u32(variation.tag.0), variation.value)
+        })),
+        None => Location::default(),
+    };
+    let outlines = Outlines {
+        glyphs: font_ref.outline_glyphs(),
+        location: &location,
+    };
+    let colors = PaletteColors::new(font, palette, brightness);
+    let base = font_transform(units_per_em, cache_key, offset, transform_opt);
+
+    // Find the size of the image, then paint it
+    let mut bounds_painter = BoundsPainter {
+        outlines: &outlines,
+        transforms: vec![base],
+        clips: Vec::new(),
+        bounds_opt: None,
+    };
+    if let Err(err) = glyph.paint(&location, &mut bounds_painter) {
+        log::warn!("failed to paint COLRv1 glyph {}: {}", cache_key.glyph_id, err);
+        return None;
+    }
+    let (left, top, width, height) = image_rect(bounds_painter.bounds_opt?)?;
+
+    let mut painter = RasterPainter {
+        outlines: &outlines,
+        colors: &colors,
+        transforms: vec![concat(&base, &image_transform(left, top))],
+        raster: Raster::new(width, height),
+    };
+    glyph.paint(&location, &mut painter).ok()?;
+    Some(painter.raster.into_image(left, top))
+}
@@ -0,0 +1,154 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+// Rendering of color glyph formats that swash does not support
+
+use swash::ColorPalette;
+use swash::zeno::{Bounds, Transform, Vector};
+
+use crate::{CacheKey, Font, TextBrightness};
+
+pub(crate) use self::colr::colr_image;
+mod colr;
+
+mod paint;
+
+pub(crate) use self::svg::svg_image;
+mod svg;
+
+// Largest width or height of a color glyph image, to limit memory used by broken fonts
+const MAX_SIZE: f32 = 4096.0;
+
+// Colors of a palette from the CPAL table of a font
+struct PaletteColors<'a> {
+    palette_opt: Option<ColorPalette<'a>>,
+    foreground: [u8; 4],
+}
+
+impl<'a> PaletteColors<'a> {
+    fn new(font: &'a Font, palette: u16, brightness: TextBrightness) -> Self {
+        Self {
+            palette_opt: font.as_swash().color_palettes().nth(palette as usize),
+            // The text color is not known when rendering, so it is picked from the brightness
+            foreground: match brightness {
+                TextBrightness::Dark => [0, 0, 0, 0xFF],
+                TextBrightness::Light => [0xFF; 4],
+            },
+        }
+    }
+
+    // Straight RGBA color of a palette entry, where entry 0xFFFF is the text color
+    fn entry(&self, index: u16) -> Option<[u8; 4]> {
+        if index == 0xFFFF {
+            return Some(self.foreground);
+        }
+        let palette = self.palette_opt.as_ref()?;
+        if index < palette.len() {
+            Some(palette.get(index))
+        } else {
+            None
+        }
+    }
+
+    // Premultiplied color of a palette entry, with its alpha multiplied by `alpha`
+    fn color(&self, index: u16, alpha: f32) -> [f32; 4] {
+        premultiply(self.entry(index).unwrap_or([0; 4]), alpha)
+    }
+}
+
+// Premultiply a straight RGBA color, with its alpha multiplied by `alpha`
+fn premultiply(color: [u8; 4], alpha: f32) -> [f32; 4] {
+    let a = color[3] as f32 / 255.0 * alpha.clamp(0.0, 1.0);
+    [
+        color[0] as f32 / 255.0 * a,
+        color[1] as f32 / 255.0 * a,
+        color[2] as f32 / 255.0 * a,
+        a,
+    ]
+}
+
+// Transform that applies `a`, then `b`
+//
+// Transform::then and Transform::invert of zeno swap the xy and yx factors compared to
+// Transform::transform_point, which masks are rendered with, so they are wrong for rotations
+// and skews.
+fn concat(a: &Transform, b: &Transform) -> Transform {
+    Transform::new(
+        a.xx * b.xx + a.xy * b.yx,
+        a.xx * b.xy + a.xy * b.yy,
+        a.yx * b.xx + a.yy * b.yx,
+        a.yx * b.xy + a.yy * b.yy,
+        a.x * b.xx + a.y * b.yx + b.x,
+        a.x * b.xy + a.y * b.yy + b.y,
+    )
+}
+
+// Inverse of a transform, if it has one, see concat
+fn invert(t: &Transform) -> Option<Transform> {
+    let det = t.xx * t.yy - t.yx * t.xy;
+    if !det.is_finite() || det == 0.0 {
+        return None;
+    }
+    Some(Transform::new(
+        t.yy / det,
+        -t.xy / det,
+        -t.yx / det,
+        t.xx / det,
+        (t.yx * t.y - t.yy * t.x) / det,
+        (t.xy * t.x - t.xx * t.y) / det,
+    ))
+}
+
+// Transform from font units to pixels, both increasing upward, like swash renders outlines
+fn font_transform(
+    units_per_em: f32,
+    cache_key: CacheKey,
+    offset: Vector,
+    transform_opt: Option<Transform>,
+) -> Transform {
+    let scale = cache_key.font_size as f32 / units_per_em.max(1.0);
+    let mut transform = Transform::scale(scale, scale);
+    if let Some(skew) = transform_opt {
+        transform = concat(&transform, &skew);
+    }
+    transform.then_translate(offset.x, offset.y)
+}
+
+// Get the left, top, width, and height of the pixels covering bounds that increase upward
+fn image_rect(bounds: Bounds) -> Option<(i32, i32, u32, u32)> {
+    let left = bounds.min.x.floor();
+    let right = bounds.max.x.ceil();
+    let bottom = bounds.min.y.floor();
+    let top = bounds.max.y.ceil();
+    // Also false for NaN
+    if !(left < right && bottom < top && right - left <= MAX_SIZE && top - bottom <= MAX_SIZE) {
+        return None;
+    }
+    Some((left as i32, top as i32, (right - left) as u32, (top - bottom) as u32))
+}
+
+// Transform from pixels increasing upward to the pixels of an image, increasing downward
+fn image_transform(left: i32, top: i32) -> Transform {
+    Transform::new(1.0, 0.0, 0.0, -1.0, -left as f32, top as f32)
+}
+
+// Intersect two bounds, which may leave them empty
+fn intersect(a: Bounds, b: Bounds) -> Bounds {
+    Bounds::new(
+        Vector::new(a.min.x.max(b.min.x), a.min.y.max(b.min.y)),
+        Vector::new(a.max.x.min(b.max.x), a.max.y.min(b.max.y)),
+    )
+}
+
+// Unite bounds, ignoring empty ones
+fn unite(a_opt: Option<Bounds>, b: Bounds) -> Option<Bounds> {
+    if !(b.min.x < b.max.x && b.min.y < b.max.y) {
+        return a_opt;
+    }
+    Some(match a_opt {
+        Some(a) => Bounds::new(
+            Vector::new(a.min.x.min(b.min.x), a.min.y.min(b.min.y)),
+            Vector::new(a.max.x.max(b.max.x), a.max.y.max(b.max.y)),
+        ),
+        None => b,
+    })
+}
@@ -0,0 +1,569 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use swash::scale::image::{Content, Image};
+use swash::zeno::{Fill, Mask, PathData, Placement, Point, Style, Transform};
+
+// Compositing of a layer with the layers below it
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Composite {
+    Clear,
+    Src,
+    Dest,
+    SrcOver,
+    DestOver,
+    SrcIn,
+    DestIn,
+    SrcOut,
+    DestOut,
+    SrcAtop,
+    DestAtop,
+    Xor,
+    Plus,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+    ColorDodge,
+    ColorBurn,
+    HardLight,
+    SoftLight,
+    Difference,
+    Exclusion,
+    Multiply,
+    Hue,
+    Saturation,
+    Color,
+    Luminosity,
+}
+
+// How gradients continue outside of their first and last stops
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Extend {
+    Pad,
+    Repeat,
+    Reflect,
+}
+
+// Stop of a gradient, with a premultiplied color
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Stop {
+    pub offset: f32,
+    pub color: [f32; 4],
+}
+
+// Geometry of a gradient, in the coordinates of its paint
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Gradient {
+    Linear {
+        p0: Point,
+        p1: Point,
+    },
+    // Two point conical gradient, from the circle at c0 to the circle at c1
+    Radial {
+        c0: Point,
+        r0: f32,
+        c1: Point,
+        r1: f32,
+    },
+    // Angles are in degrees, clockwise from the x axis with y increasing upward
+    Sweep {
+        c: Point,
+        start: f32,
+        end: f32,
+    },
+}
+
+impl Gradient {
+    // Position on the color line at a point, if the gradient covers it
+    fn position(&self, p: Point) -> Option<f32> {
+        match *self {
+            Self::Linear { p0, p1 } => {
+                let d = p1 - p0;
+                let len = d.dot(d);
+                if len == 0.0 {
+                    return None;
+                }
+                Some((p - p0).dot(d) / len)
+            },
+            Self::Radial { c0, r0, c1, r1 } => {
+                // Solve |p - c(t)| = r(t) for the largest t with a radius that is not negative
+                let cd = c1 - c0;
+                let pd = p - c0;
+                let dr = r1 - r0;
+                let a = cd.dot(cd) - dr * dr;
+                let b = pd.dot(cd) + r0 * dr;
+                let c = pd.dot(pd) - r0 * r0;
+                let valid = |t: f32| if r0 + t * dr >= 0.0 { Some(t) } else { None };
+                if a.abs() < 1e-6 {
+                    if b.abs() < 1e-6 {
+                        return None;
+                    }
+                    valid(c / (2.0 * b))
+                } else {
+                    let discriminant = b * b - a * c;
+                    if discriminant < 0.0 {
+                        return None;
+                    }
+                    let root = discriminant.sqrt();
+                    let (t0, t1) = ((b + root) / a, (b - root) / a);
+                    valid(t0.max(t1)).or_else(|| valid(t0.min(t1)))
+                }
+            },
+            Self::Sweep { c, start, end } => {
+                if start == end {
+                    return None;
+                }
+                let d = p - c;
+                let angle = 360.0 - d.y.atan2(d.x).to_degrees();
+                let angle = angle.rem_euclid(360.0);
+                Some((angle - start) / (end - start))
+            },
+        }
+    }
+}
+
+// Color to fill an area with
+#[derive(Clone, Debug)]
+pub(crate) enum Paint {
+    // Premultiplied color
+    Solid([f32; 4]),
+    Gradient {
+        gradient: Gradient,
+        // Stops sorted by offset
+        stops: Vec<Stop>,
+        extend: Extend,
+        // Transform from the coordinates of the gradient to pixels
+        transform: Transform,
+    },
+}
+
+impl Paint {
+    // Get the premultiplied color of a gradient at a position on its color line
+    fn color_line(stops: &[Stop], extend: Extend, t: f32) -> [f32; 4] {
+        let t = match extend {
+            Extend::Pad => t,
+            Extend::Repeat => t - t.floor(),
+            Extend::Reflect => {
+                let t = t.rem_euclid(2.0);
+                if t > 1.0 {
+                    2.0 - t
+                } else {
+                    t
+                }
+            },
+        };
+        let (first, last) = match (stops.first(), stops.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return [0.0; 4],
+        };
+        if t <= first.offset {
+            return first.color;
+        }
+        if t >= last.offset {
+            return last.color;
+        }
+        for pair in stops.windows(2) {
+            let (a, b) = (&pair[0], &pair[1]);
+            if t <= b.offset {
+                let span = b.offset - a.offset;
+                let f = if span > 0.0 { (t - a.offset) / span } else { 1.0 };
+                let mut color = a.color;
+                for (value, end) in color.iter_mut().zip(b.color.iter()) {
+                    *value += (end - *value) * f;
+                }
+                return color;
+            }
+        }
+        last.color
+    }
+}
+
+// Transform of a path into pixels, and how it is filled
+pub(crate) struct Shape<'a> {
+    pub style: Style<'a>,
+    pub transform: Transform,
+}
+
+impl<'a> Shape<'a> {
+    pub fn fill(fill: Fill, transform: Transform) -> Self {
+        Self {
+            style: Style::Fill(fill),
+            transform,
+        }
+    }
+}
+
+struct Layer {
+    pixels: Vec<[f32; 4]>,
+    composite: Composite,
+    opacity: f32,
+}
+
+// Premultiplied RGBA pixels drawn with layers and clips, like a small vector canvas
+pub(crate) struct Raster {
+    width: u32,
+    height: u32,
+    layers: Vec<Layer>,
+    // Coverage of each clip, intersected with the clips below it
+    clips: Vec<Vec<f32>>,
+}
+
+impl Raster {
+    pub fn new(width: u32, height: u32) -> Self {
+        let mut raster = Self {
+            width,
+            height,
+            layers: Vec::new(),
+            clips: Vec::new(),
+        };
+        raster.push_layer(Composite::SrcOver, 1.0);
+        raster
+    }
+
+    fn len(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    // Coverage of a path, from 0 to 1
+    pub fn coverage(&self, path: impl PathData, shape: &Shape) -> Vec<f32> {
+        let mut mask = vec![0; self.len()];
+        Mask::new(path)
+            .style(shape.style)
+            .transform(Some(shape.transform))
+            .size(self.width, self.height)
+            .render_into(&mut mask, None);
+        mask.into_iter().map(|a| a as f32 / 255.0).collect()
+    }
+
+    // Clip drawing to a coverage mask, until the matching pop_clip
+    pub fn push_clip_coverage(&mut self, mut coverage: Vec<f32>) {
+        if let Some(clip) = self.clips.last() {
+            for (a, b) in coverage.iter_mut().zip(clip.iter()) {
+                *a *= b;
+            }
+        }
+        self.clips.push(coverage);
+    }
+
+    // Clip drawing to a path, until the matching pop_clip
+    pub fn push_clip(&mut self, path: impl PathData, shape: &Shape) {
+        let coverage = self.coverage(path, shape);
+        self.push_clip_coverage(coverage);
+    }
+
+    pub fn pop_clip(&mut self) {
+        self.clips.pop();
+    }
+
+    // Fill the area inside of the clips
+    pub fn fill(&mut self, paint: &Paint) {
+        let clip_opt = self.clips.pop();
+        self.fill_coverage(paint, clip_opt.as_deref());
+        if let Some(clip) = clip_opt {
+            self.clips.push(clip);
+        }
+    }
+
+    // Fill a path, inside of the clips
+    pub fn fill_path(&mut self, path: impl PathData, shape: &Shape, paint: &Paint) {
+        let mut coverage = self.coverage(path, shape);
+        if let Some(clip) = self.clips.last() {
+            for (a, b) in coverage.iter_mut().zip(clip.iter()) {
+                *a *= b;
+            }
+        }
+        self.fill_coverage(paint, Some(&coverage));
+    }
+
+    fn fill_coverage(&mut self, paint: &Paint, coverage_opt: Option<&[f32]>) {
+        let width = self.width as usize;
+        let inverse_opt = match paint {
+            Paint::Solid(_) => None,
+            Paint::Gradient { transform, .. } => match super::invert(transform) {
+                Some(some) => Some(some),
+                // A gradient squashed to a line has no area
+                None => return,
+            },
+        };
+        let layer = self.layers.last_mut().unwrap();
+        for (i, pixel) in layer.pixels.iter_mut().enumerate() {
+            let coverage = coverage_opt.map_or(1.0, |coverage| coverage[i]);
+            if coverage <= 0.0 {
+                continue;
+            }
+            let color = match paint {
+                Paint::Solid(color) => *color,
+                Paint::Gradient { gradient, stops, extend, .. } => {
+                    let p = Point::new((i % width) as f32 + 0.5, (i / width) as f32 + 0.5);
+                    let p = inverse_opt.unwrap().transform_point(p);
+                    match gradient.position(p) {
+                        Some(t) => Paint::color_line(stops, *extend, t),
+                        None => continue,
+                    }
+                },
+            };
+            let a = color[3] * coverage;
+            for c in 0..4 {
+                pixel[c] = color[c] * coverage + pixel[c] * (1.0 - a);
+            }
+        }
+    }
+
+    // Draw on a new layer, until it is composited with the matching pop_layer
+    pub fn push_layer(&mut self, composite: Composite, opacity: f32) {
+        self.layers.push(Layer {
+            pixels: vec![[0.0; 4]; self.len()],
+            composite,
+            opacity,
+        });
+    }
+
+    pub fn pop_layer(&mut self) {
+        if self.layers.len() < 2 {
+            return;
+        }
+        let layer = self.layers.pop().unwrap();
+        let below = self.layers.last_mut().unwrap();
+        for (d, s) in below.pixels.iter_mut().zip(layer.pixels.iter()) {
+            let s = s.map(|c| c * layer.opacity);
+            *d = composite(layer.composite, s, *d);
+        }
+    }
+
+    // Convert to a color image with straight alpha, placed at `left` and `top`
+    pub fn into_image(mut self, left: i32, top: i32) -> Image {
+        while self.layers.len() > 1 {
+            self.pop_layer();
+        }
+        let mut image = Image::new();
+        image.content = Content::Color;
+        image.placement = Placement {
+            left,
+            top,
+            width: self.width,
+            height: self.height,
+        };
+        image.data = Vec::with_capacity(self.len() * 4);
+        for pixel in self.layers[0].pixels.iter() {
+            let a = pixel[3].clamp(0.0, 1.0);
+            for value in pixel[..3].iter() {
+                let value = if a > 0.0 { value / a } else { 0.0 };
+                image.data.push((value.clamp(0.0, 1.0) * 255.0).round() as u8);
+            }
+            image.data.push((a * 255.0).round() as u8);
+        }
+        image
+    }
+}
+
+// Composite a premultiplied source pixel with a destination pixel
+fn composite(mode: Composite, s: [f32; 4], d: [f32; 4]) -> [f32; 4] {
+    let (sa, da) = (s[3], d[3]);
+    let porter_duff = |fs: f32, fd: f32| {
+        let mut out = [0.0; 4];
+        for c in 0..4 {
+            out[c] = s[c] * fs + d[c] * fd;
+        }
+        out
+    };
+    match mode {
+        Composite::Clear => [0.0; 4],
+        Composite::Src => s,
+        Composite::Dest => d,
+        Composite::SrcOver => porter_duff(1.0, 1.0 - sa),
+        Composite::DestOver => porter_duff(1.0 - da, 1.0),
+        Composite::SrcIn => porter_duff(da, 0.0),
+        Composite::DestIn => porter_duff(0.0, sa),
+        Composite::SrcOut => porter_duff(1.0 - da, 0.0),
+        Composite::DestOut => porter_duff(0.0, 1.0 - sa),
+        Composite::SrcAtop => porter_duff(da, 1.0 - sa),
+        Composite::DestAtop => porter_duff(1.0 - da, sa),
+        Composite::Xor => porter_duff(1.0 - da, 1.0 - sa),
+        Composite::Plus => {
+            let mut out = [0.0; 4];
+            for c in 0..4 {
+                out[c] = (s[c] + d[c]).min(1.0);
+            }
+            out
+        },
+        _ => {
+            // Blend straight colors, then composite them like source over
+            let unpremultiply = |p: [f32; 4]| {
+                if p[3] > 0.0 {
+                    [p[0] / p[3], p[1] / p[3], p[2] / p[3]]
+                } else {
+                    [0.0; 3]
+                }
+            };
+            let cs = unpremultiply(s);
+            let cb = unpremultiply(d);
+            let blended = blend(mode, cs, cb);
+            let mut out = [0.0; 4];
+            for c in 0..3 {
+                out[c] = s[c] * (1.0 - da) + d[c] * (1.0 - sa) + sa * da * blended[c];
+            }
+            out[3] = sa + da - sa * da;
+            out
+        },
+    }
+}
+
+// Blend a straight source color with a backdrop color, as in the W3C compositing specification
+fn blend(mode: Composite, cs: [f32; 3], cb: [f32; 3]) -> [f32; 3] {
+    let separable = |f: fn(f32, f32) -> f32| [f(cs[0], cb[0]), f(cs[1], cb[1]), f(cs[2], cb[2])];
+    match mode {
+        Composite::Screen => separable(screen),
+        Composite::Overlay => separable(|s, b| hard_light(b, s)),
+        Composite::Darken => separable(f32::min),
+        Composite::Lighten => separable(f32::max),
+        Composite::ColorDodge => separable(|s, b| {
+            if b <= 0.0 {
+                0.0
+            } else if s >= 1.0 {
+                1.0
+            } else {
+                (b / (1.0 - s)).min(1.0)
+            }
+        }),
+        Composite::ColorBurn => separable(|s, b| {
+            if b >= 1.0 {
+                1.0
+            } else if s <= 0.0 {
+                0.0
+            } else {
+                1.0 - ((1.0 - b) / s).min(1.0)
+            }
+        }),
+        Composite::HardLight => separable(hard_light),
+        Composite::SoftLight => separable(|s, b| {
+            if s <= 0.5 {
+                b - (1.0 - 2.0 * s) * b * (1.0 - b)
+            } else {
+                let d = if b <= 0.25 {
+                    ((16.0 * b - 12.0) * b + 4.0) * b
+                } else {
+                    b.sqrt()
+                };
+                b + (2.0 * s - 1.0) * (d - b)
+            }
+        }),
+        Composite::Difference => separable(|s, b| (s - b).abs()),
+        Composite::Exclusion => separable(|s, b| s + b - 2.0 * s * b),
+        Composite::Multiply => separable(|s, b| s * b),
+        Composite::Hue => set_lum(set_sat(cs, sat(cb)), lum(cb)),
+        Composite::Saturation => set_lum(set_sat(cb, sat(cs)), lum(cb)),
+        Composite::Color => set_lum(cs, lum(cb)),
+        Composite::Luminosity => set_lum(cb, lum(cs)),
+        _ => cs,
+    }
+}
+
+fn screen(s: f32, b: f32) -> f32 {
+    s + b - s * b
+}
+
+fn hard_light(s: f32, b: f32) -> f32 {
+    if s <= 0.5 {
+        b * 2.0 * s
+    } else {
+        screen(b, 2.0 * s - 1.0)
+    }
+}
+
+fn lum(c: [f32; 3]) -> f32 {
+    0.3 * c[0] + 0.59 * c[1] + 0.11 * c[2]
+}
+
+fn set_lum(c: [f32; 3], l: f32) -> [f32; 3] {
+    let d = l - lum(c);
+    let c = [c[0] + d, c[1] + d, c[2] + d];
+    // Clip to the range of colors, keeping the luminosity
+    let l = lum(c);
+    let n = c[0].min(c[1]).min(c[2]);
+    let x = c[0].max(c[1]).max(c[2]);
+    c.map(|v| {
+        let mut v = v;
+        if n < 0.0 {
+            v = l + (v - l) * l / (l - n);
+        }
+        if x > 1.0 {
+            v = l + (v - l) * (1.0 - l) / (x - l);
+        }
+        v
+    })
+}
+
+fn sat(c: [f32; 3]) -> f32 {
+    c[0].max(c[1]).max(c[2]) - c[0].min(c[1]).min(c[2])
+}
+
+fn set_sat(c: [f32; 3], s: f32) -> [f32; 3] {
+    let max = c[0].max(c[1]).max(c[2]);
+    let min = c[0].min(c[1]).min(c[2]);
+    if max > min {
+        c.map(|v| (v - min) * s / (max - min))
+    } else {
+        [0.0; 3]
+    }
+}
+
+#[test]
+fn test_gradient_position() {
+    let linear = Gradient::Linear { p0: Point::new(10.0, 0.0), p1: Point::new(20.0, 0.0) };
+    assert_eq!(linear.position(Point::new(15.0, 7.0)), Some(0.5));
+    assert_eq!(linear.position(Point::new(0.0, 0.0)), Some(-1.0));
+
+    // Concentric circles, from radius 2 to 6
+    let radial = Gradient::Radial { c0: Point::new(0.0, 0.0), r0: 2.0, c1: Point::new(0.0, 0.0), r1: 6.0 };
+    assert_eq!(radial.position(Point::new(4.0, 0.0)), Some(0.5));
+    assert_eq!(radial.position(Point::new(0.0, -6.0)), Some(1.0));
+
+    // A quarter turn clockwise, with y increasing upward
+    let sweep = Gradient::Sweep { c: Point::new(0.0, 0.0), start: 0.0, end: 90.0 };
+    assert_eq!(sweep.position(Point::new(1.0, -1.0)), Some(0.5));
+
+    let stops = [
+        Stop { offset: 0.0, color: [1.0, 0.0, 0.0, 1.0] },
+        Stop { offset: 1.0, color: [0.0, 0.0, 0.5, 0.5] },
+    ];
+    assert_eq!(Paint::color_line(&stops, Extend::Pad, 1.5), [0.0, 0.0, 0.5, 0.5]);
+    assert_eq!(Paint::color_line(&stops, Extend::Repeat, 1.5), [0.5, 0.0, 0.25, 0.75]);
+    assert_eq!(Paint::color_line(&stops, Extend::Reflect, 1.75), [0.75, 0.0, 0.125, 0.875]);
+}
+
+#[test]
+fn test_raster_composite() {
+    let square = |x: f32, y: f32| -> Vec<swash::zeno::Command> {
+        use swash::zeno::PathBuilder;
+        let mut path = Vec::new();
+        path.add_rect([x, y], 2.0, 2.0);
+        path
+    };
+    let shape = Shape::fill(Fill::NonZero, Transform::IDENTITY);
+
+    // Red and half blue, overlapping in one pixel
+    let mut raster = Raster::new(3, 3);
+    raster.fill_path(&square(0.0, 0.0), &shape, &Paint::Solid([1.0, 0.0, 0.0, 1.0]));
+    raster.push_layer(Composite::SrcOver, 0.5);
+    raster.fill_path(&square(1.0, 1.0), &shape, &Paint::Solid([0.0, 0.0, 1.0, 1.0]));
+    raster.pop_layer();
+    // Keep only the pixels of the blue square that are over red
+    raster.push_layer(Composite::DestIn, 1.0);
+    raster.fill_path(&square(0.0, 0.0), &shape, &Paint::Solid([0.0, 0.0, 0.0, 1.0]));
+    raster.pop_layer();
+
+    let image = raster.into_image(0, 0);
+    let pixel = |x: usize, y: usize| &image.data[(y * 3 + x) * 4..(y * 3 + x) * 4 + 4];
+    assert_eq!(pixel(0, 0), [0xFF, 0, 0, 0xFF]);
+    assert_eq!(pixel(1, 1), [0x80, 0, 0x80, 0xFF]);
+    assert_eq!(pixel(2, 2), [0, 0, 0, 0]);
+
+    // Fills are clipped
+    let mut raster = Raster::new(3, 3);
+    raster.push_clip(&square(1.0, 1.0), &shape);
+    raster.fill(&Paint::Solid([0.0, 1.0, 0.0, 1.0]));
+    raster.pop_clip();
+    let image = raster.into_image(0, 0);
+    assert_eq!(image.data.chunks(4).filter(|pixel| pixel[3] == 0xFF).count(), 4);
+    assert_eq!(image.data[0..4], [0, 0, 0, 0]);
+}
@@ -0,0 +1,770 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use std::collections::HashMap;
+use roxmltree::Node;
+use swash::scale::image::Image;
+use swash::zeno::{Bounds, Cap, Command, Fill, Join, PathBuilder, PathData, Stroke, Style, Transform, Vector};
+
+use crate::{CacheKey, Font, TextBrightness};
+use super::paint::{Composite, Extend, Gradient, Paint, Raster, Shape, Stop};
+use super::{concat, font_transform, image_rect, image_transform, intersect, premultiply, unite, PaletteColors};
+
+// Limit on nested use elements and gradient references, to stop cycles
+const MAX_DEPTH: usize = 16;
+
+const XLINK: &str = "http://www.w3.org/1999/xlink";
+
+// Drawing operations of a document, in its coordinates
+enum Op {
+    Draw {
+        path: Vec<Command>,
+        style: Style<'static>,
+        transform: Transform,
+        paint: Paint,
+    },
+    PushLayer(f32),
+    PopLayer,
+    // Clip to the union of paths
+    PushClip(Vec<(Vec<Command>, Transform, Fill)>),
+    PopClip,
+}
+
+// Paint of a fill or stroke before it is resolved for an element
+#[derive(Clone, Copy)]
+enum SvgPaint<'a, 'input> {
+    Color([u8; 4]),
+    CurrentColor,
+    Gradient(Node<'a, 'input>),
+}
+
+// Inherited properties of an element
+#[derive(Clone, Copy)]
+struct State<'a, 'input> {
+    color: [u8; 4],
+    fill_opt: Option<SvgPaint<'a, 'input>>,
+    fill_opacity: f32,
+    fill_rule: Fill,
+    stroke_opt: Option<SvgPaint<'a, 'input>>,
+    stroke_opacity: f32,
+    stroke_width: f32,
+    line_join: Join,
+    line_cap: Cap,
+    miter_limit: f32,
+}
+
+struct Document<'a, 'input> {
+    ids: HashMap<&'a str, Node<'a, 'input>>,
+    colors: &'a PaletteColors<'a>,
+    units_per_em: f32,
+    ops: Vec<Op>,
+}
+
+// Get a property from the style attribute, or from its presentation attribute
+fn property<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    if let Some(style) = node.attribute("style") {
+        for declaration in style.split(';') {
+            if let Some((key, value)) = declaration.split_once(':') {
+                if key.trim() == name {
+                    return Some(value.trim());
+                }
+            }
+        }
+    }
+    node.attribute(name).map(|value| value.trim())
+}
+
+fn href<'a>(node: Node<'a, '_>) -> Option<&'a str> {
+    node.attribute((XLINK, "href")).or_else(|| node.attribute("href"))?.strip_prefix('#')
+}
+
+fn number(value: &str) -> Option<f32> {
+    value.trim().trim_end_matches("px").parse().ok()
+}
+
+// Parse a number, with percentages of `percent`
+fn length(value: &str, percent: f32) -> Option<f32> {
+    match value.trim().strip_suffix('%') {
+        Some(value) => Some(number(value)? / 100.0 * percent),
+        None => number(value),
+    }
+}
+
+fn numbers(value: &str) -> Vec<f32> {
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .filter_map(number)
+        .collect()
+}
+
+fn opacity(value: &str) -> Option<f32> {
+    length(value, 1.0).map(|value| value.clamp(0.0, 1.0))
+}
+
+// Parse a transform list, where the first transform is applied last
+fn transform_list(value: &str) -> Transform {
+    let mut transform = Transform::IDENTITY;
+    let mut rest = value;
+    while let Some((name, after)) = rest.split_once('(') {
+        let (args, after) = match after.split_once(')') {
+            Some(some) => some,
+            None => break,
+        };
+        rest = after;
+        let args = numbers(args);
+        let arg = |i: usize, default: f32| args.get(i).copied().unwrap_or(default);
+        let next = match name.trim_matches(|c: char| c == ',' || c.is_whitespace()) {
+            "matrix" if args.len() == 6 => Transform::new(args[0], args[1], args[2], args[3], args[4], args[5]),
+            "translate" => Transform::translation(arg(0, 0.0), arg(1, 0.0)),
+            "scale" => Transform::scale(arg(0, 1.0), arg(1, arg(0, 1.0))),
+            "rotate" => {
+                let (sin, cos) = arg(0, 0.0).to_radians().sin_cos();
+                let (cx, cy) = (arg(1, 0.0), arg(2, 0.0));
+                let rotation = Transform::new(cos, sin, -sin, cos, 0.0, 0.0);
+                concat(&Transform::translation(-cx, -cy), &rotation).then_translate(cx, cy)
+            },
+            "skewX" => Transform::new(1.0, 0.0, arg(0, 0.0).to_radians().tan(), 1.0, 0.0, 0.0),
+            "skewY" => Transform::new(1.0, arg(0, 0.0).to_radians().tan(), 0.0, 1.0, 0.0, 0.0),
+            _ => continue,
+        };
+        transform = concat(&next, &transform);
+    }
+    transform
+}
+
+fn named_color(name: &str) -> Option<[u8; 3]> {
+    Some(match name {
+        "black" => [0, 0, 0],
+        "silver" => [0xC0, 0xC0, 0xC0],
+        "gray" | "grey" => [0x80, 0x80, 0x80],
+        "white" => [0xFF, 0xFF, 0xFF],
+        "maroon" => [0x80, 0, 0],
+        "red" => [0xFF, 0, 0],
+        "purple" => [0x80, 0, 0x80],
+        "fuchsia" | "magenta" => [0xFF, 0, 0xFF],
+        "green" => [0, 0x80, 0],
+        "lime" => [0, 0xFF, 0],
+        "olive" => [0x80, 0x80, 0],
+        "yellow" => [0xFF, 0xFF, 0],
+        "navy" => [0, 0, 0x80],
+        "blue" => [0, 0, 0xFF],
+        "teal" => [0, 0x80, 0x80],
+        "aqua" | "cyan" => [0, 0xFF, 0xFF],
+        "orange" => [0xFF, 0xA5, 0],
+        _ => return None,
+    })
+}
+
+impl<'a, 'input> Document<'a, 'input> {
+    // Parse a color, where `var(--colorN, fallback)` is entry N of the palette
+    fn color(&self, value: &str) -> Option<[u8; 4]> {
+        let value = value.trim();
+        if let Some(var) = value.strip_prefix("var(").and_then(|var| var.strip_suffix(')')) {
+            let (name, fallback_opt) = match var.split_once(',') {
+                Some((name, fallback)) => (name, Some(fallback)),
+                None => (var, None),
+            };
+            let entry_opt = name.trim().strip_prefix("--color")
+                .and_then(|index| index.parse::<u16>().ok())
+                .and_then(|index| self.colors.entry(index));
+            return entry_opt.or_else(|| self.color(fallback_opt?));
+        }
+
+        if let Some(hex) = value.strip_prefix('#') {
+            let digit = |i: usize| u8::from_str_radix(hex.get(i..i + 1)?, 16).ok();
+            let byte = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+            return match hex.len() {
+                3 => Some([digit(0)? * 0x11, digit(1)? * 0x11, digit(2)? * 0x11, 0xFF]),
+                6 => Some([byte(0)?, byte(2)?, byte(4)?, 0xFF]),
+                8 => Some([byte(0)?, byte(2)?, byte(4)?, byte(6)?]),
+                _ => None,
+            };
+        }
+
+        if let Some(args) = value.strip_prefix("rgba(").or_else(|| value.strip_prefix("rgb(")) {
+            let args: Vec<&str> = args.strip_suffix(')')?.split(',').collect();
+            if args.len() < 3 {
+                return None;
+            }
+            let channel = |i: usize| Some(length(args[i], 255.0)?.clamp(0.0, 255.0).round() as u8);
+            let alpha = match args.get(3) {
+                Some(alpha) => (opacity(alpha)? * 255.0).round() as u8,
+                None => 0xFF,
+            };
+            return Some([channel(0)?, channel(1)?, channel(2)?, alpha]);
+        }
+
+        if value.eq_ignore_ascii_case("transparent") {
+            return Some([0; 4]);
+        }
+        let [r, g, b] = named_color(&value.to_ascii_lowercase())?;
+        Some([r, g, b, 0xFF])
+    }
+
+    // Parse a fill or stroke, where `None` is not painted
+    fn paint(&self, value: &str) -> Option<Option<SvgPaint<'a, 'input>>> {
+        if value == "none" {
+            return Some(None);
+        }
+        if value == "currentColor" {
+            return Some(Some(SvgPaint::CurrentColor));
+        }
+        if let Some(url) = value.strip_prefix("url(") {
+            let (url, fallback) = url.split_once(')')?;
+            let id = url.trim().trim_matches(|c| c == '"' || c == '\'').strip_prefix('#')?;
+            return match self.ids.get(id) {
+                Some(node) => Some(Some(SvgPaint::Gradient(*node))),
+                None if fallback.trim().is_empty() => Some(None),
+                None => self.paint(fallback.trim()),
+            };
+        }
+        self.color(value).map(|color| Some(SvgPaint::Color(color)))
+    }
+
+    // Get the state of an element from the state of its parent
+    fn inherit(&self, node: Node<'a, 'input>, parent: &State<'a, 'input>) -> State<'a, 'input> {
+        let mut state = *parent;
+        if let Some(color) = property(node, "color").and_then(|value| self.color(value)) {
+            state.color = color;
+        }
+        if let Some(fill) = property(node, "fill").and_then(|value| self.paint(value)) {
+            state.fill_opt = fill;
+        }
+        if let Some(value) = property(node, "fill-opacity").and_then(opacity) {
+            state.fill_opacity = value;
+        }
+        match property(node, "fill-rule") {
+            Some("nonzero") => state.fill_rule = Fill::NonZero,
+            Some("evenodd") => state.fill_rule = Fill::EvenOdd,
+            _ => {},
+        }
+        if let Some(stroke) = property(node, "stroke").and_then(|value| self.paint(value)) {
+            state.stroke_opt = stroke;
+        }
+        if let Some(value) = property(node, "stroke-opacity").and_then(opacity) {
+            state.stroke_opacity = value;
+        }
+        if let Some(value) = property(node, "stroke-width").and_then(|value| length(value, self.units_per_em)) {
+            state.stroke_width = value;
+        }
+        match property(node, "stroke-linejoin") {
+            Some("miter") => state.line_join = Join::Miter,
+            Some("round") => state.line_join = Join::Round,
+            Some("bevel") => state.line_join = Join::Bevel,
+            _ => {},
+        }
+        match property(node, "stroke-linecap") {
+            Some("butt") => state.line_cap = Cap::Butt,
+            Some("round") => state.line_cap = Cap::Round,
+            Some("square") => state.line_cap = Cap::Square,
+            _ => {},
+        }
+        if let Some(value) = property(node, "stroke-miterlimit").and_then(number) {
+            state.miter_limit = value.max(1.0);
+        }
+        state
+    }
+
+    // Attribute of a gradient, or of the gradients it references
+    fn gradient_attribute(&self, node: Node<'a, 'input>, name: &str) -> Option<&'a str> {
+        let mut node = node;
+        for _ in 0..MAX_DEPTH {
+            if let Some(value) = node.attribute(name) {
+                return Some(value);
+            }
+            node = *self.ids.get(href(node)?)?;
+        }
+        None
+    }
+
+    // Stops of a gradient, or of the gradients it references
+    fn gradient_stops(&self, node: Node<'a, 'input>, state: &State, opacity: f32) -> Vec<Stop> {
+        let mut node = node;
+        for _ in 0..MAX_DEPTH {
+            let mut stops = Vec::new();
+            for child in node.children().filter(|child| child.has_tag_name("stop")) {
+                let offset = child.attribute("offset").and_then(|value| length(value, 1.0)).unwrap_or(0.0);
+                // Offsets can not decrease
+                let offset = offset.clamp(stops.last().map_or(0.0, |stop: &Stop| stop.offset), 1.0);
+                let color = match property(child, "stop-color") {
+                    Some("currentColor") => state.color,
+                    Some(value) => self.color(value).unwrap_or([0, 0, 0, 0xFF]),
+                    None => [0, 0, 0, 0xFF],
+                };
+                let stop_opacity = property(child, "stop-opacity").and_then(self::opacity).unwrap_or(1.0);
+                stops.push(Stop {
+                    offset,
+                    color: premultiply(color, stop_opacity * opacity),
+                });
+            }
+            if !stops.is_empty() {
+                return stops;
+            }
+            node = match href(node).and_then(|id| self.ids.get(id)) {
+                Some(some) => *some,
+                None => break,
+            };
+        }
+        Vec::new()
+    }
+
+    // Resolve a gradient for a path with bounds `bbox` in user space, where `None` is not painted
+    fn gradient(
+        &self,
+        node: Node<'a, 'input>,
+        state: &State,
+        opacity: f32,
+        bbox: Bounds,
+        transform: Transform,
+    ) -> Option<Paint> {
+        let linear = node.has_tag_name("linearGradient");
+        if !linear && !node.has_tag_name("radialGradient") {
+            return None;
+        }
+
+        let mut stops = self.gradient_stops(node, state, opacity);
+        if stops.len() < 2 {
+            return stops.pop().map(|stop| Paint::Solid(stop.color));
+        }
+
+        let attribute = |name: &str| self.gradient_attribute(node, name);
+        let user_space = attribute("gradientUnits") == Some("userSpaceOnUse");
+        let percent = if user_space { self.units_per_em } else { 1.0 };
+        let get = |name: &str, default: &str| {
+            length(attribute(name).unwrap_or(default), percent).unwrap_or(0.0)
+        };
+        let gradient = if linear {
+            Gradient::Linear {
+                p0: [get("x1", "0%"), get("y1", "0%")].into(),
+                p1: [get("x2", "100%"), get("y2", "0%")].into(),
+            }
+        } else {
+            let (cx, cy) = (get("cx", "50%"), get("cy", "50%"));
+            Gradient::Radial {
+                c0: [
+                    attribute("fx").and_then(|value| length(value, percent)).unwrap_or(cx),
+                    attribute("fy").and_then(|value| length(value, percent)).unwrap_or(cy),
+                ].into(),
+                r0: get("fr", "0%"),
+                c1: [cx, cy].into(),
+                r1: get("r", "50%"),
+            }
+        };
+        let extend = match attribute("spreadMethod") {
+            Some("reflect") => Extend::Reflect,
+            Some("repeat") => Extend::Repeat,
+            _ => Extend::Pad,
+        };
+
+        let mut gradient_transform = attribute("gradientTransform").map_or(Transform::IDENTITY, transform_list);
+        if !user_space {
+            let (w, h) = (bbox.max.x - bbox.min.x, bbox.max.y - bbox.min.y);
+            // Bounding box units are not defined for paths without area
+            if !(w > 0.0 && h > 0.0) {
+                return None;
+            }
+            let bbox_transform = Transform::new(w, 0.0, 0.0, h, bbox.min.x, bbox.min.y);
+            gradient_transform = concat(&gradient_transform, &bbox_transform);
+        }
+
+        Some(Paint::Gradient {
+            gradient,
+            stops,
+            extend,
+            transform: concat(&gradient_transform, &transform),
+        })
+    }
+
+    fn resolve(
+        &self,
+        paint: SvgPaint<'a, 'input>,
+        state: &State,
+        opacity: f32,
+        path: &[Command],
+        transform: Transform,
+    ) -> Option<Paint> {
+        match paint {
+            SvgPaint::Color(color) => Some(Paint::Solid(premultiply(color, opacity))),
+            SvgPaint::CurrentColor => Some(Paint::Solid(premultiply(state.color, opacity))),
+            SvgPaint::Gradient(node) => {
+                let bbox = swash::zeno::bounds(path, Fill::NonZero, None);
+                self.gradient(node, state, opacity, bbox, transform)
+            },
+        }
+    }
+
+    // Outline of a shape element, in its user space
+    fn shape(&self, node: Node) -> Option<Vec<Command>> {
+        let get = |name: &str| node.attribute(name).and_then(|value| length(value, self.units_per_em));
+        let mut path = Vec::new();
+        match node.tag_name().name() {
+            "path" => path.extend(node.attribute("d")?.commands()),
+            "rect" => {
+                let (w, h) = (get("width")?, get("height")?);
+                if !(w > 0.0 && h > 0.0) {
+                    return None;
+                }
+                let (rx, ry) = match (get("rx"), get("ry")) {
+                    (Some(rx), Some(ry)) => (rx, ry),
+                    (Some(r), None) | (None, Some(r)) => (r, r),
+                    (None, None) => (0.0, 0.0),
+                };
+                let xy = [get("x").unwrap_or(0.0), get("y").unwrap_or(0.0)];
+                if rx > 0.0 && ry > 0.0 {
+                    path.add_round_rect(xy, w, h, rx.min(w / 2.0), ry.min(h / 2.0));
+                } else {
+                    path.add_rect(xy, w, h);
+                }
+            },
+            "circle" => {
+                let r = get("r")?;
+                if r <= 0.0 {
+                    return None;
+                }
+                path.add_circle([get("cx").unwrap_or(0.0), get("cy").unwrap_or(0.0)], r);
+            },
+            "ellipse" => {
+                let (rx, ry) = (get("rx")?, get("ry")?);
+                if !(rx > 0.0 && ry > 0.0) {
+                    return None;
+                }
+                path.add_ellipse([get("cx").unwrap_or(0.0), get("cy").unwrap_or(0.0)], rx, ry);
+            },
+            "line" => {
+                path.move_to([get("x1").unwrap_or(0.0), get("y1").unwrap_or(0.0)]);
+                path.line_to([get("x2").unwrap_or(0.0), get("y2").unwrap_or(0.0)]);
+            },
+            "polyline" | "polygon" => {
+                let points = numbers(node.attribute("points")?);
+                let mut pairs = points.chunks_exact(2);
+                let first = pairs.next()?;
+                path.move_to([first[0], first[1]]);
+                for pair in pairs {
+                    path.line_to([pair[0], pair[1]]);
+                }
+                if node.has_tag_name("polygon") {
+                    path.close();
+                }
+            },
+            _ => return None,
+        }
+        Some(path)
+    }
+
+    // Paths of a clipPath element, in the user space of the element it clips
+    fn clip_paths(&self, node: Node<'a, 'input>, transform: Transform) -> Vec<(Vec<Command>, Transform, Fill)> {
+        let transform = concat(&node.attribute("transform").map_or(Transform::IDENTITY, transform_list), &transform);
+        let mut paths = Vec::new();
+        for child in node.children().filter(|child| child.is_element()) {
+            if property(child, "display") == Some("none") {
+                continue;
+            }
+            let child_transform = child.attribute("transform").map_or(Transform::IDENTITY, transform_list);
+            let fill = match property(child, "clip-rule").or_else(|| property(node, "clip-rule")) {
+                Some("evenodd") => Fill::EvenOdd,
+                _ => Fill::NonZero,
+            };
+            if let Some(path) = self.shape(child) {
+                paths.push((path, concat(&child_transform, &transform), fill));
+            }
+        }
+        paths
+    }
+
+    fn draw(&mut self, node: Node<'a, 'input>, state: &State<'a, 'input>, transform: Transform) {
+        let path = match self.shape(node) {
+            Some(some) => some,
+            None => return,
+        };
+        if let Some(fill) = state.fill_opt {
+            if let Some(paint) = self.resolve(fill, state, state.fill_opacity, &path, transform) {
+                self.ops.push(Op::Draw {
+                    path: path.clone(),
+                    style: Style::Fill(state.fill_rule),
+                    transform,
+                    paint,
+                });
+            }
+        }
+        if let Some(stroke) = state.stroke_opt {
+            if state.stroke_width > 0.0 {
+                if let Some(paint) = self.resolve(stroke, state, state.stroke_opacity, &path, transform) {
+                    let mut style = Stroke::new(state.stroke_width);
+                    style.join(state.line_join).cap(state.line_cap).miter_limit(state.miter_limit);
+                    self.ops.push(Op::Draw {
+                        path,
+                        style: Style::Stroke(style),
+                        transform,
+                        paint,
+                    });
+                }
+            }
+        }
+    }
+
+    // Add the operations of an element and its children
+    fn element(&mut self, node: Node<'a, 'input>, parent: &State<'a, 'input>, transform: Transform, depth: usize) {
+        if depth > MAX_DEPTH {
+            return;
+        }
+        let name = node.tag_name().name();
+        if matches!(
+            name,
+            "defs" | "clipPath" | "linearGradient" | "radialGradient" | "mask" | "pattern" | "symbol" | "style"
+        ) || property(node, "display") == Some("none") {
+            return;
+        }
+
+        let state = self.inherit(node, parent);
+        let mut transform = concat(&node.attribute("transform").map_or(Transform::IDENTITY, transform_list), &transform);
+
+        // Opacity applies to the element and its children as a group
+        let opacity = property(node, "opacity").and_then(opacity).unwrap_or(1.0);
+        if opacity < 1.0 {
+            self.ops.push(Op::PushLayer(opacity));
+        }
+        let clip_opt = property(node, "clip-path")
+            .and_then(|value| value.strip_prefix("url(#")?.strip_suffix(')'))
+            .and_then(|id| self.ids.get(id.trim()).copied())
+            .filter(|clip| clip.has_tag_name("clipPath"));
+        if let Some(clip) = clip_opt {
+            let paths = self.clip_paths(clip, transform);
+            self.ops.push(Op::PushClip(paths));
+        }
+
+        match name {
+            "svg" | "g" | "a" | "switch" => {
+                for child in node.children().filter(|child| child.is_element()) {
+                    self.element(child, &state, transform, depth + 1);
+                }
+            },
+            "use" => {
+                if let Some(target) = href(node).and_then(|id| self.ids.get(id).copied()) {
+                    let get = |name: &str| node.attribute(name).and_then(|value| length(value, self.units_per_em));
+                    let translation = Transform::translation(get("x").unwrap_or(0.0), get("y").unwrap_or(0.0));
+                    transform = concat(&translation, &transform);
+                    // Symbols are only drawn by use elements
+                    if target.has_tag_name("symbol") {
+                        let state = self.inherit(target, &state);
+                        for child in target.children().filter(|child| child.is_element()) {
+                            self.element(child, &state, transform, depth + 1);
+                        }
+                    } else {
+                        self.element(target, &state, transform, depth + 1);
+                    }
+                }
+            },
+            _ => self.draw(node, &state, transform),
+        }
+
+        if clip_opt.is_some() {
+            self.ops.push(Op::PopClip);
+        }
+        if opacity < 1.0 {
+            self.ops.push(Op::PopLayer);
+        }
+    }
+}
+
+// Decompress a document if it is compressed with gzip
+fn gunzip(data: &[u8]) -> Option<Vec<u8>> {
+    const FEXTRA: u8 = 4;
+    const FNAME: u8 = 8;
+    const FCOMMENT: u8 = 16;
+    const FHCRC: u8 = 2;
+
+    if data.get(..3)? != [0x1F, 0x8B, 8] {
+        return None;
+    }
+    let flags = data[3];
+    let mut i = 10;
+    if flags & FEXTRA != 0 {
+        let len = u16::from_le_bytes([*data.get(i)?, *data.get(i + 1)?]) as usize;
+        i += 2 + len;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            i += data.get(i..)?.iter().position(|&byte| byte == 0)? + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        i += 2;
+    }
+    miniz_oxide::inflate::decompress_to_vec(data.get(i..)?).ok()
+}
+
+// Render a glyph from an SVG document, if it has one
+pub(crate) fn svg_image(
+    font: &Font,
+    cache_key: CacheKey,
+    offset: Vector,
+    transform_opt: Option<Transform>,
+    palette: u16,
+    brightness: TextBrightness,
+) -> Option<Image> {
+    let data = font.rustybuzz.glyph_svg_image(ttf_parser::GlyphId(cache_key.glyph_id))?;
+    let decompressed_opt = if data.starts_with(&[0x1F, 0x8B]) {
+        Some(gunzip(data)?)
+    } else {
+        None
+    };
+    let text = match std::str::from_utf8(decompressed_opt.as_deref().unwrap_or(data)) {
+        Ok(ok) => ok,
+        Err(err) => {
+            log::warn!("failed to decode SVG document of glyph {}: {}", cache_key.glyph_id, err);
+            return None;
+        }
+    };
+    let options = roxmltree::ParsingOptions {
+        allow_dtd: true,
+        ..Default::default()
+    };
+    let tree = match roxmltree::Document::parse_with_options(text, options) {
+        Ok(ok) => ok,
+        Err(err) => {
+            log::warn!("failed to parse SVG document of glyph {}: {}", cache_key.glyph_id, err);
+            return None;
+        }
+    };
+
+    let units_per_em = font.rustybuzz.units_per_em() as f32;
+    let colors = PaletteColors::new(font, palette, brightness);
+    let mut document = Document {
+        ids: tree.descendants()
+            .filter_map(|node| Some((node.attribute("id")?, node)))
+            .collect(),
+        colors: &colors,
+        units_per_em,
+        ops: Vec::new(),
+    };
+
+    // Documents with many glyphs have an element for each one, with the properties of its ancestors
+    let id = format!("glyph{}", cache_key.glyph_id);
+    let node = document.ids.get(id.as_str()).copied().unwrap_or_else(|| tree.root_element());
+    let mut state = State {
+        color: colors.entry(0xFFFF).unwrap_or([0, 0, 0, 0xFF]),
+        fill_opt: Some(SvgPaint::Color([0, 0, 0, 0xFF])),
+        fill_opacity: 1.0,
+        fill_rule: Fill::NonZero,
+        stroke_opt: None,
+        stroke_opacity: 1.0,
+        stroke_width: 1.0,
+        line_join: Join::Miter,
+        line_cap: Cap::Butt,
+        miter_limit: 4.0,
+    };
+    let mut transform = Transform::IDENTITY;
+    let mut ancestors: Vec<_> = node.ancestors().skip(1).filter(|node| node.is_element()).collect();
+    ancestors.reverse();
+    for ancestor in ancestors {
+        state = document.inherit(ancestor, &state);
+        if let Some(value) = ancestor.attribute("transform") {
+            transform = concat(&transform_list(value), &transform);
+        }
+    }
+    document.element(node, &state, transform, 0);
+
+    // Documents have y increasing downward, from the glyph origin in font units
+    let base = concat(&Transform::scale(1.0, -1.0), &font_transform(units_per_em, cache_key, offset, transform_opt));
+
+    // Find the size of the image, then paint it
+    let mut bounds_opt = None;
+    let mut clips: Vec<Bounds> = Vec::new();
+    for op in document.ops.iter() {
+        match op {
+            Op::Draw { path, style, transform, .. } => {
+                let mut bounds = swash::zeno::bounds(path, *style, Some(concat(transform, &base)));
+                if let Some(clip) = clips.last() {
+                    bounds = intersect(bounds, *clip);
+                }
+                bounds_opt = unite(bounds_opt, bounds);
+            },
+            Op::PushClip(paths) => {
+                let mut clip_opt = None;
+                for (path, transform, fill) in paths.iter() {
+                    clip_opt = unite(clip_opt, swash::zeno::bounds(path, *fill, Some(concat(transform, &base))));
+                }
+                // An empty clip hides everything
+                let mut clip = clip_opt.unwrap_or(Bounds::new(Vector::new(0.0, 0.0), Vector::new(0.0, 0.0)));
+                if let Some(outer) = clips.last() {
+                    clip = intersect(clip, *outer);
+                }
+                clips.push(clip);
+            },
+            Op::PopClip => {
+                clips.pop();
+            },
+            Op::PushLayer(_) | Op::PopLayer => {},
+        }
+    }
+    let (left, top, width, height) = image_rect(bounds_opt?)?;
+
+    let root = concat(&base, &image_transform(left, top));
+    let mut raster = Raster::new(width, height);
+    for op in document.ops.iter() {
+        match op {
+            Op::Draw { path, style, transform, paint } => {
+                let transform = concat(transform, &root);
+                let paint = match paint {
+                    Paint::Gradient { gradient, stops, extend, transform } => Paint::Gradient {
+                        gradient: *gradient,
+                        stops: stops.clone(),
+                        extend: *extend,
+                        transform: concat(transform, &root),
+                    },
+                    solid => solid.clone(),
+                };
+                let shape = Shape {
+                    style: *style,
+                    transform,
+                };
+                raster.fill_path(path, &shape, &paint);
+            },
+            Op::PushLayer(opacity) => raster.push_layer(Composite::SrcOver, *opacity),
+            Op::PopLayer => raster.pop_layer(),
+            Op::PushClip(paths) => {
+                let mut coverage = vec![0.0; width as usize * height as usize];
+                for (path, transform, fill) in paths.iter() {
+                    let shape = Shape::fill(*fill, concat(transform, &root));
+                    for (a, b) in coverage.iter_mut().zip(raster.coverage(path, &shape)) {
+                        *a = b.max(*a);
+                    }
+                }
+                raster.push_clip_coverage(coverage);
+            },
+            Op::PopClip => raster.pop_clip(),
+        }
+    }
+    Some(raster.into_image(left, top))
+}
+
+#[test]
+fn test_svg_values() {
+    use swash::zeno::Point;
+
+    let colors = PaletteColors {
+        palette_opt: None,
+        foreground: [0xFF; 4],
+    };
+    let document = Document {
+        ids: HashMap::new(),
+        colors: &colors,
+        units_per_em: 1000.0,
+        ops: Vec::new(),
+    };
+    assert_eq!(document.color("#0F8"), Some([0, 0xFF, 0x88, 0xFF]));
+    assert_eq!(document.color("#10203040"), Some([0x10, 0x20, 0x30, 0x40]));
+    assert_eq!(document.color("rgb(255, 50%, 0)"), Some([0xFF, 0x80, 0, 0xFF]));
+    assert_eq!(document.color("Teal"), Some([0, 0x80, 0x80, 0xFF]));
+    // Palette entries that are missing use the fallback
+    assert_eq!(document.color("var(--color65535)"), Some([0xFF; 4]));
+    assert_eq!(document.color("var(--color2, red)"), Some([0xFF, 0, 0, 0xFF]));
+    assert_eq!(document.color("var(--color2)"), None);
+
+    // The last transform in a list is applied first
+    let transform = transform_list("translate(10, 20) scale(2) rotate(90 1 1)");
+    let p = transform.transform_point(Point::new(2.0, 1.0));
+    assert!((p.x - 12.0).abs() < 1e-4 && (p.y - 24.0).abs() < 1e-4, "{:?}", p);
+    assert_eq!(length("50%", 1000.0), Some(500.0));
+    assert_eq!(numbers("1,2 3, 4"), [1.0, 2.0, 3.0, 4.0]);
+}
@@ -0,0 +1,105 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use std::cmp::Reverse;
+use unicode_script::{Script, UnicodeScript};
+
+/// Minimum number of covered code points for a font to support a script
+const SCRIPT_MIN_CODEPOINTS: usize = 16;
+
+/// Unicode coverage of a font, built from its cmap table
+pub struct FontCoverage {
+    /// Sorted, non-overlapping, inclusive ranges of covered code points
+    ranges: Vec<(u32, u32)>,
+}
+
+impl FontCoverage {
+    /// Build coverage from the Unicode subtables of a font's cmap
+    pub fn new(face: &ttf_parser::Face) -> Self {
+        let mut codepoints = Vec::new();
+        if let Some(cmap) = face.tables().cmap {
+            for subtable in cmap.subtables {
+                if !subtable.is_unicode() {
+                    continue;
+                }
+
+                subtable.codepoints(|codepoint| {
+                    // Some subtables list code points that map to the missing glyph
+                    if subtable.glyph_index(codepoint).is_some_and(|id| id.0 != 0) {
+                        codepoints.push(codepoint);
+                    }
+                });
+            }
+        }
+        codepoints.sort_unstable();
+        codepoints.dedup();
+
+        let mut ranges: Vec<(u32, u32)> = Vec::new();
+        for codepoint in codepoints {
+            match ranges.last_mut() {
+                Some(range) if range.1 + 1 == codepoint => range.1 = codepoint,
+                _ => ranges.push((codepoint, codepoint)),
+            }
+        }
+
+        Self { ranges }
+    }
+
+    /// Create coverage from sorted, non-overlapping, inclusive ranges of code points
+    pub(crate) fn from_ranges(ranges: Vec<(u32, u32)>) -> Self {
+        Self { ranges }
+    }
+
+    /// Get the sorted, non-overlapping, inclusive ranges of covered code points
+    pub(crate) fn ranges(&self) -> &[(u32, u32)] {
+        &self.ranges
+    }
+
+    /// Check if a character is covered
+    pub fn contains(&self, c: char) -> bool {
+        let codepoint = c as u32;
+        self.ranges.binary_search_by(|&(start, end)| {
+            if end < codepoint {
+                std::cmp::Ordering::Less
+            } else if start > codepoint {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        }).is_ok()
+    }
+
+    /// Check if all characters are covered
+    pub fn contains_all(&self, chars: &[char]) -> bool {
+        chars.iter().all(|c| self.contains(*c))
+    }
+
+    /// Scripts with enough covered code points to be supported, sorted by coverage
+    pub fn scripts(&self) -> Vec<Script> {
+        let mut counts: Vec<(Script, usize)> = Vec::new();
+        for &(start, end) in self.ranges.iter() {
+            for c in (start..=end).filter_map(char::from_u32) {
+                let script = c.script();
+                if matches!(script, Script::Common | Script::Inherited | Script::Unknown) {
+                    continue;
+                }
+                match counts.iter_mut().find(|(count_script, _)| *count_script == script) {
+                    Some((_, count)) => *count += 1,
+                    None => counts.push((script, 1)),
+                }
+            }
+        }
+        counts.retain(|(_, count)| *count >= SCRIPT_MIN_CODEPOINTS);
+        counts.sort_by_key(|(_, count)| Reverse(*count));
+        counts.into_iter().map(|(script, _)| script).collect()
+    }
+
+    /// Number of covered code points
+    pub fn len(&self) -> usize {
+        self.ranges.iter().map(|(start, end)| (end - start + 1) as usize).sum()
+    }
+
+    /// Check if no code points are covered
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+}
@@ -0,0 +1,188 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use std::collections::HashMap;
+use unicode_script::Script;
+
+/// How configured fallback families combine with the built-in families
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FallbackMode {
+    /// Try configured families before the built-in families, and for scripts also before the
+    /// requested family
+    Prepend,
+    /// Try configured families after the built-in families
+    Append,
+    /// Use only the configured families
+    Replace,
+}
+
+#[derive(Clone, Debug)]
+struct FallbackList {
+    families: Vec<String>,
+    mode: FallbackMode,
+}
+
+impl FallbackList {
+    fn new(families: &[&str], mode: FallbackMode) -> Self {
+        Self {
+            families: families.iter().map(|family| family.to_string()).collect(),
+            mode,
+        }
+    }
+
+    fn apply<'a>(&'a self, builtin: &[&'a str]) -> Vec<&'a str> {
+        let configured = self.families.iter().map(|family| family.as_str());
+        match self.mode {
+            FallbackMode::Prepend => configured.chain(builtin.iter().copied()).collect(),
+            FallbackMode::Append => builtin.iter().copied().chain(configured).collect(),
+            FallbackMode::Replace => configured.collect(),
+        }
+    }
+}
+
+/// User configuration of font fallback, overriding or extending the built-in families
+///
+/// Locale keys match either the full locale (`zh-HK`) or its language (`ja` for `ja-JP`),
+/// ignoring case. Locale specific configuration takes precedence over configuration for all locales.
+#[derive(Clone, Debug, Default)]
+pub struct FontFallbackConfig {
+    common: Option<FallbackList>,
+    locale_common: HashMap<String, FallbackList>,
+    scripts: HashMap<Script, FallbackList>,
+    locale_scripts: HashMap<(String, Script), FallbackList>,
+    forbidden: Vec<String>,
+}
+
+impl FontFallbackConfig {
+    /// Create an empty configuration, using only the built-in families
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set families used after any script specific fallbacks
+    pub fn set_common(&mut self, families: &[&str], mode: FallbackMode) {
+        self.common = Some(FallbackList::new(families, mode));
+    }
+
+    /// Set families used after any script specific fallbacks, for a locale
+    pub fn set_locale_common(&mut self, locale: &str, families: &[&str], mode: FallbackMode) {
+        self.locale_common.insert(locale.to_lowercase(), FallbackList::new(families, mode));
+    }
+
+    /// Set families used for a script
+    pub fn set_script(&mut self, script: Script, families: &[&str], mode: FallbackMode) {
+        self.scripts.insert(script, FallbackList::new(families, mode));
+    }
+
+    /// Set families used for a script, for a locale
+    pub fn set_locale_script(
+        &mut self,
+        locale: &str,
+        script: Script,
+        families: &[&str],
+        mode: FallbackMode
+    ) {
+        self.locale_scripts.insert(
+            (locale.to_lowercase(), script),
+            FallbackList::new(families, mode)
+        );
+    }
+
+    /// Add a family that will never be used for fallback
+    pub fn add_forbidden(&mut self, family: &str) {
+        if !self.is_forbidden(family) {
+            self.forbidden.push(family.to_string());
+        }
+    }
+
+    /// Get the families that will never be used for fallback
+    pub fn forbidden(&self) -> &[String] {
+        &self.forbidden
+    }
+
+    /// Remove all configuration, using only the built-in families
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Check if any families are configured for a script
+    pub fn has_script(&self, script: Script) -> bool {
+        self.scripts.contains_key(&script)
+        || self.locale_scripts.keys().any(|(_, key_script)| *key_script == script)
+    }
+
+    /// Check if a family will never be used for fallback
+    pub fn is_forbidden(&self, family: &str) -> bool {
+        self.forbidden.iter().any(|forbidden| forbidden == family)
+    }
+
+    /// Resolve the families used after any script specific fallbacks
+    pub fn common_families<'a>(&'a self, locale: &str, builtin: &[&'a str]) -> Vec<&'a str> {
+        let locale = locale.to_lowercase();
+        let list_opt = locale_keys(&locale)
+            .find_map(|key| self.locale_common.get(key))
+            .or(self.common.as_ref());
+        match list_opt {
+            Some(list) => list.apply(builtin),
+            None => builtin.to_vec(),
+        }
+    }
+
+    /// Resolve the families used for a script
+    pub fn script_families<'a>(
+        &'a self,
+        script: Script,
+        locale: &str,
+        builtin: &[&'a str]
+    ) -> Vec<&'a str> {
+        match self.script_list(script, locale) {
+            Some(list) => list.apply(builtin),
+            None => builtin.to_vec(),
+        }
+    }
+
+    /// Resolve the families tried before the requested family for a script
+    ///
+    /// These are the families configured with [FallbackMode::Prepend]
+    pub fn preferred_families(&self, script: Script, locale: &str) -> Vec<&str> {
+        match self.script_list(script, locale) {
+            Some(list) if list.mode == FallbackMode::Prepend => list.apply(&[]),
+            _ => Vec::new(),
+        }
+    }
+
+    fn script_list(&self, script: Script, locale: &str) -> Option<&FallbackList> {
+        let locale = locale.to_lowercase();
+        let list_opt = locale_keys(&locale)
+            .find_map(|key| self.locale_scripts.get(&(key.to_string(), script)));
+        list_opt.or_else(|| self.scripts.get(&script))
+    }
+}
+
+/// Full locale followed by its language, if different
+fn locale_keys(locale: &str) -> impl Iterator<Item = &str> {
+    let language = locale.split(['-', '_']).next().unwrap_or(locale);
+    std::iter::once(locale).chain(
+        Some(language).filter(|language| *language != locale)
+    )
+}
+
+#[test]
+fn test_fallback_config() {
+    let mut config = FontFallbackConfig::new();
+    config.set_common(&["Brand Sans"], FallbackMode::Prepend);
+    config.set_script(Script::Han, &["Brand CJK"], FallbackMode::Append);
+    config.set_locale_script("ja", Script::Han, &["Brand JP"], FallbackMode::Replace);
+    config.add_forbidden("Broken Sans");
+
+    assert_eq!(config.common_families("en-US", &["Noto Sans"]), ["Brand Sans", "Noto Sans"]);
+    assert_eq!(config.script_families(Script::Han, "zh-CN", &["Noto Sans CJK SC"]), ["Noto Sans CJK SC", "Brand CJK"]);
+    assert_eq!(config.script_families(Script::Han, "ja-JP", &["Noto Sans CJK JA"]), ["Brand JP"]);
+    assert_eq!(config.script_families(Script::Arabic, "ja-JP", &["Noto Sans Arabic"]), ["Noto Sans Arabic"]);
+    assert!(config.has_script(Script::Han));
+    assert!(!config.has_script(Script::Latin));
+    assert!(config.is_forbidden("Broken Sans"));
+
+    assert!(config.preferred_families(Script::Han, "zh-CN").is_empty());
+    config.set_script(Script::Latin, &["Brand Sans"], FallbackMode::Prepend);
+    assert_eq!(config.preferred_families(Script::Latin, "en-US"), ["Brand Sans"]);
+}
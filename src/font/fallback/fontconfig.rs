@@ -0,0 +1,305 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use fontconfig_parser::{
+    EditMode,
+    Expression,
+    FontConfig,
+    FontMatch,
+    MatchTarget,
+    Property,
+    Value,
+};
+use std::path::{Path, PathBuf};
+use unicode_script::Script;
+
+use super::{FallbackMode, FontFallbackConfig};
+
+// Scripts that a fontconfig language tag selects fonts for
+fn lang_scripts(lang: &str) -> &'static [Script] {
+    let language = lang.split(['-', '_']).next().unwrap_or(lang);
+    match language {
+        "am" | "ti" => &[Script::Ethiopic],
+        "ar" | "fa" | "ps" | "ur" => &[Script::Arabic],
+        "bn" | "as" => &[Script::Bengali],
+        "bo" | "dz" => &[Script::Tibetan],
+        "el" => &[Script::Greek],
+        "gu" => &[Script::Gujarati],
+        "he" | "yi" => &[Script::Hebrew],
+        "hi" | "mr" | "ne" | "sa" => &[Script::Devanagari],
+        "hy" => &[Script::Armenian],
+        "ja" => &[Script::Han, Script::Hiragana, Script::Katakana],
+        "ka" => &[Script::Georgian],
+        "km" => &[Script::Khmer],
+        "kn" => &[Script::Kannada],
+        "ko" => &[Script::Hangul, Script::Han],
+        "lo" => &[Script::Lao],
+        "ml" => &[Script::Malayalam],
+        "mn" => &[Script::Mongolian],
+        "my" => &[Script::Myanmar],
+        "or" => &[Script::Oriya],
+        "pa" => &[Script::Gurmukhi],
+        "si" => &[Script::Sinhala],
+        "syr" => &[Script::Syriac],
+        "ta" => &[Script::Tamil],
+        "te" => &[Script::Telugu],
+        "th" => &[Script::Thai],
+        "zh" => &[Script::Han, Script::Bopomofo],
+        _ => &[],
+    }
+}
+
+// Scripts where the font depends on the locale (Han unification)
+fn script_by_locale(script: Script) -> bool {
+    matches!(
+        script,
+        Script::Bopomofo |
+        Script::Han |
+        Script::Hangul |
+        Script::Hiragana |
+        Script::Katakana
+    )
+}
+
+fn expression_strings(expression: &Expression, strings: &mut Vec<String>) {
+    match expression {
+        Expression::Simple(Value::String(string)) => strings.push(string.clone()),
+        Expression::Simple(Value::LangSet(string)) => strings.push(string.clone()),
+        Expression::List(_, expressions) => for expression in expressions.iter() {
+            expression_strings(expression, strings);
+        },
+        _ => (),
+    }
+}
+
+fn push_unique(families: &mut Vec<String>, family: &str) {
+    if !families.iter().any(|existing| existing == family) {
+        families.push(family.to_string());
+    }
+}
+
+// Match a fontconfig glob, where `*` matches any characters and `?` matches one character
+fn glob_matches(glob: &str, text: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut glob_i, mut text_i) = (0, 0);
+    // Position after the last `*`, and the text position it matches up to
+    let mut star_opt = None;
+    while text_i < text.len() {
+        match glob.get(glob_i) {
+            Some('*') => {
+                glob_i += 1;
+                star_opt = Some((glob_i, text_i));
+            },
+            Some(c) if *c == '?' || *c == text[text_i] => {
+                glob_i += 1;
+                text_i += 1;
+            },
+            _ => match star_opt {
+                // Let the last `*` match one more character
+                Some((star_glob_i, star_text_i)) => {
+                    glob_i = star_glob_i;
+                    text_i = star_text_i + 1;
+                    star_opt = Some((star_glob_i, text_i));
+                },
+                None => return false,
+            },
+        }
+    }
+    glob[glob_i..].iter().all(|c| *c == '*')
+}
+
+fn config_path() -> PathBuf {
+    match std::env::var_os("FONTCONFIG_FILE") {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from("/etc/fonts/fonts.conf"),
+    }
+}
+
+/// Read the user's fontconfig configuration, setting generic families and fallbacks
+///
+/// Aliases for `sans-serif`, `serif`, and `monospace` select the generic families and the
+/// common fallbacks, pattern matches on `lang` select script fallbacks, and rejected families
+/// are forbidden. Faces from files matching rejected globs are removed.
+pub fn load_fontconfig(db: &mut fontdb::Database, config: &mut FontFallbackConfig) {
+    load_fontconfig_path(db, config, &config_path());
+}
+
+fn load_fontconfig_path(db: &mut fontdb::Database, config: &mut FontFallbackConfig, path: &Path) {
+    let now = std::time::Instant::now();
+
+    let mut fontconfig = FontConfig::default();
+    if let Err(err) = fontconfig.merge_config(path) {
+        log::warn!("failed to read fontconfig '{}': {}", path.display(), err);
+        return;
+    }
+
+    let has_family = |db: &fontdb::Database, family: &str| {
+        db.faces().iter().any(|face| face.family == family)
+    };
+
+    // Generic families, and common fallbacks from their preferred families
+    let mut common = Vec::new();
+    for generic in ["sans-serif", "serif", "monospace", "emoji"] {
+        let mut families = Vec::new();
+        for alias in fontconfig.aliases.iter() {
+            if alias.alias == generic {
+                for family in alias.prefer.iter().chain(alias.accept.iter()).chain(alias.default.iter()) {
+                    push_unique(&mut families, family);
+                }
+            }
+        }
+
+        if let Some(family) = families.iter().find(|family| has_family(db, family)) {
+            log::debug!("fontconfig {} family '{}'", generic, family);
+            match generic {
+                "sans-serif" => db.set_sans_serif_family(family.as_str()),
+                "serif" => db.set_serif_family(family.as_str()),
+                "monospace" => db.set_monospace_family(family.as_str()),
+                _ => (),
+            }
+        }
+
+        if generic != "serif" {
+            for family in families.iter() {
+                push_unique(&mut common, family);
+            }
+        }
+    }
+    if !common.is_empty() {
+        let common: Vec<&str> = common.iter().map(|family| family.as_str()).collect();
+        config.set_common(&common, FallbackMode::Prepend);
+    }
+
+    // Script fallbacks from families added for a language
+    let mut langs: Vec<(String, Vec<String>)> = Vec::new();
+    for match_ in fontconfig.matches.iter() {
+        if match_.target != MatchTarget::Pattern {
+            continue;
+        }
+
+        let mut match_langs = Vec::new();
+        for test in match_.tests.iter() {
+            if let Property::Lang(expression) = &test.value {
+                expression_strings(expression, &mut match_langs);
+            }
+        }
+
+        let mut families = Vec::new();
+        for edit in match_.edits.iter() {
+            if matches!(edit.mode, EditMode::Delete | EditMode::DeleteAll) {
+                continue;
+            }
+            if let Property::Family(expression) = &edit.value {
+                expression_strings(expression, &mut families);
+            }
+        }
+
+        for lang in match_langs {
+            let lang = lang.to_lowercase();
+            let lang_families = match langs.iter_mut().find(|(key, _)| *key == lang) {
+                Some((_, lang_families)) => lang_families,
+                None => {
+                    langs.push((lang, Vec::new()));
+                    &mut langs.last_mut().unwrap().1
+                }
+            };
+            for family in families.iter() {
+                push_unique(lang_families, family);
+            }
+        }
+    }
+    for (lang, families) in langs.iter() {
+        let families: Vec<&str> = families.iter()
+            .map(|family| family.as_str())
+            .filter(|family| has_family(db, family))
+            .collect();
+        if families.is_empty() {
+            continue;
+        }
+
+        for script in lang_scripts(lang) {
+            log::debug!("fontconfig {:?} for '{}': {:?}", script, lang, families);
+            if script_by_locale(*script) {
+                config.set_locale_script(lang, *script, &families, FallbackMode::Prepend);
+            } else {
+                config.set_script(*script, &families, FallbackMode::Prepend);
+            }
+        }
+    }
+
+    // Rejected families
+    for select_font in fontconfig.select_fonts.iter() {
+        for reject in select_font.rejects.iter() {
+            match reject {
+                FontMatch::Pattern(properties) => for property in properties.iter() {
+                    if let Property::Family(expression) = property {
+                        let mut families = Vec::new();
+                        expression_strings(expression, &mut families);
+                        for family in families {
+                            config.add_forbidden(&family);
+                        }
+                    }
+                },
+                FontMatch::Glob(glob) => {
+                    let rejected: Vec<fontdb::ID> = db.faces().iter().filter(|face| {
+                        match &face.source {
+                            fontdb::Source::File(path) | fontdb::Source::SharedFile(path, _) => {
+                                glob_matches(glob, &path.to_string_lossy())
+                            },
+                            fontdb::Source::Binary(_) => false,
+                        }
+                    }).map(|face| face.id).collect();
+                    for id in rejected {
+                        log::debug!("fontconfig rejectfont glob '{}' removed face {:?}", glob, id);
+                        db.remove_face(id);
+                    }
+                }
+            }
+        }
+    }
+
+    log::info!(
+        "Loaded fontconfig '{}' in {}ms.",
+        path.display(),
+        now.elapsed().as_millis()
+    );
+}
+
+#[test]
+fn test_glob_matches() {
+    assert!(glob_matches("/usr/share/fonts/*.ttf", "/usr/share/fonts/a/b.ttf"));
+    assert!(glob_matches("*/Noto?ans-*", "/fonts/NotoSans-Bold.otf"));
+    assert!(glob_matches("*", ""));
+    assert!(!glob_matches("*.ttf", "/fonts/a.otf"));
+    assert!(!glob_matches("/fonts/?", "/fonts/ab"));
+}
+
+#[cfg(feature = "test-fonts")]
+#[test]
+fn test_load_fontconfig() {
+    use crate::Family;
+
+    let mut db = fontdb::Database::new();
+    db.load_fonts_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/fonts"));
+    let mut config = FontFallbackConfig::new();
+    let path = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fontconfig/fonts.conf"));
+    load_fontconfig_path(&mut db, &mut config, path);
+
+    // The first installed family of each alias
+    assert_eq!(db.family_name(&Family::SansSerif), "DejaVu Sans");
+    assert_eq!(db.family_name(&Family::Monospace), "DejaVu Sans Mono");
+    assert_eq!(
+        config.common_families("en-US", &[]),
+        ["Missing Sans", "DejaVu Sans", "DejaVu Sans Mono"]
+    );
+
+    // Language matches, with families that are not installed skipped
+    assert_eq!(config.script_families(Script::Hangul, "ko-KR", &[]), ["Test CJK"]);
+    assert_eq!(config.script_families(Script::Hangul, "ja", &[]), Vec::<&str>::new());
+    assert_eq!(config.script_families(Script::Hebrew, "en-US", &[]), ["DejaVu Sans"]);
+
+    // Rejected patterns and globs
+    assert!(config.is_forbidden("Rejected Sans"));
+    assert!(db.faces().iter().all(|face| face.family != "Test Color"));
+    assert!(db.faces().iter().any(|face| face.family == "Test CJK"));
+}
@@ -1,9 +1,23 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+};
 use unicode_script::Script;
 
-use crate::Font;
+use crate::{Attrs, Family, Font, Stretch, Style, Weight};
+
+pub use self::config::*;
+mod config;
+
+pub use self::emoji::*;
+mod emoji;
+
+#[cfg(all(target_os = "linux", feature = "fontconfig"))]
+pub use self::fontconfig::*;
+#[cfg(all(target_os = "linux", feature = "fontconfig"))]
+mod fontconfig;
 
 use self::platform::*;
 
@@ -27,16 +41,80 @@ mod platform;
 #[path = "windows.rs"]
 mod platform;
 
+// Normalize a locale like `ja-JP` or `zh-Hant-TW` to the keys used for Han unification
+fn han_unification_locale(locale: &str) -> &'static str {
+    let mut subtags = locale.split(['-', '_']);
+    let language = subtags.next().unwrap_or("");
+    let subtags: Vec<&str> = subtags.collect();
+    let has_subtag = |tag: &str| subtags.iter().any(|subtag| subtag.eq_ignore_ascii_case(tag));
+    if language.eq_ignore_ascii_case("ja") {
+        "ja"
+    } else if language.eq_ignore_ascii_case("ko") {
+        "ko"
+    } else if language.eq_ignore_ascii_case("zh") && (has_subtag("HK") || has_subtag("MO")) {
+        "zh-HK"
+    } else if language.eq_ignore_ascii_case("zh") && (has_subtag("TW") || has_subtag("Hant")) {
+        "zh-TW"
+    } else {
+        // Simplified Chinese is the default
+        "zh-CN"
+    }
+}
+
+/// Key for memoized fallback decisions
+///
+/// Only the attributes that affect font matching are part of the key, so that spans differing
+/// only in color or decoration share decisions
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct FontFallbackKey<'a> {
+    pub family: Family<'a>,
+    pub monospaced: bool,
+    pub stretch: Stretch,
+    pub style: Style,
+    pub weight: Weight,
+    pub scripts: Vec<Script>,
+    pub locale: &'a str,
+}
+
+impl<'a> FontFallbackKey<'a> {
+    pub fn new(attrs: &Attrs<'a>, scripts: Vec<Script>, locale: &'a str) -> Self {
+        Self {
+            family: attrs.family,
+            monospaced: attrs.monospaced,
+            stretch: attrs.stretch,
+            style: attrs.style,
+            weight: attrs.weight,
+            scripts,
+            locale,
+        }
+    }
+}
+
+/// Maximum number of keys in [FontFallbackCache], it is cleared when full
+pub const FONT_FALLBACK_CACHE_KEYS: usize = 256;
+
+/// Memoized fallback decisions, the font selected for each character and emoji presentation
+pub type FontFallbackCache<'a> = HashMap<
+    FontFallbackKey<'a>,
+    HashMap<(char, Option<EmojiPresentation>), Option<fontdb::ID>>
+>;
+
 pub struct FontFallbackIter<'a> {
     fonts: &'a [Arc<Font<'a>>],
+    preferred_families: Vec<&'a str>,
+    preferred_i: usize,
     default_families: &'a [&'a str],
     default_i: usize,
     scripts: Vec<Script>,
     locale: &'a str,
+    config: &'a FontFallbackConfig,
+    script_families: Vec<(Script, Vec<&'a str>)>,
     script_i: (usize, usize),
+    common_families: Vec<&'a str>,
     common_i: usize,
     other_i: usize,
     end: bool,
+    seen: Vec<&'a Arc<Font<'a>>>,
 }
 
 impl<'a> FontFallbackIter<'a> {
@@ -44,18 +122,87 @@ impl<'a> FontFallbackIter<'a> {
         fonts: &'a [Arc<Font<'a>>],
         default_families: &'a [&'a str],
         scripts: Vec<Script>,
-        locale: &'a str
+        locale: &'a str,
+        config: &'a FontFallbackConfig,
     ) -> Self {
+        let script_families = scripts.iter().map(|script| (
+            *script,
+            config.script_families(
+                *script,
+                locale,
+                script_fallback(script, han_unification_locale(locale))
+            )
+        )).collect();
+        // Only the first script is the script of the text, the others share its fallbacks
+        let preferred_families = scripts.first()
+            .map_or_else(Vec::new, |script| config.preferred_families(*script, locale));
+        let common_families = config.common_families(locale, common_fallback());
         Self {
             fonts,
+            preferred_families,
+            preferred_i: 0,
             default_families,
             default_i: 0,
             scripts,
             locale,
+            config,
+            script_families,
             script_i: (0, 0),
+            common_families,
             common_i: 0,
             other_i: 0,
             end: false,
+            seen: Vec::new(),
+        }
+    }
+
+    /// Find the first fallback font that covers all characters
+    ///
+    /// Fonts are evaluated once and in fallback order, previously returned fonts are checked
+    /// again without advancing the iterator
+    pub fn find(&mut self, chars: &[char]) -> Option<&'a Arc<Font<'a>>> {
+        self.find_by(chars, |_| true)
+    }
+
+    /// Find the first fallback font that covers all characters of an emoji presentation
+    ///
+    /// Fonts with color glyphs are preferred for emoji presentation, and fonts without color
+    /// glyphs for text presentation. Any covering font is used if none is preferred.
+    pub fn find_presentation(
+        &mut self,
+        chars: &[char],
+        presentation_opt: Option<EmojiPresentation>
+    ) -> Option<&'a Arc<Font<'a>>> {
+        let color = match presentation_opt {
+            Some(EmojiPresentation::Emoji) => true,
+            Some(EmojiPresentation::Text) => false,
+            None => return self.find(chars),
+        };
+        self.find_by(chars, |font| font.is_color() == color)
+            .or_else(|| self.find(chars))
+    }
+
+    fn find_by<F: Fn(&Font) -> bool>(&mut self, chars: &[char], f: F) -> Option<&'a Arc<Font<'a>>> {
+        let mut i = 0;
+        loop {
+            if i >= self.seen.len() && self.next().is_none() {
+                return None;
+            }
+
+            let font = self.seen[i];
+            if f(font) && font.coverage().contains_all(chars) {
+                return Some(font);
+            }
+            i += 1;
+        }
+    }
+
+    /// Get the first font in fallback order, used when no font covers a character
+    pub fn first(&mut self) -> Option<&'a Arc<Font<'a>>> {
+        if self.seen.is_empty() {
+            self.next()
+        } else {
+            Some(self.seen[0])
         }
     }
 
@@ -77,7 +224,7 @@ impl<'a> FontFallbackIter<'a> {
                 word
             );
         } else if ! self.scripts.is_empty() && self.common_i > 0 {
-            let family = common_fallback()[self.common_i - 1];
+            let family = self.common_families[self.common_i - 1];
             log::debug!(
                 "Failed to find script fallback for {:?} locale '{}', used '{}': '{}'",
                 self.scripts,
@@ -89,12 +236,28 @@ impl<'a> FontFallbackIter<'a> {
     }
 }
 
-impl<'a> Iterator for FontFallbackIter<'a> {
-    type Item = &'a Arc<Font<'a>>;
-    fn next(&mut self) -> Option<Self::Item> {
+impl<'a> FontFallbackIter<'a> {
+    fn next_candidate(&mut self) -> Option<&'a Arc<Font<'a>>> {
+        // Families prepended for a script come before the requested family
+        while self.preferred_i < self.preferred_families.len() {
+            let preferred_family = self.preferred_families[self.preferred_i];
+            self.preferred_i += 1;
+            if self.config.is_forbidden(preferred_family) {
+                continue;
+            }
+            for font in self.fonts.iter() {
+                if font.info.family == preferred_family {
+                    return Some(font);
+                }
+            }
+        }
+
         while self.default_i < self.default_families.len() {
             let default_family = self.default_families[self.default_i];
             self.default_i += 1;
+            if self.config.is_forbidden(default_family) {
+                continue;
+            }
 
             for font in self.fonts.iter() {
                 if font.info.family == default_family {
@@ -103,13 +266,15 @@ impl<'a> Iterator for FontFallbackIter<'a> {
             }
         }
 
-        while self.script_i.0 < self.scripts.len() {
-            let script = self.scripts[self.script_i.0];
+        while self.script_i.0 < self.script_families.len() {
+            let (script, script_families) = &self.script_families[self.script_i.0];
 
-            let script_families = script_fallback(&script, self.locale);
             while self.script_i.1 < script_families.len() {
                 let script_family = script_families[self.script_i.1];
                 self.script_i.1 += 1;
+                if self.config.is_forbidden(script_family) {
+                    continue;
+                }
                 for font in self.fonts.iter() {
                     if font.info.family == script_family {
                         return Some(font);
@@ -122,10 +287,12 @@ impl<'a> Iterator for FontFallbackIter<'a> {
             self.script_i.1 = 0;
         }
 
-        let common_families = common_fallback();
-        while self.common_i < common_families.len() {
-            let common_family = common_families[self.common_i];
+        while self.common_i < self.common_families.len() {
+            let common_family = self.common_families[self.common_i];
             self.common_i += 1;
+            if self.config.is_forbidden(common_family) {
+                continue;
+            }
             for font in self.fonts.iter() {
                 if font.info.family == common_family {
                     return Some(font);
@@ -135,12 +302,12 @@ impl<'a> Iterator for FontFallbackIter<'a> {
         }
 
         //TODO: do we need to do this?
-        //TODO: do not evaluate fonts more than once!
         let forbidden_families = forbidden_fallback();
         while self.other_i < self.fonts.len() {
             let font = &self.fonts[self.other_i];
             self.other_i += 1;
-            if ! forbidden_families.contains(&font.info.family.as_str()) {
+            if ! forbidden_families.contains(&font.info.family.as_str())
+            && ! self.config.is_forbidden(&font.info.family) {
                 return Some(font);
             }
         }
@@ -149,3 +316,27 @@ impl<'a> Iterator for FontFallbackIter<'a> {
         None
     }
 }
+
+impl<'a> Iterator for FontFallbackIter<'a> {
+    type Item = &'a Arc<Font<'a>>;
+    fn next(&mut self) -> Option<Self::Item> {
+        // Do not evaluate fonts more than once
+        while let Some(font) = self.next_candidate() {
+            if !self.seen.iter().any(|seen| seen.info.id == font.info.id) {
+                self.seen.push(font);
+                return Some(font);
+            }
+        }
+        None
+    }
+}
+
+#[test]
+fn test_han_unification_locale() {
+    assert_eq!(han_unification_locale("ja-JP"), "ja");
+    assert_eq!(han_unification_locale("ko_KR"), "ko");
+    assert_eq!(han_unification_locale("zh-HK"), "zh-HK");
+    assert_eq!(han_unification_locale("zh-Hant-TW"), "zh-TW");
+    assert_eq!(han_unification_locale("zh-Hans-CN"), "zh-CN");
+    assert_eq!(han_unification_locale("en-US"), "zh-CN");
+}
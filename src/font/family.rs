@@ -0,0 +1,177 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use unicode_script::Script;
+
+use crate::{Stretch, Style, Weight};
+
+/// A variation axis of a variable font face
+#[derive(Clone, Debug, PartialEq)]
+pub struct FontAxis {
+    /// Axis tag, like `wght` or `wdth`
+    pub tag: String,
+    /// Minimum value
+    pub min: f32,
+    /// Default value
+    pub default: f32,
+    /// Maximum value
+    pub max: f32,
+    /// True if the axis should not be shown in user interfaces
+    pub hidden: bool,
+}
+
+/// A face of a font family, see [FontFamily]
+#[derive(Clone, Debug)]
+pub struct FontFace {
+    /// Face ID, for use with [crate::FontSystem::get_font]
+    pub id: fontdb::ID,
+    /// PostScript name
+    pub post_script_name: String,
+    /// Index of the face in a font collection (TTC), zero for other fonts
+    pub index: u32,
+    /// Name of the named instance, for faces of variable fonts, like `Display SemiBold`
+    pub instance_opt: Option<String>,
+    /// [Style] of the face
+    pub style: Style,
+    /// [Weight] of the face
+    pub weight: Weight,
+    /// [Stretch] of the face
+    pub stretch: Stretch,
+    /// True if all glyphs have the same advance
+    pub monospaced: bool,
+    /// Variation axes, empty for static fonts
+    pub axes: Vec<FontAxis>,
+}
+
+/// A font family and its faces, for font pickers
+#[derive(Clone, Debug)]
+pub struct FontFamily {
+    /// Family name, for use with [crate::Family::Name]
+    pub name: String,
+    /// Family name localized to the [crate::FontSystem] locale, for display
+    pub display_name: String,
+    /// Faces of the family
+    pub faces: Vec<FontFace>,
+    /// Scripts supported by the regular face of the family
+    pub scripts: Vec<Script>,
+}
+
+impl FontFamily {
+    /// Check if all faces are monospaced
+    pub fn monospaced(&self) -> bool {
+        self.faces.iter().all(|face| face.monospaced)
+    }
+
+    /// Check if any face is variable
+    pub fn variable(&self) -> bool {
+        self.faces.iter().any(|face| !face.axes.is_empty())
+    }
+
+    /// Available weights, sorted
+    pub fn weights(&self) -> Vec<Weight> {
+        let mut weights: Vec<Weight> = self.faces.iter().map(|face| face.weight).collect();
+        weights.sort_by_key(|weight| weight.0);
+        weights.dedup();
+        weights
+    }
+
+    /// Available styles
+    pub fn styles(&self) -> Vec<Style> {
+        let mut styles = Vec::new();
+        for face in self.faces.iter() {
+            if !styles.contains(&face.style) {
+                styles.push(face.style);
+            }
+        }
+        styles
+    }
+
+    /// Available stretches, sorted
+    pub fn stretches(&self) -> Vec<Stretch> {
+        let mut stretches: Vec<Stretch> = self.faces.iter().map(|face| face.stretch).collect();
+        stretches.sort_by_key(|stretch| stretch.to_number());
+        stretches.dedup();
+        stretches
+    }
+
+    /// Check if a script is supported
+    pub fn supports_script(&self, script: Script) -> bool {
+        self.scripts.contains(&script)
+    }
+}
+
+// Windows language IDs for common locales, see
+// https://docs.microsoft.com/en-us/typography/opentype/spec/name#windows-language-ids
+fn windows_language_id(locale: &str) -> Option<u16> {
+    let mut subtags = locale.split(['-', '_']);
+    let language = subtags.next()?.to_lowercase();
+    let region = subtags.next_back().map(|region| region.to_uppercase());
+    Some(match (language.as_str(), region.as_deref()) {
+        ("zh", Some("TW")) | ("zh", Some("HANT")) => 0x0404,
+        ("zh", Some("HK")) => 0x0C04,
+        ("zh", Some("MO")) => 0x1404,
+        ("zh", Some("SG")) => 0x1004,
+        ("zh", _) => 0x0804,
+        ("pt", Some("PT")) => 0x0816,
+        ("pt", _) => 0x0416,
+        ("ar", _) => 0x0401,
+        ("cs", _) => 0x0405,
+        ("da", _) => 0x0406,
+        ("de", _) => 0x0407,
+        ("el", _) => 0x0408,
+        ("en", _) => 0x0409,
+        ("es", _) => 0x0C0A,
+        ("fi", _) => 0x040B,
+        ("fr", _) => 0x040C,
+        ("he", _) => 0x040D,
+        ("hi", _) => 0x0439,
+        ("hu", _) => 0x040E,
+        ("it", _) => 0x0410,
+        ("ja", _) => 0x0411,
+        ("ko", _) => 0x0412,
+        ("nb", _) | ("no", _) => 0x0414,
+        ("nl", _) => 0x0413,
+        ("pl", _) => 0x0415,
+        ("ru", _) => 0x0419,
+        ("sv", _) => 0x041D,
+        ("th", _) => 0x041E,
+        ("tr", _) => 0x041F,
+        ("uk", _) => 0x0422,
+        ("vi", _) => 0x042A,
+        _ => return None,
+    })
+}
+
+/// Get the family name of a face localized to a locale, if the face provides one
+pub(crate) fn localized_family_name(face: &ttf_parser::Face, locale: &str) -> Option<String> {
+    // Family names are grouped by this name ID, so display names must use it too
+    const FAMILY: u16 = 1;
+
+    let language_id = windows_language_id(locale)?;
+
+    // Prefer an exact match, then a match of the primary language
+    let mut best: Option<(bool, String)> = None;
+    for name in face.names() {
+        if name.name_id != FAMILY || name.language_id & 0x3FF != language_id & 0x3FF {
+            continue;
+        }
+
+        let exact = name.language_id == language_id;
+        if best.as_ref().is_none_or(|(best_exact, _)| exact && !best_exact) {
+            if let Some(string) = name.to_string() {
+                best = Some((exact, string));
+            }
+        }
+    }
+    best.map(|(_, string)| string)
+}
+
+/// Get the variation axes of a face
+pub(crate) fn face_axes(face: &ttf_parser::Face) -> Vec<FontAxis> {
+    face.variation_axes().into_iter().map(|axis| FontAxis {
+        tag: axis.tag.to_string(),
+        min: axis.min_value,
+        default: axis.def_value,
+        max: axis.max_value,
+        hidden: axis.hidden,
+    }).collect()
+}
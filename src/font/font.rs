@@ -1,17 +1,28 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
-use std::ops::Deref;
+use std::{
+    ops::Deref,
+    sync::{Arc, Mutex},
+};
 
+use crate::{FontCoverage, FontInstance, FontMetrics};
+use crate::fallback::has_color_glyphs;
+
+/// A font face loaded for shaping and rendering
 pub struct Font<'a> {
     pub info: &'a fontdb::FaceInfo,
+    /// Named instance of a variable font, if this face is one
+    pub instance_opt: Option<&'a FontInstance>,
     pub data: &'a [u8],
     pub rustybuzz: rustybuzz::Face<'a>,
     #[cfg(feature = "swash")]
     pub swash: (u32, swash::CacheKey),
+    coverage: Mutex<Option<Arc<FontCoverage>>>,
+    color: bool,
 }
 
 impl<'a> Font<'a> {
-    pub fn new(info: &'a fontdb::FaceInfo) -> Option<Self> {
+    pub fn new(info: &'a fontdb::FaceInfo, instance_opt: Option<&'a FontInstance>) -> Option<Self> {
         let data = match &info.source {
             fontdb::Source::Binary(data) => data.deref().as_ref(),
             fontdb::Source::File(path) => {
@@ -21,18 +32,60 @@ impl<'a> Font<'a> {
             fontdb::Source::SharedFile(_path, data) => data.deref().as_ref(),
         };
 
+        let mut rustybuzz = rustybuzz::Face::from_slice(data, info.index)?;
+        if let Some(instance) = instance_opt {
+            rustybuzz.set_variations(&instance.variations);
+        }
+
         Some(Self {
             info,
+            instance_opt,
             data,
-            rustybuzz: rustybuzz::Face::from_slice(data, info.index)?,
+            rustybuzz,
             #[cfg(feature = "swash")]
             swash: {
                 let swash = swash::FontRef::from_index(data, info.index as usize)?;
                 (swash.offset, swash.key)
             },
+            coverage: Mutex::new(None),
+            color: has_color_glyphs(data, info.index),
         })
     }
 
+    /// Get metrics of this font, scaled to a font size in pixels
+    pub fn metrics(&self, font_size: i32) -> FontMetrics {
+        FontMetrics::new(&self.rustybuzz, font_size)
+    }
+
+    /// Get the Unicode coverage of this font, built on first use
+    pub fn coverage(&self) -> Arc<FontCoverage> {
+        let mut coverage = self.coverage.lock().unwrap();
+        coverage.get_or_insert_with(|| {
+            let now = std::time::Instant::now();
+
+            let font_coverage = Arc::new(FontCoverage::new(&self.rustybuzz));
+
+            log::debug!(
+                "coverage of '{}' ({} code points) in {:?}",
+                self.info.post_script_name,
+                font_coverage.len(),
+                now.elapsed()
+            );
+
+            font_coverage
+        }).clone()
+    }
+
+    /// Check if this font has color glyphs, like color emoji fonts
+    pub fn is_color(&self) -> bool {
+        self.color
+    }
+
+    /// Set coverage that was built previously, like from a font index
+    pub(crate) fn set_coverage(&self, coverage: Arc<FontCoverage>) {
+        *self.coverage.lock().unwrap() = Some(coverage);
+    }
+
     #[cfg(feature = "swash")]
     pub fn as_swash(&self) -> swash::FontRef {
         swash::FontRef {
@@ -0,0 +1,483 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use crate::{FontCoverage, FontInstance, Stretch, Style, Weight};
+use crate::fallback::has_color_glyphs;
+use super::instance::add_named_instances;
+use super::system::{font_file_source, is_font_file, visit_font_dir};
+
+// Changing the format requires changing this header, so old files are ignored
+const INDEX_HEADER: &str = "cosmic-text font index 3";
+
+/// Faces loaded using a font index, with their coverage, named instances, and color glyphs
+///
+/// Variable faces are already replaced by their named instances, so they are not parsed again.
+#[derive(Default)]
+pub(crate) struct IndexedFaces {
+    pub(crate) coverages: HashMap<fontdb::ID, Arc<FontCoverage>>,
+    pub(crate) instances: HashMap<fontdb::ID, FontInstance>,
+    pub(crate) color_faces: HashSet<fontdb::ID>,
+}
+
+// Parsed metadata of a face
+struct IndexFace {
+    index: u32,
+    family: String,
+    post_script_name: String,
+    style: Style,
+    weight: Weight,
+    stretch: Stretch,
+    monospaced: bool,
+    color: bool,
+    coverage: Arc<FontCoverage>,
+    instance_opt: Option<FontInstance>,
+}
+
+// Parsed faces of a font file, valid while the file is not modified
+struct IndexFile {
+    modified: Duration,
+    len: u64,
+    faces: Vec<IndexFace>,
+}
+
+fn escape(string: &str) -> String {
+    string.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
+
+fn unescape(string: &str) -> String {
+    let mut unescaped = String::with_capacity(string.len());
+    let mut chars = string.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('t') => unescaped.push('\t'),
+                Some('n') => unescaped.push('\n'),
+                Some(other) => unescaped.push(other),
+                None => (),
+            }
+        } else {
+            unescaped.push(c);
+        }
+    }
+    unescaped
+}
+
+fn style_name(style: Style) -> &'static str {
+    match style {
+        Style::Normal => "normal",
+        Style::Italic => "italic",
+        Style::Oblique => "oblique",
+    }
+}
+
+fn parse_style(name: &str) -> Option<Style> {
+    match name {
+        "normal" => Some(Style::Normal),
+        "italic" => Some(Style::Italic),
+        "oblique" => Some(Style::Oblique),
+        _ => None,
+    }
+}
+
+fn parse_stretch(number: u16) -> Option<Stretch> {
+    Some(match number {
+        1 => Stretch::UltraCondensed,
+        2 => Stretch::ExtraCondensed,
+        3 => Stretch::Condensed,
+        4 => Stretch::SemiCondensed,
+        5 => Stretch::Normal,
+        6 => Stretch::SemiExpanded,
+        7 => Stretch::Expanded,
+        8 => Stretch::ExtraExpanded,
+        9 => Stretch::UltraExpanded,
+        _ => return None,
+    })
+}
+
+fn format_ranges(coverage: &FontCoverage) -> String {
+    let ranges: Vec<String> = coverage.ranges().iter()
+        .map(|(start, end)| format!("{:x}-{:x}", start, end))
+        .collect();
+    ranges.join(",")
+}
+
+fn parse_ranges(string: &str) -> Option<FontCoverage> {
+    let mut ranges = Vec::new();
+    for range in string.split(',').filter(|range| !range.is_empty()) {
+        let (start, end) = range.split_once('-')?;
+        ranges.push((u32::from_str_radix(start, 16).ok()?, u32::from_str_radix(end, 16).ok()?));
+    }
+    Some(FontCoverage::from_ranges(ranges))
+}
+
+fn format_variations(variations: &[rustybuzz::Variation]) -> String {
+    let variations: Vec<String> = variations.iter()
+        .map(|variation| format!("{}={}", variation.tag, variation.value))
+        .collect();
+    variations.join(",")
+}
+
+fn parse_variations(string: &str) -> Option<Vec<rustybuzz::Variation>> {
+    let mut variations = Vec::new();
+    for variation in string.split(',').filter(|variation| !variation.is_empty()) {
+        let (tag, value) = variation.split_once('=')?;
+        variations.push(rustybuzz::Variation {
+            tag: ttf_parser::Tag::from_bytes_lossy(tag.as_bytes()),
+            value: value.parse().ok()?,
+        });
+    }
+    Some(variations)
+}
+
+fn parse_face(fields: &[&str]) -> Option<IndexFace> {
+    if fields.len() != 11 {
+        return None;
+    }
+    // Static faces have no instance name
+    let instance_opt = if fields[9].is_empty() {
+        None
+    } else {
+        Some(FontInstance {
+            name: unescape(fields[9]),
+            variations: parse_variations(fields[10])?,
+        })
+    };
+    Some(IndexFace {
+        index: fields[0].parse().ok()?,
+        family: unescape(fields[1]),
+        post_script_name: unescape(fields[2]),
+        style: parse_style(fields[3])?,
+        weight: Weight(fields[4].parse().ok()?),
+        stretch: parse_stretch(fields[5].parse().ok()?)?,
+        monospaced: fields[6] == "1",
+        color: fields[7] == "1",
+        coverage: Arc::new(parse_ranges(fields[8])?),
+        instance_opt,
+    })
+}
+
+// Read an index file, any error discards the whole index
+fn read_index(path: &Path) -> Option<HashMap<PathBuf, IndexFile>> {
+    let data = fs::read_to_string(path).ok()?;
+    let mut lines = data.lines();
+    if lines.next()? != INDEX_HEADER {
+        log::info!("ignoring font index '{}' with unknown format", path.display());
+        return None;
+    }
+
+    let mut files = HashMap::new();
+    let mut file_opt: Option<(PathBuf, IndexFile)> = None;
+    for line in lines {
+        let fields: Vec<&str> = line.split('\t').collect();
+        match fields[0] {
+            "file" if fields.len() == 5 => {
+                if let Some((file_path, file)) = file_opt.take() {
+                    files.insert(file_path, file);
+                }
+                file_opt = Some((PathBuf::from(unescape(fields[1])), IndexFile {
+                    modified: Duration::new(fields[2].parse().ok()?, fields[3].parse().ok()?),
+                    len: fields[4].parse().ok()?,
+                    faces: Vec::new(),
+                }));
+            },
+            "face" => {
+                let (_, file) = file_opt.as_mut()?;
+                file.faces.push(parse_face(&fields[1..])?);
+            },
+            _ => {
+                log::warn!("ignoring invalid font index '{}'", path.display());
+                return None;
+            }
+        }
+    }
+    if let Some((file_path, file)) = file_opt.take() {
+        files.insert(file_path, file);
+    }
+    Some(files)
+}
+
+fn write_index(path: &Path, files: &[(PathBuf, IndexFile)]) -> std::io::Result<()> {
+    let mut data = String::new();
+    data.push_str(INDEX_HEADER);
+    data.push('\n');
+    for (file_path, file) in files.iter() {
+        let file_path = match file_path.to_str() {
+            Some(some) => some,
+            None => continue,
+        };
+        data.push_str(&format!(
+            "file\t{}\t{}\t{}\t{}\n",
+            escape(file_path),
+            file.modified.as_secs(),
+            file.modified.subsec_nanos(),
+            file.len
+        ));
+        for face in file.faces.iter() {
+            let (instance_name, variations) = match &face.instance_opt {
+                Some(instance) => (escape(&instance.name), format_variations(&instance.variations)),
+                None => (String::new(), String::new()),
+            };
+            data.push_str(&format!(
+                "face\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                face.index,
+                escape(&face.family),
+                escape(&face.post_script_name),
+                style_name(face.style),
+                face.weight.0,
+                face.stretch.to_number(),
+                if face.monospaced { 1 } else { 0 },
+                if face.color { 1 } else { 0 },
+                format_ranges(&face.coverage),
+                instance_name,
+                variations
+            ));
+        }
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // Write to a temporary file first, so that readers never see a partial index
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, data)?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Load fonts of font directories using an index file of previously parsed fonts
+///
+/// Font files that were not modified since they were indexed are added without parsing them,
+/// other font files are parsed and the index file is updated.
+pub(crate) fn load_fonts_indexed(
+    db: &mut fontdb::Database,
+    index_path: &Path,
+    dirs: &[PathBuf],
+) -> IndexedFaces {
+    let mut old_files = read_index(index_path).unwrap_or_default();
+    let mut files = Vec::new();
+    let mut indexed = IndexedFaces::default();
+    let mut parsed = 0;
+
+    let mut paths = Vec::new();
+    for dir in dirs.iter() {
+        visit_font_dir(dir, &mut |path, metadata| {
+            if metadata.is_file() && is_font_file(path) {
+                paths.push((path.to_path_buf(), metadata.clone()));
+            }
+        });
+    }
+
+    for (path, metadata) in paths {
+        let modified = metadata.modified().ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        let len = metadata.len();
+
+        match old_files.remove(&path) {
+            Some(file) if file.modified == modified && file.len == len => {
+                let source = match font_file_source(&path) {
+                    Ok(ok) => ok,
+                    Err(err) => {
+                        log::warn!("failed to load '{}': {}", path.display(), err);
+                        continue;
+                    }
+                };
+                for face in file.faces.iter() {
+                    db.push_face_info(fontdb::FaceInfo {
+                        id: fontdb::ID::dummy(),
+                        source: source.clone(),
+                        index: face.index,
+                        family: face.family.clone(),
+                        post_script_name: face.post_script_name.clone(),
+                        style: face.style,
+                        weight: face.weight,
+                        stretch: face.stretch,
+                        monospaced: face.monospaced,
+                    });
+                    let id = db.faces()[db.len() - 1].id;
+                    indexed.coverages.insert(id, face.coverage.clone());
+                    if face.color {
+                        indexed.color_faces.insert(id);
+                    }
+                    if let Some(instance) = &face.instance_opt {
+                        indexed.instances.insert(id, instance.clone());
+                    }
+                }
+                files.push((path, file));
+            },
+            _ => {
+                parsed += 1;
+                let start = db.len();
+                match font_file_source(&path) {
+                    Ok(source) => db.load_font_source(source),
+                    Err(err) => log::warn!("failed to load '{}': {}", path.display(), err),
+                }
+
+                let mut faces = Vec::new();
+                let infos = db.faces()[start..].to_vec();
+                for info in infos {
+                    let (coverage_opt, color) = db.with_face_data(info.id, |data, index| {
+                        let coverage_opt = ttf_parser::Face::from_slice(data, index).ok()
+                            .map(|face| FontCoverage::new(&face));
+                        (coverage_opt, has_color_glyphs(data, index))
+                    }).unwrap_or((None, false));
+                    let coverage = Arc::new(coverage_opt.unwrap_or_else(|| FontCoverage::from_ranges(Vec::new())));
+
+                    // Store named instances instead of variable faces, like the font system lists them
+                    let mut instances: Vec<_> = add_named_instances(db, &info).into_iter()
+                        .map(|(instance_info, instance)| (instance_info, Some(instance)))
+                        .collect();
+                    if instances.is_empty() {
+                        instances.push((info, None));
+                    }
+
+                    for (info, instance_opt) in instances {
+                        indexed.coverages.insert(info.id, coverage.clone());
+                        if color {
+                            indexed.color_faces.insert(info.id);
+                        }
+                        if let Some(instance) = &instance_opt {
+                            indexed.instances.insert(info.id, instance.clone());
+                        }
+                        faces.push(IndexFace {
+                            index: info.index,
+                            family: info.family.clone(),
+                            post_script_name: info.post_script_name.clone(),
+                            style: info.style,
+                            weight: info.weight,
+                            stretch: info.stretch,
+                            monospaced: info.monospaced,
+                            color,
+                            coverage: coverage.clone(),
+                            instance_opt,
+                        });
+                    }
+                }
+                files.push((path, IndexFile { modified, len, faces }));
+            }
+        }
+    }
+
+    // Files that were parsed or removed change the index
+    if parsed > 0 || !old_files.is_empty() {
+        match write_index(index_path, &files) {
+            Ok(()) => log::info!(
+                "Updated font index '{}', parsed {} of {} font files.",
+                index_path.display(),
+                parsed,
+                files.len()
+            ),
+            Err(err) => log::warn!("failed to write font index '{}': {}", index_path.display(), err),
+        }
+    }
+
+    indexed
+}
+
+/// Default location of the font index file, in the user's cache directory
+pub(crate) fn default_index_path() -> Option<PathBuf> {
+    #[cfg(target_os = "windows")]
+    let cache_dir = std::env::var_os("LOCALAPPDATA").map(PathBuf::from);
+
+    #[cfg(target_os = "macos")]
+    let cache_dir = std::env::var_os("HOME").map(|home| Path::new(&home).join("Library/Caches"));
+
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    let cache_dir = std::env::var_os("XDG_CACHE_HOME").map(PathBuf::from).or_else(|| {
+        std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache"))
+    });
+
+    cache_dir.map(|cache_dir| cache_dir.join("cosmic-text").join("font-index"))
+}
+
+#[test]
+fn test_font_index_escape() {
+    let string = "Family\tName\\With\nEscapes";
+    assert_eq!(unescape(&escape(string)), string);
+    assert!(!escape(string).contains('\t'));
+
+    let coverage = FontCoverage::from_ranges(vec![(0x20, 0x7E), (0xA0, 0xFF)]);
+    let parsed = parse_ranges(&format_ranges(&coverage)).unwrap();
+    assert_eq!(parsed.ranges(), coverage.ranges());
+    assert!(parse_ranges("").unwrap().is_empty());
+    assert!(parse_ranges("20").is_none());
+
+    let variations = vec![
+        rustybuzz::Variation { tag: ttf_parser::Tag::from_bytes(b"wght"), value: 650.0 },
+        rustybuzz::Variation { tag: ttf_parser::Tag::from_bytes(b"wdth"), value: 87.5 },
+    ];
+    let parsed = parse_variations(&format_variations(&variations)).unwrap();
+    assert_eq!(parsed.len(), 2);
+    assert_eq!(parsed[0].tag, variations[0].tag);
+    assert_eq!(parsed[1].value, 87.5);
+    assert!(parse_variations("wght").is_none());
+}
+
+#[cfg(feature = "test-fonts")]
+#[test]
+fn test_font_index_round_trip() {
+    let dirs = [PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/fonts"))];
+    let index_dir = std::env::temp_dir().join(format!("cosmic-text-test-{}", std::process::id()));
+    let index_path = index_dir.join("font-index");
+
+    let faces = |db: &fontdb::Database, indexed: &IndexedFaces| {
+        let mut faces: Vec<_> = db.faces().iter().map(|face| (
+            face.post_script_name.clone(),
+            face.family.clone(),
+            face.index,
+            style_name(face.style),
+            face.weight.0,
+            face.stretch.to_number(),
+            face.monospaced,
+            indexed.color_faces.contains(&face.id),
+            indexed.coverages[&face.id].ranges().to_vec(),
+        )).collect();
+        faces.sort();
+        faces
+    };
+
+    // The first load parses all fonts and writes the index
+    let mut parsed_db = fontdb::Database::new();
+    let parsed = load_fonts_indexed(&mut parsed_db, &index_path, &dirs);
+    let index = fs::read_to_string(&index_path).unwrap();
+
+    // The second load restores all fonts from the index, without changing it
+    let mut indexed_db = fontdb::Database::new();
+    let indexed = load_fonts_indexed(&mut indexed_db, &index_path, &dirs);
+    assert_eq!(fs::read_to_string(&index_path).unwrap(), index);
+    fs::remove_dir_all(&index_dir).unwrap();
+
+    let parsed_faces = faces(&parsed_db, &parsed);
+    assert_eq!(parsed_faces, faces(&indexed_db, &indexed));
+
+    // WOFF and WOFF2 files are indexed with the other fonts
+    let mono_count = parsed_faces.iter()
+        .filter(|face| face.0 == "DejaVuSansMono")
+        .count();
+    assert_eq!(mono_count, if cfg!(feature = "woff") { 3 } else { 1 });
+
+    // Variable faces are replaced by their named instances, with the weight and stretch of each
+    let variable: Vec<_> = parsed_faces.iter()
+        .filter(|face| face.1 == "Test Variable")
+        .map(|face| (face.0.as_str(), face.4, face.5))
+        .collect();
+    assert_eq!(variable, [
+        ("TestVariable-Bold", 700, 5),
+        ("TestVariable-CondensedBold", 700, 3),
+        ("TestVariable-Light", 300, 5),
+        ("TestVariable-Regular", 400, 5),
+    ]);
+
+    // Restored faces can be read
+    for face in indexed_db.faces() {
+        assert!(indexed_db.with_face_data(face.id, |data, index| {
+            ttf_parser::Face::from_slice(data, index).is_ok()
+        }).unwrap());
+    }
+}
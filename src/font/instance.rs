@@ -0,0 +1,201 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use ttf_parser::{RawFace, Tag};
+
+use crate::{Stretch, Style, Weight};
+
+/// A named instance of a variable font face, like `SemiBold` or `Display Bold`
+///
+/// Each named instance is listed as its own face, with the [Weight], [Stretch], and [Style] of
+/// its axis values, so it can be selected with [crate::Attrs] like a static face.
+#[derive(Clone, Debug)]
+pub struct FontInstance {
+    /// Subfamily name of the instance
+    pub name: String,
+    /// Axis values of the instance
+    pub variations: Vec<rustybuzz::Variation>,
+}
+
+// Name IDs used for named instances
+const FAMILY: u16 = 1;
+const VARIATIONS_POST_SCRIPT_NAME_PREFIX: u16 = 25;
+
+// Get an English name, or any name if there is no English name
+fn face_name(face: &ttf_parser::Face, name_id: u16) -> Option<String> {
+    let mut fallback = None;
+    for name in face.names() {
+        if name.name_id != name_id {
+            continue;
+        }
+
+        let english = match name.platform_id {
+            ttf_parser::PlatformId::Windows => name.language_id == 0x0409,
+            ttf_parser::PlatformId::Macintosh => name.language_id == 0,
+            _ => false,
+        };
+        if english {
+            if let Some(string) = name.to_string() {
+                return Some(string);
+            }
+        } else if fallback.is_none() {
+            fallback = name.to_string();
+        }
+    }
+    fallback
+}
+
+// Closest stretch to a width axis value, in percent of normal width
+fn width_stretch(width: f32) -> Stretch {
+    [
+        (Stretch::UltraCondensed, 50.0),
+        (Stretch::ExtraCondensed, 62.5),
+        (Stretch::Condensed, 75.0),
+        (Stretch::SemiCondensed, 87.5),
+        (Stretch::Normal, 100.0),
+        (Stretch::SemiExpanded, 112.5),
+        (Stretch::Expanded, 125.0),
+        (Stretch::ExtraExpanded, 150.0),
+        (Stretch::UltraExpanded, 200.0),
+    ].iter().min_by(|(_, a), (_, b)| {
+        (a - width).abs().partial_cmp(&(b - width).abs()).unwrap()
+    }).unwrap().0
+}
+
+/// Get the named instances of a variable font face, with a face info for each
+///
+/// Face infos are clones of `info` with the post script name, [Style], [Weight], and [Stretch]
+/// of the instance. This is empty for static fonts.
+fn named_instances(
+    data: &[u8],
+    info: &fontdb::FaceInfo,
+) -> Vec<(fontdb::FaceInfo, FontInstance)> {
+    let fvar = match RawFace::from_slice(data, info.index).ok()
+        .and_then(|raw| raw.table(Tag::from_bytes(b"fvar")))
+    {
+        Some(some) => some,
+        None => return Vec::new(),
+    };
+    let face = match ttf_parser::Face::from_slice(data, info.index) {
+        Ok(ok) => ok,
+        Err(_) => return Vec::new(),
+    };
+
+    let u16_at = |offset: usize| -> Option<u16> {
+        Some(u16::from_be_bytes([*fvar.get(offset)?, *fvar.get(offset + 1)?]))
+    };
+    let fixed_at = |offset: usize| -> Option<f32> {
+        let bytes = fvar.get(offset..offset + 4)?;
+        Some(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32 / 65536.0)
+    };
+
+    let axes_offset = u16_at(4).unwrap_or(0) as usize;
+    let axis_count = u16_at(8).unwrap_or(0) as usize;
+    let axis_size = u16_at(10).unwrap_or(0) as usize;
+    let instance_count = u16_at(12).unwrap_or(0) as usize;
+    let instance_size = u16_at(14).unwrap_or(0) as usize;
+    if axis_count == 0 || instance_size < 4 + 4 * axis_count {
+        return Vec::new();
+    }
+
+    let tags: Vec<Tag> = (0..axis_count).filter_map(|axis_i| {
+        let offset = axes_offset + axis_i * axis_size;
+        let bytes = fvar.get(offset..offset + 4)?;
+        Some(Tag::from_bytes(&[bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }).collect();
+    if tags.len() != axis_count {
+        return Vec::new();
+    }
+
+    let post_script_prefix = face_name(&face, VARIATIONS_POST_SCRIPT_NAME_PREFIX)
+        .or_else(|| face_name(&face, FAMILY))
+        .unwrap_or_else(|| info.family.clone())
+        .replace(' ', "");
+
+    let mut instances = Vec::with_capacity(instance_count);
+    let instances_offset = axes_offset + axis_count * axis_size;
+    for instance_i in 0..instance_count {
+        let offset = instances_offset + instance_i * instance_size;
+        let name = match u16_at(offset).and_then(|name_id| face_name(&face, name_id)) {
+            Some(some) => some,
+            None => continue,
+        };
+
+        let mut variations = Vec::with_capacity(axis_count);
+        for (axis_i, tag) in tags.iter().enumerate() {
+            match fixed_at(offset + 4 + axis_i * 4) {
+                Some(value) => variations.push(rustybuzz::Variation { tag: *tag, value }),
+                None => break,
+            }
+        }
+        if variations.len() != axis_count {
+            continue;
+        }
+
+        let post_script_name_opt = if instance_size >= 6 + 4 * axis_count {
+            u16_at(offset + 4 + 4 * axis_count)
+                .filter(|name_id| *name_id != 0xFFFF)
+                .and_then(|name_id| face_name(&face, name_id))
+        } else {
+            None
+        };
+
+        let value = |tag: &[u8; 4]| {
+            let tag = Tag::from_bytes(tag);
+            variations.iter().find(|variation| variation.tag == tag).map(|variation| variation.value)
+        };
+
+        let mut instance_info = info.clone();
+        instance_info.post_script_name = post_script_name_opt.unwrap_or_else(|| {
+            format!("{}-{}", post_script_prefix, name.replace(' ', ""))
+        });
+        if let Some(weight) = value(b"wght") {
+            instance_info.weight = Weight(weight.round().clamp(1.0, 1000.0) as u16);
+        }
+        if let Some(width) = value(b"wdth") {
+            instance_info.stretch = width_stretch(width);
+        }
+        if value(b"ital").is_some_and(|ital| ital >= 0.5) {
+            instance_info.style = Style::Italic;
+        } else if value(b"slnt").is_some_and(|slnt| slnt != 0.0) {
+            instance_info.style = Style::Oblique;
+        }
+
+        instances.push((instance_info, FontInstance { name, variations }));
+    }
+    instances
+}
+
+/// Replace a variable face in a database with a face for each of its named instances
+///
+/// Instances are added with a clone of the source of the variable face, so the font data is
+/// shared and not parsed again, and the variable face is removed, so the database has the same
+/// faces as one restored from a font index. Static faces are kept, and return no instances.
+pub(crate) fn add_named_instances(
+    db: &mut fontdb::Database,
+    info: &fontdb::FaceInfo,
+) -> Vec<(fontdb::FaceInfo, FontInstance)> {
+    let instances = db.with_face_data(info.id, |data, _index| {
+        named_instances(data, info)
+    }).unwrap_or_default();
+    if instances.is_empty() {
+        return Vec::new();
+    }
+
+    let mut added = Vec::with_capacity(instances.len());
+    for (mut instance_info, instance) in instances {
+        db.push_face_info(instance_info.clone());
+        instance_info.id = db.faces()[db.len() - 1].id;
+        added.push((instance_info, instance));
+    }
+    db.remove_face(info.id);
+    added
+}
+
+#[test]
+fn test_width_stretch() {
+    assert_eq!(width_stretch(100.0), Stretch::Normal);
+    assert_eq!(width_stretch(75.0), Stretch::Condensed);
+    assert_eq!(width_stretch(80.0), Stretch::Condensed);
+    assert_eq!(width_stretch(120.0), Stretch::Expanded);
+    assert_eq!(width_stretch(300.0), Stretch::UltraExpanded);
+}
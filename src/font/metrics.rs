@@ -0,0 +1,119 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+/// Metrics of a font, scaled to a font size in pixels
+///
+/// Positions are relative to the baseline, with positive values above it
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FontMetrics {
+    /// Font size in pixels
+    pub font_size: i32,
+    /// Distance from the baseline to the top of the tallest glyphs
+    pub ascent: f32,
+    /// Distance from the baseline to the bottom of the lowest glyphs, positive
+    pub descent: f32,
+    /// Recommended gap between the descent of a line and the ascent of the next line
+    pub line_gap: f32,
+    /// Height of capital letters
+    pub cap_height: f32,
+    /// Height of lowercase letters
+    pub x_height: f32,
+    /// Position of the top of the underline
+    pub underline_position: f32,
+    /// Thickness of the underline
+    pub underline_thickness: f32,
+    /// Position of the top of the strikeout
+    pub strikeout_position: f32,
+    /// Thickness of the strikeout
+    pub strikeout_thickness: f32,
+    /// Average width of characters
+    pub average_width: f32,
+}
+
+impl FontMetrics {
+    /// Read metrics from a face, scaled to a font size in pixels
+    pub fn new(face: &rustybuzz::Face, font_size: i32) -> Self {
+        let scale = font_size as f32 / face.units_per_em() as f32;
+
+        let ascent = face.ascender() as f32 * scale;
+        let descent = -(face.descender() as f32) * scale;
+
+        // Estimate missing metrics from the ascent, like most renderers do
+        let cap_height = match face.capital_height() {
+            Some(some) if some > 0 => some as f32 * scale,
+            _ => ascent * 0.7,
+        };
+        let x_height = match face.x_height() {
+            Some(some) if some > 0 => some as f32 * scale,
+            _ => ascent * 0.5,
+        };
+
+        let (underline_position, underline_thickness) = match face.underline_metrics() {
+            Some(some) => (some.position as f32 * scale, some.thickness as f32 * scale),
+            None => (-descent / 2.0, font_size as f32 / 14.0),
+        };
+        let (strikeout_position, strikeout_thickness) = match face.strikeout_metrics() {
+            Some(some) => (some.position as f32 * scale, some.thickness as f32 * scale),
+            None => (x_height / 2.0 + underline_thickness / 2.0, underline_thickness),
+        };
+
+        // Use xAvgCharWidth from OS/2, or the advance of 'x'
+        let average_width = face.table_data(rustybuzz::Tag::from_bytes(b"OS/2"))
+            .and_then(|os2| os2.get(2..4))
+            .map(|bytes| i16::from_be_bytes([bytes[0], bytes[1]]))
+            .filter(|width| *width > 0)
+            .map(|width| width as f32)
+            .or_else(|| {
+                let glyph_id = face.glyph_index('x')?;
+                face.glyph_hor_advance(glyph_id).map(|advance| advance as f32)
+            })
+            .map_or(font_size as f32 / 2.0, |width| width * scale);
+
+        Self {
+            font_size,
+            ascent,
+            descent,
+            line_gap: face.line_gap() as f32 * scale,
+            cap_height,
+            x_height,
+            underline_position,
+            underline_thickness,
+            strikeout_position,
+            strikeout_thickness,
+            average_width,
+        }
+    }
+
+    /// Recommended distance between baselines
+    pub fn line_height(&self) -> f32 {
+        self.ascent + self.descent + self.line_gap
+    }
+}
+
+#[cfg(feature = "test-fonts")]
+#[test]
+fn test_font_metrics() {
+    use crate::{Attrs, Family, FontSystem};
+
+    let font_system = FontSystem::new_test();
+
+    // DejaVu Sans has 2048 units per em, with an ascender of 1901 and a descender of -483
+    let metrics = font_system.get_font_metrics(Attrs::new(), 2048).unwrap();
+    assert_eq!(metrics.font_size, 2048);
+    assert_eq!(metrics.ascent, 1901.0);
+    assert_eq!(metrics.descent, 483.0);
+    assert_eq!(metrics.line_gap, 0.0);
+
+    let metrics = font_system.get_font_metrics(Attrs::new(), 16).unwrap();
+    assert_eq!(metrics.ascent, 1901.0 * 16.0 / 2048.0);
+    assert_eq!(metrics.descent, 483.0 * 16.0 / 2048.0);
+
+    // Test CJK has 1000 units per em, with an ascender of 850 and a descender of -299
+    let attrs = Attrs::new().family(Family::Name("Test CJK"));
+    let font_matches = font_system.get_font_matches(attrs);
+    let font = font_matches.fonts.iter().find(|font| font.info.family == "Test CJK").unwrap();
+    assert_eq!(font.rustybuzz.units_per_em(), 1000);
+    let metrics = font.metrics(1000);
+    assert_eq!(metrics.ascent, 850.0);
+    assert_eq!(metrics.descent, 299.0);
+    assert_eq!(font.metrics(20), font_system.get_font_metrics(attrs, 20).unwrap());
+}
@@ -1,12 +1,33 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
+pub use self::coverage::*;
+mod coverage;
+
+pub use self::fallback::{FallbackMode, FontFallbackConfig};
 pub(crate) mod fallback;
 
-pub(crate) use self::font::*;
+pub use self::family::*;
+mod family;
+
+pub use self::font::*;
 mod font;
 
+mod index;
+
+pub use self::instance::FontInstance;
+mod instance;
+
 pub use self::matches::*;
 mod matches;
 
+pub use self::metrics::*;
+mod metrics;
+
 pub use self::system::*;
 mod system;
+
+#[cfg(feature = "test-fonts")]
+mod test_fonts;
+
+#[cfg(feature = "woff")]
+mod woff;
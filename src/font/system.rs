@@ -1,38 +1,389 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
 use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
+    collections::{HashMap, HashSet},
+    fs,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::SystemTime,
 };
 
-use crate::{Attrs, Font, FontMatches};
+use unicode_script::Script;
+
+use crate::{Attrs, Font, FontCoverage, FontFace, FontFallbackConfig, FontFamily, FontInstance, FontMatches, FontMetrics, Style};
+use crate::fallback::{FontFallbackCache, has_color_glyphs};
+use crate::font::family::{face_axes, localized_family_name};
+use crate::font::index::{IndexedFaces, load_fonts_indexed};
+use crate::font::instance::add_named_instances;
+use crate::shape::fallback_ignored;
+
+// Directories searched for system fonts, see fontdb::Database::load_system_fonts
+fn font_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+
+    #[cfg(target_os = "windows")]
+    {
+        if let Some(system_root) = std::env::var_os("SYSTEMROOT") {
+            dirs.push(Path::new(&system_root).join("Fonts"));
+        } else {
+            dirs.push(PathBuf::from("C:\\Windows\\Fonts"));
+        }
+        if let Some(local_app_data) = std::env::var_os("LOCALAPPDATA") {
+            dirs.push(Path::new(&local_app_data).join("Microsoft\\Windows\\Fonts"));
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        dirs.push(PathBuf::from("/Library/Fonts"));
+        dirs.push(PathBuf::from("/System/Library/Fonts"));
+        dirs.push(PathBuf::from("/System/Library/AssetsV2/com_apple_MobileAsset_Font6"));
+        dirs.push(PathBuf::from("/Network/Library/Fonts"));
+        if let Some(home) = std::env::var_os("HOME") {
+            dirs.push(Path::new(&home).join("Library/Fonts"));
+        }
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    {
+        dirs.push(PathBuf::from("/usr/share/fonts"));
+        dirs.push(PathBuf::from("/usr/local/share/fonts"));
+        if let Some(home) = std::env::var_os("HOME") {
+            dirs.push(Path::new(&home).join(".fonts"));
+            dirs.push(Path::new(&home).join(".local/share/fonts"));
+        }
+    }
+
+    dirs
+}
+
+// Extensions of font files, as loaded by fontdb, and WOFF files when they can be decoded
+const FONT_EXTENSIONS: &[&str] = &[
+    "ttf",
+    "ttc",
+    "otf",
+    "otc",
+    #[cfg(feature = "woff")]
+    "woff",
+    #[cfg(feature = "woff")]
+    "woff2",
+];
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or("");
+    extensions.iter().any(|font| extension.eq_ignore_ascii_case(font))
+}
+
+pub(crate) fn is_font_file(path: &Path) -> bool {
+    has_extension(path, FONT_EXTENSIONS)
+}
+
+fn is_woff_file(path: &Path) -> bool {
+    has_extension(path, &["woff", "woff2"])
+}
+
+/// Visit every entry of a font directory and its subdirectories
+pub(crate) fn visit_font_dir(dir: &Path, visit: &mut dyn FnMut(&Path, &fs::Metadata)) {
+    let entries = match fs::read_dir(dir) {
+        Ok(ok) => ok,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let metadata = match fs::metadata(&path) {
+            Ok(ok) => ok,
+            Err(_) => continue,
+        };
+        visit(&path, &metadata);
+        if metadata.is_dir() {
+            visit_font_dir(&path, visit);
+        }
+    }
+}
+
+/// Get the source of a font file, decoding WOFF and WOFF2 files as fontdb only reads TrueType
+/// and OpenType files
+pub(crate) fn font_file_source(path: &Path) -> io::Result<fontdb::Source> {
+    if is_woff_file(path) {
+        let data = sfnt_data(fs::read(path)?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Ok(fontdb::Source::Binary(Arc::new(data)))
+    } else {
+        Ok(fontdb::Source::File(path.to_path_buf()))
+    }
+}
+
+// Number of entries and latest modification time in font directories
+type FontsStamp = (usize, Option<SystemTime>);
+
+fn fonts_stamp() -> FontsStamp {
+    let mut stamp = (0, None);
+    for dir in font_dirs() {
+        visit_font_dir(&dir, &mut |_path, metadata| {
+            stamp.0 += 1;
+            if let Ok(modified) = metadata.modified() {
+                if stamp.1.is_none_or(|latest| modified > latest) {
+                    stamp.1 = Some(modified);
+                }
+            }
+        });
+    }
+    stamp
+}
+
+// Convert font data to TrueType/OpenType data, decompressing WOFF and WOFF2
+fn sfnt_data(data: Vec<u8>) -> Result<Vec<u8>, &'static str> {
+    #[cfg(feature = "woff")]
+    if crate::font::woff::is_woff(&data) {
+        let now = std::time::Instant::now();
+        let sfnt = crate::font::woff::decode_woff(&data)?;
+        log::debug!("decoded {} bytes of WOFF to {} bytes in {:?}", data.len(), sfnt.len(), now.elapsed());
+        return Ok(sfnt);
+    }
+
+    Ok(data)
+}
+
+// Where fonts are loaded from, kept for reloading
+enum FontLoader {
+    // Parse system fonts
+    System,
+    // Load system fonts using a font index file
+    Index(PathBuf),
+    // Load the bundled test fonts
+    #[cfg(feature = "test-fonts")]
+    Test,
+}
+
+impl FontLoader {
+    // Test fonts do not depend on system configuration, like locale and fontconfig
+    fn is_system(&self) -> bool {
+        #[cfg(feature = "test-fonts")]
+        if let Self::Test = self {
+            return false;
+        }
+        true
+    }
+}
+
+// Font database, with a face for each named instance of variable fonts
+struct FontDatabase {
+    db: fontdb::Database,
+    faces: Vec<fontdb::FaceInfo>,
+    instances: HashMap<fontdb::ID, FontInstance>,
+    // Coverage of faces loaded from a font index
+    coverages: HashMap<fontdb::ID, Arc<FontCoverage>>,
+    // Faces with color glyphs, checked once when faces are added
+    color_faces: HashSet<fontdb::ID>,
+    // Fallback configuration, which fontconfig derives from the installed fonts
+    fallback: FontFallbackConfig,
+    // Font database of the next generation, set when fonts are reloaded
+    next: OnceLock<Box<FontDatabase>>,
+}
+
+impl FontDatabase {
+    fn new(db: fontdb::Database, indexed: IndexedFaces, fallback: FontFallbackConfig) -> Self {
+        let mut font_db = Self {
+            db,
+            faces: Vec::new(),
+            instances: indexed.instances,
+            coverages: indexed.coverages,
+            color_faces: indexed.color_faces,
+            fallback,
+            next: OnceLock::new(),
+        };
+        font_db.add_faces(0);
+        font_db
+    }
+
+    // Add faces of the database starting at an index, replacing variable faces with their
+    // named instances
+    fn add_faces(&mut self, start: usize) {
+        let now = std::time::Instant::now();
+
+        // Variable faces are removed when adding their instances, so the faces are copied first
+        let infos = self.db.faces()[start..].to_vec();
+        let mut instance_count = 0;
+        for info in infos {
+            // Faces from a font index are stored with their named instances already added
+            if self.coverages.contains_key(&info.id) {
+                self.faces.push(info);
+                continue;
+            }
+
+            let color = self.db.with_face_data(info.id, has_color_glyphs).unwrap_or(false);
+            let instances = add_named_instances(&mut self.db, &info);
+            if instances.is_empty() {
+                if color {
+                    self.color_faces.insert(info.id);
+                }
+                self.faces.push(info);
+                continue;
+            }
+
+            for (instance_info, instance) in instances {
+                if color {
+                    self.color_faces.insert(instance_info.id);
+                }
+                log::debug!("named instance '{}' of '{}'", instance.name, instance_info.post_script_name);
+                self.instances.insert(instance_info.id, instance);
+                self.faces.push(instance_info);
+                instance_count += 1;
+            }
+        }
+
+        if instance_count > 0 {
+            log::info!(
+                "Added {} named instances in {}ms.",
+                instance_count,
+                now.elapsed().as_millis()
+            );
+        }
+    }
+}
 
 /// Access system fonts
 pub struct FontSystem<'a> {
     pub locale: String,
-    pub db: fontdb::Database,
+    // Font database of the first generation, later generations are chained to it, see
+    // Self::reload
+    font_db: FontDatabase,
+    generation: AtomicUsize,
+    // State of the font directories, recorded on the first check for changes
+    fonts_stamp_opt: Mutex<Option<FontsStamp>>,
+    loader: FontLoader,
+    // Fonts loaded by the application, added again when reloading
+    font_sources: Vec<fontdb::Source>,
     pub font_cache: Mutex<HashMap<fontdb::ID, Option<Arc<Font<'a>>>>>,
     pub font_matches_cache: Mutex<HashMap<Attrs<'a>, Arc<FontMatches<'a>>>>,
+    pub(crate) font_fallback_cache: Mutex<FontFallbackCache<'a>>,
 }
 
 impl<'a> FontSystem<'a> {
     pub fn new() -> Self {
-        let locale = sys_locale::get_locale().unwrap_or_else(|| {
-            log::warn!("failed to get system locale, falling back to en-US");
+        Self::new_with_loader(FontLoader::System)
+    }
+
+    /// Create a font system, using a font index file to speed up loading system fonts
+    ///
+    /// The index stores metadata and coverage of parsed fonts. Fonts that were not modified since
+    /// they were indexed are not parsed again, and the index is updated when fonts change. Use
+    /// [Self::default_index_path] for a location in the user's cache directory.
+    pub fn new_with_index<P: Into<PathBuf>>(index_path: P) -> Self {
+        Self::new_with_loader(FontLoader::Index(index_path.into()))
+    }
+
+    /// Create a font system with only the bundled test fonts, for reproducible layout tests
+    ///
+    /// Fonts, generic families, fallback, and locale are the same on every system. The fonts
+    /// cover Latin, Arabic, Hebrew, Hangul, some Han, kana, and a few emoji, see
+    /// `fonts/README.md`.
+    #[cfg(feature = "test-fonts")]
+    pub fn new_test() -> Self {
+        Self::new_with_loader(FontLoader::Test)
+    }
+
+    /// Default location of the font index file, see [Self::new_with_index]
+    pub fn default_index_path() -> Option<PathBuf> {
+        crate::font::index::default_index_path()
+    }
+
+    fn new_with_loader(loader: FontLoader) -> Self {
+        let locale = if loader.is_system() {
+            sys_locale::get_locale().unwrap_or_else(|| {
+                log::warn!("failed to get system locale, falling back to en-US");
+                String::from("en-US")
+            })
+        } else {
             String::from("en-US")
-        });
+        };
         log::info!("Locale: {}", locale);
 
+        #[allow(unused_mut)]
+        let (mut db, indexed) = Self::load_db(&loader);
+
+        #[allow(unused_mut)]
+        let mut fallback = FontFallbackConfig::new();
+
+        #[cfg(feature = "test-fonts")]
+        if let FontLoader::Test = loader {
+            fallback = crate::font::test_fonts::test_fallback();
+        }
+
+        #[cfg(all(target_os = "linux", feature = "fontconfig"))]
+        if loader.is_system() {
+            crate::fallback::load_fontconfig(&mut db, &mut fallback);
+        }
+
+        Self {
+            locale,
+            font_db: FontDatabase::new(db, indexed, fallback),
+            generation: AtomicUsize::new(0),
+            fonts_stamp_opt: Mutex::new(None),
+            loader,
+            font_sources: Vec::new(),
+            font_cache: Mutex::new(HashMap::new()),
+            font_matches_cache: Mutex::new(HashMap::new()),
+            font_fallback_cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Font database of the current generation
+    fn font_db(&self) -> &FontDatabase {
+        let mut font_db = &self.font_db;
+        while let Some(next) = font_db.next.get() {
+            font_db = next;
+        }
+        font_db
+    }
+
+    // Font database of the current generation for changes, dropping earlier generations, which
+    // can no longer be borrowed
+    fn font_db_mut(&mut self) -> &mut FontDatabase {
+        while let Some(next) = self.font_db.next.take() {
+            self.font_db = *next;
+        }
+        &mut self.font_db
+    }
+
+    fn load_db(loader: &FontLoader) -> (fontdb::Database, IndexedFaces) {
         let mut db = fontdb::Database::new();
+        let mut indexed = IndexedFaces::default();
         {
             let now = std::time::Instant::now();
 
-            db.load_system_fonts();
             //TODO: configurable default fonts
             db.set_monospace_family("Fira Mono");
             db.set_sans_serif_family("Fira Sans");
             db.set_serif_family("DejaVu Serif");
 
+            match loader {
+                FontLoader::System => {
+                    db.load_system_fonts();
+
+                    // Fontdb only loads TrueType and OpenType files
+                    #[cfg(feature = "woff")]
+                    for dir in font_dirs() {
+                        visit_font_dir(&dir, &mut |path, metadata| {
+                            if metadata.is_file() && is_woff_file(path) {
+                                match font_file_source(path) {
+                                    Ok(source) => db.load_font_source(source),
+                                    Err(err) => log::warn!("failed to load '{}': {}", path.display(), err),
+                                }
+                            }
+                        });
+                    }
+                },
+                FontLoader::Index(index_path) => {
+                    indexed = load_fonts_indexed(&mut db, index_path, &font_dirs());
+                },
+                #[cfg(feature = "test-fonts")]
+                FontLoader::Test => crate::font::test_fonts::load_test_fonts(&mut db),
+            }
+
             log::info!(
                 "Parsed {} font faces in {}ms.",
                 db.len(),
@@ -57,20 +408,175 @@ impl<'a> FontSystem<'a> {
             );
         }
 
-        Self {
-            locale,
-            db,
-            font_cache: Mutex::new(HashMap::new()),
-            font_matches_cache: Mutex::new(HashMap::new()),
+        (db, indexed)
+    }
+
+    /// Get the font database
+    ///
+    /// This replaces the `db` field, as faces are added for named instances. Loading fonts into
+    /// the database directly is not supported, use [Self::load_font_data] instead.
+    pub fn db(&self) -> &fontdb::Database {
+        &self.font_db().db
+    }
+
+    /// Get all font faces, including a face for each named instance of variable fonts
+    ///
+    /// Unlike [fontdb::Database::faces], variable faces are replaced by their named instances
+    pub fn faces(&self) -> &[fontdb::FaceInfo] {
+        &self.font_db().faces
+    }
+
+    /// Get a font face by ID, see [Self::faces]
+    pub fn face(&self, id: fontdb::ID) -> Option<&fontdb::FaceInfo> {
+        self.faces().iter().find(|face| face.id == id)
+    }
+
+    /// Get the named instance of a face, if it is one
+    pub fn face_instance(&self, id: fontdb::ID) -> Option<&FontInstance> {
+        self.font_db().instances.get(&id)
+    }
+
+    /// Get the number of times fonts have been reloaded
+    ///
+    /// Font IDs, and cache keys using them, are only valid for the generation they were created
+    /// in. [crate::TextBuffer]s reshape their text and [crate::SwashCache]s clear their images
+    /// when this changes. Anything else keeping them outside of the font system, like a glyph
+    /// atlas, must be cleared too.
+    pub fn generation(&self) -> usize {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// Rescan system fonts, after fonts are installed or removed
+    ///
+    /// Fonts loaded with [Self::load_font_data] or [Self::load_font_file] are loaded again, and
+    /// fontconfig configuration is read again when enabled. Font caches are cleared and the
+    /// generation is incremented, see [Self::generation].
+    ///
+    /// This works while fonts, [crate::TextBuffer]s, and [crate::SwashCache]s borrow the font
+    /// system. The fonts of earlier generations stay valid, so their font data is kept until the
+    /// font system is dropped or changed through a mutable reference.
+    pub fn reload(&self) {
+        let (mut db, indexed) = Self::load_db(&self.loader);
+        for source in self.font_sources.iter() {
+            db.load_font_source(source.clone());
+        }
+
+        // Fontconfig settings replace the previous ones, other fallback changes are kept
+        #[allow(unused_mut)]
+        let mut fallback = self.fallback().clone();
+        #[cfg(all(target_os = "linux", feature = "fontconfig"))]
+        if self.loader.is_system() {
+            crate::fallback::load_fontconfig(&mut db, &mut fallback);
+        }
+
+        // Another reload may have finished in the meantime, chain to the latest generation
+        let mut font_db = Box::new(FontDatabase::new(db, indexed, fallback));
+        while let Err(err) = self.font_db().next.set(font_db) {
+            font_db = err;
+        }
+
+        // Fonts of the previous generation may have been cached while loading
+        self.font_cache.lock().unwrap().clear();
+        self.font_matches_cache.lock().unwrap().clear();
+        self.font_fallback_cache.lock().unwrap().clear();
+        if self.loader.is_system() {
+            *self.fonts_stamp_opt.lock().unwrap() = Some(fonts_stamp());
+        }
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+
+        log::info!("Reloaded fonts, generation {}", generation);
+    }
+
+    /// Reload fonts if font directories have changed, returning true if fonts were reloaded
+    ///
+    /// This polls the modification times of the system font directories, and is meant to be
+    /// called periodically, see [Self::reload]. The first call only records the state of the
+    /// font directories.
+    pub fn reload_if_changed(&self) -> bool {
+        if !self.loader.is_system() {
+            return false;
+        }
+
+        let stamp = fonts_stamp();
+        {
+            let mut fonts_stamp_opt = self.fonts_stamp_opt.lock().unwrap();
+            match *fonts_stamp_opt {
+                Some(fonts_stamp) if fonts_stamp != stamp => (),
+                _ => {
+                    *fonts_stamp_opt = Some(stamp);
+                    return false;
+                }
+            }
         }
+
+        log::info!("Font directories changed");
+        self.reload();
+        true
+    }
+
+    /// Load a font from TrueType, OpenType, WOFF, or WOFF2 data
+    ///
+    /// WOFF and WOFF2 data is decompressed when the `woff` feature is enabled. Fonts are kept
+    /// when fonts are reloaded. An error is returned if the data could not be decoded or has no
+    /// font faces.
+    pub fn load_font_data(&mut self, data: Vec<u8>) -> io::Result<()> {
+        let data = sfnt_data(data).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        self.load_font_source(fontdb::Source::Binary(Arc::new(data)))
+    }
+
+    /// Load a font from a TrueType, OpenType, WOFF, or WOFF2 file
+    ///
+    /// See [Self::load_font_data]
+    pub fn load_font_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let data = fs::read(path.as_ref())?;
+        self.load_font_data(data)
+    }
+
+    fn load_font_source(&mut self, source: fontdb::Source) -> io::Result<()> {
+        let font_db = self.font_db_mut();
+        let len = font_db.db.len();
+        let faces_len = font_db.faces.len();
+        font_db.db.load_font_source(source.clone());
+        if font_db.db.len() == len {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "no font faces found in font data"));
+        }
+        font_db.add_faces(len);
+
+        for face in font_db.faces[faces_len..].iter() {
+            log::debug!("loaded font '{}'", face.post_script_name);
+        }
+
+        // Fonts have not been used yet, as that borrows the font system, but matches may change
+        self.font_sources.push(source);
+        self.font_matches_cache.get_mut().unwrap().clear();
+        self.font_fallback_cache.get_mut().unwrap().clear();
+        Ok(())
+    }
+
+    /// Get the font fallback configuration
+    pub fn fallback(&self) -> &FontFallbackConfig {
+        &self.font_db().fallback
+    }
+
+    /// Get the font fallback configuration for changes
+    ///
+    /// This should be done before shaping, memoized fallback decisions are cleared.
+    pub fn fallback_mut(&mut self) -> &mut FontFallbackConfig {
+        self.font_fallback_cache.get_mut().unwrap().clear();
+        &mut self.font_db_mut().fallback
     }
 
     pub fn get_font(&'a self, id: fontdb::ID) -> Option<Arc<Font<'a>>> {
         let mut font_cache = self.font_cache.lock().unwrap();
         font_cache.entry(id).or_insert_with(|| {
-            let face = self.db.face(id)?;
-            match Font::new(face) {
-                Some(font) => Some(Arc::new(font)),
+            let face = self.face(id)?;
+            match Font::new(face, self.face_instance(id)) {
+                Some(font) => {
+                    if let Some(coverage) = self.font_db().coverages.get(&id) {
+                        font.set_coverage(coverage.clone());
+                    }
+                    Some(Arc::new(font))
+                },
                 None => {
                     log::warn!("failed to load font '{}'", face.post_script_name);
                     None
@@ -80,12 +586,14 @@ impl<'a> FontSystem<'a> {
     }
 
     pub fn get_font_matches(&'a self, attrs: Attrs<'a>) -> Arc<FontMatches<'a>> {
+        // Attributes like color and decoration do not change the fonts, so they share matches
+        let attrs = attrs.match_attrs();
         let mut font_matches_cache = self.font_matches_cache.lock().unwrap();
         font_matches_cache.entry(attrs).or_insert_with(|| {
             let now = std::time::Instant::now();
 
             let mut fonts = Vec::new();
-            for face in self.db.faces() {
+            for face in self.faces() {
                 if !attrs.matches(face) {
                     continue;
                 }
@@ -96,9 +604,47 @@ impl<'a> FontSystem<'a> {
                 }
             }
 
+            // Color emoji fonts usually have a single face, include them for all attributes so
+            // that emoji in bold, italic, or monospaced text use them
+            for face in self.faces() {
+                if attrs.matches(face) || fonts.iter().any(|font| font.info.family == face.family) {
+                    continue;
+                }
+
+                if self.font_db().color_faces.contains(&face.id) {
+                    if let Some(font) = self.get_font(face.id) {
+                        fonts.push(font);
+                    }
+                }
+            }
+
+            let default_family = self.db().family_name(&attrs.family).to_string();
+
+            // If the default family lacks the requested face, use its closest face so that
+            // missing styles are synthesized instead of falling back to another family
+            if !fonts.iter().any(|font| font.info.family == default_family) {
+                // Prefer the closest stretch, then style, then weight
+                let closest_opt = self.faces().iter()
+                    .filter(|face| face.family == default_family)
+                    .min_by_key(|face| (
+                        (face.stretch.to_number() as i32 - attrs.stretch.to_number() as i32).abs(),
+                        face.style != attrs.style,
+                        (face.weight.0 as i32 - attrs.weight.0 as i32).abs(),
+                    ));
+                if let Some(font) = closest_opt.and_then(|face| self.get_font(face.id)) {
+                    log::debug!(
+                        "synthesizing {:?} for '{}' from '{}'",
+                        attrs.synthesis(font.info),
+                        default_family,
+                        font.info.post_script_name
+                    );
+                    fonts.push(font);
+                }
+            }
+
             let font_matches = Arc::new(FontMatches {
                 locale: &self.locale,
-                default_family: self.db.family_name(&attrs.family).to_string(),
+                default_family,
                 fonts
             });
 
@@ -108,4 +654,244 @@ impl<'a> FontSystem<'a> {
             font_matches
         }).clone()
     }
+
+    /// Get metrics of the font used for a set of attributes, scaled to a font size in pixels
+    ///
+    /// This uses the font of the default family, and does not account for fallback fonts
+    pub fn get_font_metrics(&'a self, attrs: Attrs<'a>, font_size: i32) -> Option<FontMetrics> {
+        let font_matches = self.get_font_matches(attrs);
+        let font = font_matches.fonts.iter()
+            .find(|font| font.info.family == font_matches.default_family)
+            .or_else(|| font_matches.fonts.first())?;
+        Some(font.metrics(font_size))
+    }
+
+    /// List installed font families and their faces, sorted by display name
+    ///
+    /// This reads the names and axes of every face, and the coverage of the regular face of each
+    /// family, without loading them into the font cache.
+    pub fn families(&self) -> Vec<FontFamily> {
+        let now = std::time::Instant::now();
+
+        let mut families: Vec<FontFamily> = Vec::new();
+        for info in self.faces() {
+            let names_opt = self.db().with_face_data(info.id, |data, index| {
+                let face = ttf_parser::Face::from_slice(data, index).ok()?;
+                Some((face_axes(&face), localized_family_name(&face, &self.locale)))
+            }).flatten();
+            let (axes, display_name_opt) = match names_opt {
+                Some(some) => some,
+                None => {
+                    log::warn!("failed to read font '{}'", info.post_script_name);
+                    continue;
+                }
+            };
+
+            let face = FontFace {
+                id: info.id,
+                post_script_name: info.post_script_name.clone(),
+                index: info.index,
+                instance_opt: self.face_instance(info.id).map(|instance| instance.name.clone()),
+                style: info.style,
+                weight: info.weight,
+                stretch: info.stretch,
+                monospaced: info.monospaced,
+                axes,
+            };
+
+            match families.iter_mut().find(|family| family.name == info.family) {
+                Some(family) => family.faces.push(face),
+                None => families.push(FontFamily {
+                    name: info.family.clone(),
+                    display_name: display_name_opt.unwrap_or_else(|| info.family.clone()),
+                    faces: vec![face],
+                    scripts: Vec::new(),
+                }),
+            }
+        }
+
+        for family in families.iter_mut() {
+            // Use the face closest to regular for script support
+            let regular_opt = family.faces.iter().min_by_key(|face| (
+                face.style != Style::Normal,
+                (face.weight.0 as i32 - 400).abs(),
+            ));
+            if let Some(face) = regular_opt {
+                family.scripts = match self.font_db().coverages.get(&face.id) {
+                    Some(coverage) => coverage.scripts(),
+                    None => self.db().with_face_data(face.id, |data, index| {
+                        let face = ttf_parser::Face::from_slice(data, index).ok()?;
+                        Some(FontCoverage::new(&face).scripts())
+                    }).flatten().unwrap_or_default(),
+                };
+            }
+        }
+
+        families.sort_by_key(|family| family.display_name.to_lowercase());
+
+        log::debug!("listed {} font families in {:?}", families.len(), now.elapsed());
+
+        families
+    }
+
+    /// List installed fonts that can render all characters of a string
+    ///
+    /// Control characters, joiners, and variation selectors are ignored. This loads every face,
+    /// and builds their coverage.
+    pub fn fonts_for_text(&'a self, text: &str) -> Vec<Arc<Font<'a>>> {
+        let chars: Vec<char> = text.chars()
+            .filter(|c| !c.is_control() && !fallback_ignored(*c))
+            .collect();
+
+        let mut fonts = Vec::new();
+        for face in self.faces() {
+            if let Some(font) = self.get_font(face.id) {
+                if font.coverage().contains_all(&chars) {
+                    fonts.push(font);
+                }
+            }
+        }
+        fonts
+    }
+
+    /// List installed font families that support a script, sorted by display name
+    pub fn families_with_script(&self, script: Script) -> Vec<FontFamily> {
+        let mut families = self.families();
+        families.retain(|family| family.supports_script(script));
+        families
+    }
+}
+
+#[cfg(feature = "test-fonts")]
+#[test]
+fn test_families() {
+    use crate::Weight;
+
+    let font_system = FontSystem::new_test();
+    let families = font_system.families();
+
+    let names: Vec<&str> = families.iter().map(|family| family.name.as_str()).collect();
+    assert_eq!(names, [
+        "DejaVu Sans",
+        "DejaVu Sans Mono",
+        "Noto Color Emoji",
+        "Test CJK",
+        "Test Color",
+        "Test Han JP",
+        "Test Han SC",
+    ]);
+
+    let sans = &families[0];
+    assert_eq!(sans.display_name, "DejaVu Sans");
+    assert_eq!(sans.faces.len(), 1);
+    assert_eq!(sans.faces[0].post_script_name, "DejaVuSans");
+    assert_eq!(sans.weights(), [Weight::NORMAL]);
+    assert_eq!(sans.styles(), [Style::Normal]);
+    assert!(!sans.monospaced());
+    assert!(!sans.variable());
+    assert!(sans.supports_script(Script::Latin));
+    assert!(sans.supports_script(Script::Hebrew));
+    assert!(!sans.supports_script(Script::Hangul));
+
+    assert!(families[1].monospaced());
+    assert!(families[3].supports_script(Script::Hangul));
+
+    let kana_names: Vec<String> = font_system.families_with_script(Script::Hiragana).into_iter()
+        .map(|family| family.name)
+        .collect();
+    assert_eq!(kana_names, ["Test Han JP"]);
+    assert_eq!(font_system.families_with_script(Script::Han).len(), 2);
+
+    // Listing families does not load fonts
+    assert!(font_system.font_cache.lock().unwrap().is_empty());
+}
+
+#[cfg(feature = "test-fonts")]
+#[test]
+fn test_font_matches_cache() {
+    use crate::{Color, Decoration, Weight};
+
+    let font_system = FontSystem::new_test();
+    let plain = font_system.get_font_matches(Attrs::new());
+    let decorated = font_system.get_font_matches(
+        Attrs::new().color(Color::rgb(0xFF, 0, 0)).decoration(Decoration::UNDERLINE)
+    );
+    assert!(Arc::ptr_eq(&plain, &decorated));
+    assert_eq!(font_system.font_matches_cache.lock().unwrap().len(), 1);
+
+    // Color fonts are included for all attributes
+    let bold = font_system.get_font_matches(Attrs::new().weight(Weight::BOLD));
+    assert!(bold.fonts.iter().any(|font| font.info.family == "Noto Color Emoji"));
+    assert!(!bold.fonts.iter().any(|font| font.info.family == "Test Han SC"));
+}
+
+#[cfg(feature = "test-fonts")]
+#[test]
+fn test_reload() {
+    use crate::{TextBuffer, TextMetrics};
+
+    let mut font_system = FontSystem::new_test();
+    let faces_len = font_system.faces().len();
+    font_system.load_font_file(concat!(env!("CARGO_MANIFEST_DIR"), "/fonts/TestColor-Regular.ttf")).unwrap();
+    assert_eq!(font_system.faces().len(), faces_len + 1);
+    assert_eq!(font_system.generation(), 0);
+
+    // Loaded fonts are kept
+    font_system.reload();
+    assert_eq!(font_system.generation(), 1);
+    assert_eq!(font_system.faces().len(), faces_len + 1);
+    assert_eq!(font_system.faces().last().unwrap().post_script_name, "TestColor-Regular");
+
+    // Test fonts do not change with the system font directories
+    assert!(!font_system.reload_if_changed());
+    assert_eq!(font_system.generation(), 1);
+
+    // Fonts load from the new generation
+    let font = font_system.get_font(font_system.faces()[0].id).unwrap();
+    assert_eq!(font.info.id, font_system.faces()[0].id);
+
+    // Buffers and caches borrowing the font system follow reloads
+    let mut buffer = TextBuffer::new(&font_system, TextMetrics::new(16, 20));
+    buffer.set_size(1000, 1000);
+    buffer.set_text("abc", Attrs::new());
+    #[cfg(feature = "swash")]
+    let cache_key = buffer.layout_runs().next().unwrap().glyphs[0].cache_key;
+    #[cfg(feature = "swash")]
+    let mut cache = crate::SwashCache::new(&font_system);
+    #[cfg(feature = "swash")]
+    assert!(cache.get_image(cache_key).is_some());
+    buffer.redraw = false;
+
+    font_system.reload();
+    assert_eq!(font_system.generation(), 2);
+    // Fonts of the previous generation stay valid
+    assert_eq!(font.info.post_script_name, font_system.faces()[0].post_script_name);
+    assert!(font_system.font_cache.lock().unwrap().is_empty());
+
+    buffer.shape_until_scroll();
+    assert!(buffer.redraw);
+    let glyph_font = font_system.get_font(buffer.layout_runs().next().unwrap().glyphs[0].cache_key.font_id).unwrap();
+    assert!(!Arc::ptr_eq(&glyph_font, &font));
+    #[cfg(feature = "swash")]
+    {
+        assert!(cache.get_image(cache_key).is_some());
+        assert_eq!((cache.stats().images, cache.stats().misses), (1, 2));
+    }
+}
+
+#[cfg(all(feature = "test-fonts", feature = "woff"))]
+#[test]
+fn test_load_font_data() {
+    let mut font_system = FontSystem::new_test();
+    let faces_len = font_system.faces().len();
+
+    let woff2 = include_bytes!("../../fonts/DejaVuSansMono-Subset.woff2");
+    font_system.load_font_data(woff2.to_vec()).unwrap();
+    assert_eq!(font_system.faces().len(), faces_len + 1);
+    assert_eq!(font_system.faces().last().unwrap().post_script_name, "DejaVuSansMono");
+
+    // Corrupt WOFF2 data and data without fonts are errors
+    assert!(font_system.load_font_data(woff2[..100].to_vec()).is_err());
+    assert!(font_system.load_font_data(vec![0; 100]).is_err());
+    assert_eq!(font_system.faces().len(), faces_len + 1);
 }
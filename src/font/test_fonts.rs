@@ -0,0 +1,50 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use std::sync::Arc;
+use unicode_script::Script;
+
+use crate::{FallbackMode, FontFallbackConfig};
+
+// Subsets of fonts for reproducible tests, see fonts/README.md
+const TEST_FONTS: &[&[u8]] = &[
+    include_bytes!("../../fonts/DejaVuSans-Subset.ttf"),
+    include_bytes!("../../fonts/DejaVuSansMono-Subset.ttf"),
+    include_bytes!("../../fonts/TestCJK-Regular.ttf"),
+    include_bytes!("../../fonts/TestHanJP-Regular.otf"),
+    include_bytes!("../../fonts/TestHanSC-Regular.otf"),
+    include_bytes!("../../fonts/NotoColorEmoji-Subset.ttf"),
+    include_bytes!("../../fonts/TestColor-Regular.ttf"),
+];
+
+const SANS: &str = "DejaVu Sans";
+const MONO: &str = "DejaVu Sans Mono";
+const CJK: &str = "Test CJK";
+const HAN_JP: &str = "Test Han JP";
+const HAN_SC: &str = "Test Han SC";
+const EMOJI: &str = "Noto Color Emoji";
+
+/// Load the bundled test fonts, and use them for generic families
+pub(crate) fn load_test_fonts(db: &mut fontdb::Database) {
+    for data in TEST_FONTS.iter() {
+        db.load_font_source(fontdb::Source::Binary(Arc::new(*data)));
+    }
+    db.set_monospace_family(MONO);
+    db.set_sans_serif_family(SANS);
+    // There is no bundled serif font
+    db.set_serif_family(SANS);
+}
+
+/// Fallback that only uses the bundled test fonts, independent of the platform
+pub(crate) fn test_fallback() -> FontFallbackConfig {
+    let mut fallback = FontFallbackConfig::new();
+    fallback.set_common(&[SANS, CJK, EMOJI], FallbackMode::Replace);
+    fallback.set_script(Script::Arabic, &[SANS], FallbackMode::Replace);
+    fallback.set_script(Script::Hebrew, &[SANS], FallbackMode::Replace);
+    fallback.set_script(Script::Hangul, &[CJK], FallbackMode::Replace);
+    // Han unification, like Noto Sans CJK: Simplified Chinese by default, Japanese for Japanese
+    fallback.set_script(Script::Han, &[HAN_SC, HAN_JP], FallbackMode::Replace);
+    fallback.set_locale_script("ja", Script::Han, &[HAN_JP, HAN_SC], FallbackMode::Replace);
+    fallback.set_script(Script::Hiragana, &[HAN_JP], FallbackMode::Replace);
+    fallback.set_script(Script::Katakana, &[HAN_JP], FallbackMode::Replace);
+    fallback
+}
@@ -0,0 +1,851 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Decoding of WOFF and WOFF2 web fonts to TrueType/OpenType data, see
+//! https://www.w3.org/TR/WOFF/ and https://www.w3.org/TR/WOFF2/
+
+use std::io::Read;
+
+const WOFF_SIGNATURE: u32 = 0x774F4646; // wOFF
+const WOFF2_SIGNATURE: u32 = 0x774F4632; // wOF2
+const TTC_FLAVOR: u32 = 0x74746366; // ttcf
+
+const GLYF: u32 = 0x676C7966;
+const LOCA: u32 = 0x6C6F6361;
+const HMTX: u32 = 0x686D7478;
+const HHEA: u32 = 0x68686561;
+const MAXP: u32 = 0x6D617870;
+
+// Tags referenced by index in the WOFF2 table directory
+const KNOWN_TAGS: [&[u8; 4]; 63] = [
+    b"cmap", b"head", b"hhea", b"hmtx", b"maxp", b"name", b"OS/2", b"post",
+    b"cvt ", b"fpgm", b"glyf", b"loca", b"prep", b"CFF ", b"VORG", b"EBDT",
+    b"EBLC", b"gasp", b"hdmx", b"kern", b"LTSH", b"PCLT", b"VDMX", b"vhea",
+    b"vmtx", b"BASE", b"GDEF", b"GPOS", b"GSUB", b"EBSC", b"JSTF", b"MATH",
+    b"CBDT", b"CBLC", b"COLR", b"CPAL", b"SVG ", b"sbix", b"acnt", b"avar",
+    b"bdat", b"bloc", b"bsln", b"cvar", b"fdsc", b"feat", b"fmtx", b"fvar",
+    b"gvar", b"hsty", b"just", b"lcar", b"mort", b"morx", b"opbd", b"prop",
+    b"trak", b"Zapf", b"Silf", b"Glat", b"Gloc", b"Feat", b"Sill",
+];
+
+// Limit on decompressed sizes, to reject malicious files before allocating
+const MAX_SFNT_SIZE: usize = 256 * 1024 * 1024;
+
+/// Check if data is a WOFF or WOFF2 font
+pub fn is_woff(data: &[u8]) -> bool {
+    matches!(Reader::new(data).u32(), Ok(WOFF_SIGNATURE) | Ok(WOFF2_SIGNATURE))
+}
+
+/// Decode WOFF or WOFF2 data to TrueType/OpenType data
+pub fn decode_woff(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    match Reader::new(data).u32()? {
+        WOFF_SIGNATURE => decode_woff1(data),
+        WOFF2_SIGNATURE => decode_woff2(data),
+        _ => Err("not a WOFF font"),
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], &'static str> {
+        let end = self.offset.checked_add(len).ok_or("offset overflow")?;
+        let bytes = self.data.get(self.offset..end).ok_or("unexpected end of data")?;
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, &'static str> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, &'static str> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn i16(&mut self) -> Result<i16, &'static str> {
+        Ok(self.u16()? as i16)
+    }
+
+    fn u32(&mut self) -> Result<u32, &'static str> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    // Variable length encoding of 32-bit integers, used in the WOFF2 table directory
+    fn uint_base128(&mut self) -> Result<u32, &'static str> {
+        let mut value: u32 = 0;
+        for i in 0..5 {
+            let byte = self.u8()?;
+            if i == 0 && byte == 0x80 {
+                return Err("invalid UIntBase128 leading zero");
+            }
+            if value & 0xFE00_0000 != 0 {
+                return Err("UIntBase128 overflow");
+            }
+            value = (value << 7) | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("UIntBase128 too long")
+    }
+
+    // Variable length encoding of 16-bit integers, used in transformed glyph data
+    fn uint_255_16(&mut self) -> Result<u16, &'static str> {
+        const ONE_MORE_BYTE_CODE_1: u8 = 255;
+        const ONE_MORE_BYTE_CODE_2: u8 = 254;
+        const WORD_CODE: u8 = 253;
+        const LOWEST_U_CODE: u16 = 253;
+        match self.u8()? {
+            WORD_CODE => self.u16(),
+            ONE_MORE_BYTE_CODE_1 => Ok(self.u8()? as u16 + LOWEST_U_CODE),
+            ONE_MORE_BYTE_CODE_2 => Ok(self.u8()? as u16 + LOWEST_U_CODE * 2),
+            code => Ok(code as u16),
+        }
+    }
+}
+
+fn push_u16(data: &mut Vec<u8>, value: u16) {
+    data.extend_from_slice(&value.to_be_bytes());
+}
+
+fn push_u32(data: &mut Vec<u8>, value: u32) {
+    data.extend_from_slice(&value.to_be_bytes());
+}
+
+fn pad4(data: &mut Vec<u8>) {
+    while !data.len().is_multiple_of(4) {
+        data.push(0);
+    }
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    let mut sum: u32 = 0;
+    for chunk in data.chunks(4) {
+        let mut word = [0; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum = sum.wrapping_add(u32::from_be_bytes(word));
+    }
+    sum
+}
+
+// Write an sfnt table directory for tables of (tag, data), tables are written separately
+fn push_table_directory(sfnt: &mut Vec<u8>, flavor: u32, tables: &[(u32, u32, &[u8])]) {
+    let num_tables = tables.len() as u16;
+    let entry_selector = if num_tables > 0 { 15 - num_tables.leading_zeros() as u16 } else { 0 };
+    let search_range = (1u16 << entry_selector).wrapping_mul(16);
+    push_u32(sfnt, flavor);
+    push_u16(sfnt, num_tables);
+    push_u16(sfnt, search_range);
+    push_u16(sfnt, entry_selector);
+    push_u16(sfnt, num_tables.wrapping_mul(16).wrapping_sub(search_range));
+
+    let mut sorted: Vec<&(u32, u32, &[u8])> = tables.iter().collect();
+    sorted.sort_by_key(|(tag, _, _)| *tag);
+    for (tag, offset, data) in sorted {
+        push_u32(sfnt, *tag);
+        push_u32(sfnt, checksum(data));
+        push_u32(sfnt, *offset);
+        push_u32(sfnt, data.len() as u32);
+    }
+}
+
+fn decode_woff1(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let mut reader = Reader::new(data);
+    let _signature = reader.u32()?;
+    let flavor = reader.u32()?;
+    let _length = reader.u32()?;
+    let num_tables = reader.u16()?;
+    let _reserved = reader.u16()?;
+    let total_sfnt_size = reader.u32()? as usize;
+    if total_sfnt_size > MAX_SFNT_SIZE {
+        return Err("font too large");
+    }
+    // Skip version, metadata, and private data fields
+    reader.bytes(24)?;
+
+    let mut tables = Vec::with_capacity(num_tables as usize);
+    for _ in 0..num_tables {
+        let tag = reader.u32()?;
+        let offset = reader.u32()? as usize;
+        let comp_length = reader.u32()? as usize;
+        let orig_length = reader.u32()? as usize;
+        let _orig_checksum = reader.u32()?;
+
+        let comp_data = data.get(offset..offset.checked_add(comp_length).ok_or("offset overflow")?)
+            .ok_or("table out of bounds")?;
+        let table = if comp_length < orig_length {
+            if orig_length > total_sfnt_size {
+                return Err("table larger than font");
+            }
+            let table = miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(comp_data, orig_length)
+                .map_err(|_| "failed to decompress table")?;
+            if table.len() != orig_length {
+                return Err("decompressed table has wrong length");
+            }
+            table
+        } else if comp_length == orig_length {
+            comp_data.to_vec()
+        } else {
+            return Err("compressed table larger than original");
+        };
+        tables.push((tag, table));
+    }
+
+    build_sfnt(flavor, &tables)
+}
+
+fn build_sfnt(flavor: u32, tables: &[(u32, Vec<u8>)]) -> Result<Vec<u8>, &'static str> {
+    let mut offset = 12 + 16 * tables.len();
+    let mut entries = Vec::with_capacity(tables.len());
+    for (tag, table) in tables.iter() {
+        entries.push((*tag, offset as u32, table.as_slice()));
+        offset += (table.len() + 3) & !3;
+        if offset > MAX_SFNT_SIZE {
+            return Err("font too large");
+        }
+    }
+
+    let mut sfnt = Vec::with_capacity(offset);
+    push_table_directory(&mut sfnt, flavor, &entries);
+    for (_, table) in tables.iter() {
+        sfnt.extend_from_slice(table);
+        pad4(&mut sfnt);
+    }
+    Ok(sfnt)
+}
+
+struct Woff2Table {
+    tag: u32,
+    transform: u8,
+    orig_length: usize,
+    // Range of the table in the decompressed stream
+    start: usize,
+    end: usize,
+}
+
+impl Woff2Table {
+    fn transformed(&self) -> bool {
+        match self.tag {
+            // For glyf and loca, version 3 is the null transform
+            GLYF | LOCA => self.transform != 3,
+            _ => self.transform != 0,
+        }
+    }
+}
+
+fn decode_woff2(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let mut reader = Reader::new(data);
+    let _signature = reader.u32()?;
+    let flavor = reader.u32()?;
+    let _length = reader.u32()?;
+    let num_tables = reader.u16()?;
+    let _reserved = reader.u16()?;
+    let total_sfnt_size = reader.u32()? as usize;
+    let total_compressed_size = reader.u32()? as usize;
+    if total_sfnt_size > MAX_SFNT_SIZE {
+        return Err("font too large");
+    }
+    // Skip version, metadata, and private data fields
+    reader.bytes(24)?;
+
+    let mut tables = Vec::with_capacity(num_tables as usize);
+    let mut stream_size = 0usize;
+    for _ in 0..num_tables {
+        let flags = reader.u8()?;
+        let tag = match flags & 0x3F {
+            63 => reader.u32()?,
+            index => u32::from_be_bytes(*KNOWN_TAGS[index as usize]),
+        };
+        let transform = flags >> 6;
+        let orig_length = reader.uint_base128()? as usize;
+        let mut table = Woff2Table {
+            tag,
+            transform,
+            orig_length,
+            start: stream_size,
+            end: 0,
+        };
+        let length = if table.transformed() {
+            reader.uint_base128()? as usize
+        } else {
+            orig_length
+        };
+        if (tag == GLYF || tag == LOCA) && !matches!(transform, 0 | 3) {
+            return Err("unsupported glyf or loca transform");
+        }
+        if tag == HMTX && transform > 1 {
+            return Err("unsupported hmtx transform");
+        }
+        stream_size = stream_size.checked_add(length).ok_or("table size overflow")?;
+        if stream_size > MAX_SFNT_SIZE {
+            return Err("font too large");
+        }
+        table.end = stream_size;
+        tables.push(table);
+    }
+
+    // Fonts as lists of table indices
+    let mut collection_version = 0;
+    let fonts: Vec<(u32, Vec<usize>)> = if flavor == TTC_FLAVOR {
+        collection_version = reader.u32()?;
+        let num_fonts = reader.uint_255_16()?;
+        let mut fonts = Vec::with_capacity(num_fonts as usize);
+        for _ in 0..num_fonts {
+            let font_num_tables = reader.uint_255_16()?;
+            let font_flavor = reader.u32()?;
+            let mut indices = Vec::with_capacity(font_num_tables as usize);
+            for _ in 0..font_num_tables {
+                let index = reader.uint_255_16()? as usize;
+                if index >= tables.len() {
+                    return Err("collection table index out of bounds");
+                }
+                indices.push(index);
+            }
+            fonts.push((font_flavor, indices));
+        }
+        fonts
+    } else {
+        vec![(flavor, (0..tables.len()).collect())]
+    };
+
+    let compressed = reader.bytes(total_compressed_size)?;
+    let mut stream = Vec::with_capacity(stream_size);
+    brotli_decompressor::Decompressor::new(compressed, 4096)
+        .take(stream_size as u64 + 1)
+        .read_to_end(&mut stream)
+        .map_err(|_| "failed to decompress font data")?;
+    if stream.len() != stream_size {
+        return Err("decompressed font data has wrong length");
+    }
+
+    // Reconstruct tables, transformed tables are reconstructed with the other tables of a font
+    let mut outputs: Vec<Option<Vec<u8>>> = tables.iter().map(|table| {
+        if table.transformed() {
+            None
+        } else {
+            Some(stream[table.start..table.end].to_vec())
+        }
+    }).collect();
+    for (_, indices) in fonts.iter() {
+        let find = |tag: u32| indices.iter().copied().find(|&i| tables[i].tag == tag);
+
+        if let (Some(glyf_i), Some(loca_i)) = (find(GLYF), find(LOCA)) {
+            if tables[glyf_i].transformed() && outputs[glyf_i].is_none() {
+                let glyf = &tables[glyf_i];
+                let (glyf_data, loca_data) = reconstruct_glyf(&stream[glyf.start..glyf.end])?;
+                if loca_data.len() != tables[loca_i].orig_length {
+                    return Err("reconstructed loca has wrong length");
+                }
+                outputs[glyf_i] = Some(glyf_data);
+                outputs[loca_i] = Some(loca_data);
+            }
+        }
+
+        if let Some(hmtx_i) = find(HMTX) {
+            if tables[hmtx_i].transformed() && outputs[hmtx_i].is_none() {
+                let table_data = |i: Option<usize>| i.and_then(|i| outputs[i].as_deref());
+                let hmtx = &tables[hmtx_i];
+                let hmtx_data = reconstruct_hmtx(
+                    &stream[hmtx.start..hmtx.end],
+                    table_data(find(HHEA)).ok_or("hmtx transform without hhea")?,
+                    table_data(find(MAXP)).ok_or("hmtx transform without maxp")?,
+                    table_data(find(GLYF)).ok_or("hmtx transform without glyf")?,
+                    table_data(find(LOCA)).ok_or("hmtx transform without loca")?,
+                )?;
+                outputs[hmtx_i] = Some(hmtx_data);
+            }
+        }
+    }
+    let outputs = outputs.into_iter()
+        .map(|output| output.ok_or("transformed table without its dependencies"))
+        .collect::<Result<Vec<Vec<u8>>, _>>()?;
+
+    if flavor != TTC_FLAVOR {
+        let tables = tables.iter().map(|table| table.tag).zip(outputs).collect::<Vec<_>>();
+        return build_sfnt(flavor, &tables);
+    }
+
+    // Collection header, then table directories of each font, then shared tables
+    let header_size = 12 + 4 * fonts.len();
+    let mut directory_offsets = Vec::with_capacity(fonts.len());
+    let mut offset = header_size;
+    for (_, indices) in fonts.iter() {
+        directory_offsets.push(offset as u32);
+        offset += 12 + 16 * indices.len();
+    }
+    let mut table_offsets = Vec::with_capacity(outputs.len());
+    for output in outputs.iter() {
+        table_offsets.push(offset as u32);
+        offset += (output.len() + 3) & !3;
+        if offset > MAX_SFNT_SIZE {
+            return Err("font too large");
+        }
+    }
+
+    let mut sfnt = Vec::with_capacity(offset);
+    push_u32(&mut sfnt, TTC_FLAVOR);
+    push_u32(&mut sfnt, collection_version);
+    push_u32(&mut sfnt, fonts.len() as u32);
+    for directory_offset in directory_offsets {
+        push_u32(&mut sfnt, directory_offset);
+    }
+    for (font_flavor, indices) in fonts.iter() {
+        let entries: Vec<(u32, u32, &[u8])> = indices.iter()
+            .map(|&i| (tables[i].tag, table_offsets[i], outputs[i].as_slice()))
+            .collect();
+        push_table_directory(&mut sfnt, *font_flavor, &entries);
+    }
+    for output in outputs.iter() {
+        sfnt.extend_from_slice(output);
+        pad4(&mut sfnt);
+    }
+    Ok(sfnt)
+}
+
+// Decode a point from the transformed glyf triplet encoding
+fn triplet(flag: u8, glyph_stream: &mut Reader) -> Result<(i32, i32), &'static str> {
+    fn with_sign(flag: u8, value: i32) -> i32 {
+        if flag & 1 != 0 { value } else { -value }
+    }
+
+    let flag = flag & 0x7F;
+    Ok(if flag < 10 {
+        let b0 = glyph_stream.u8()? as i32;
+        (0, with_sign(flag, (((flag & 14) as i32) << 7) + b0))
+    } else if flag < 20 {
+        let b0 = glyph_stream.u8()? as i32;
+        (with_sign(flag, ((((flag - 10) & 14) as i32) << 7) + b0), 0)
+    } else if flag < 84 {
+        let b0 = (flag - 20) as i32;
+        let b1 = glyph_stream.u8()? as i32;
+        (
+            with_sign(flag, 1 + (b0 & 0x30) + (b1 >> 4)),
+            with_sign(flag >> 1, 1 + ((b0 & 0x0C) << 2) + (b1 & 0x0F)),
+        )
+    } else if flag < 120 {
+        let b0 = (flag - 84) as i32;
+        let b1 = glyph_stream.u8()? as i32;
+        let b2 = glyph_stream.u8()? as i32;
+        (
+            with_sign(flag, 1 + ((b0 / 12) << 8) + b1),
+            with_sign(flag >> 1, 1 + (((b0 % 12) >> 2) << 8) + b2),
+        )
+    } else if flag < 124 {
+        let b1 = glyph_stream.u8()? as i32;
+        let b2 = glyph_stream.u8()? as i32;
+        let b3 = glyph_stream.u8()? as i32;
+        (
+            with_sign(flag, (b1 << 4) + (b2 >> 4)),
+            with_sign(flag >> 1, ((b2 & 0x0F) << 8) + b3),
+        )
+    } else {
+        let b1 = glyph_stream.u8()? as i32;
+        let b2 = glyph_stream.u8()? as i32;
+        let b3 = glyph_stream.u8()? as i32;
+        let b4 = glyph_stream.u8()? as i32;
+        (
+            with_sign(flag, (b1 << 8) + b2),
+            with_sign(flag >> 1, (b3 << 8) + b4),
+        )
+    })
+}
+
+// Write flags and coordinates of a simple glyph with the usual glyf compression
+fn push_simple_points(glyf: &mut Vec<u8>, points: &[(i32, i32, bool)], overlap: bool) {
+    const ON_CURVE_POINT: u8 = 0x01;
+    const X_SHORT_VECTOR: u8 = 0x02;
+    const Y_SHORT_VECTOR: u8 = 0x04;
+    const REPEAT_FLAG: u8 = 0x08;
+    const X_IS_SAME_OR_POSITIVE: u8 = 0x10;
+    const Y_IS_SAME_OR_POSITIVE: u8 = 0x20;
+    const OVERLAP_SIMPLE: u8 = 0x40;
+
+    fn encode(delta: i32, short: u8, same_or_positive: u8, flag: &mut u8, coords: &mut Vec<u8>) {
+        if delta == 0 {
+            *flag |= same_or_positive;
+        } else if delta > -256 && delta < 256 {
+            *flag |= short;
+            if delta > 0 {
+                *flag |= same_or_positive;
+            }
+            coords.push(delta.unsigned_abs() as u8);
+        } else {
+            push_u16(coords, delta as i16 as u16);
+        }
+    }
+
+    let mut flags = Vec::with_capacity(points.len());
+    let mut xs = Vec::with_capacity(points.len() * 2);
+    let mut ys = Vec::with_capacity(points.len() * 2);
+    let mut last_flag_i = None;
+    let mut repeat = 0u8;
+    let mut prev = (0i32, 0i32);
+    for (i, point) in points.iter().enumerate() {
+        let mut flag = if point.2 { ON_CURVE_POINT } else { 0 };
+        if i == 0 && overlap {
+            flag |= OVERLAP_SIMPLE;
+        }
+        encode(point.0 - prev.0, X_SHORT_VECTOR, X_IS_SAME_OR_POSITIVE, &mut flag, &mut xs);
+        encode(point.1 - prev.1, Y_SHORT_VECTOR, Y_IS_SAME_OR_POSITIVE, &mut flag, &mut ys);
+        prev = (point.0, point.1);
+
+        match last_flag_i {
+            Some(last_i) if flags[last_i] & !REPEAT_FLAG == flag && repeat < 255 => {
+                if repeat == 0 {
+                    flags[last_i] |= REPEAT_FLAG;
+                    flags.push(0);
+                }
+                repeat += 1;
+                *flags.last_mut().unwrap() = repeat;
+            },
+            _ => {
+                last_flag_i = Some(flags.len());
+                repeat = 0;
+                flags.push(flag);
+            }
+        }
+    }
+
+    glyf.extend_from_slice(&flags);
+    glyf.extend_from_slice(&xs);
+    glyf.extend_from_slice(&ys);
+}
+
+// Reconstruct glyf and loca tables from the transformed glyf table
+fn reconstruct_glyf(data: &[u8]) -> Result<(Vec<u8>, Vec<u8>), &'static str> {
+    const ARG_1_AND_2_ARE_WORDS: u16 = 0x0001;
+    const WE_HAVE_A_SCALE: u16 = 0x0008;
+    const MORE_COMPONENTS: u16 = 0x0020;
+    const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x0040;
+    const WE_HAVE_A_TWO_BY_TWO: u16 = 0x0080;
+    const WE_HAVE_INSTRUCTIONS: u16 = 0x0100;
+
+    let mut reader = Reader::new(data);
+    let _reserved = reader.u16()?;
+    let option_flags = reader.u16()?;
+    let num_glyphs = reader.u16()? as usize;
+    let index_format = reader.u16()?;
+    // Stream sizes are read first, then each stream follows in order
+    let mut sizes = [0; 7];
+    for size in sizes.iter_mut() {
+        *size = reader.u32()? as usize;
+    }
+    let mut n_contour_stream = Reader::new(reader.bytes(sizes[0])?);
+    let mut n_points_stream = Reader::new(reader.bytes(sizes[1])?);
+    let mut flag_stream = Reader::new(reader.bytes(sizes[2])?);
+    let mut glyph_stream = Reader::new(reader.bytes(sizes[3])?);
+    let mut composite_stream = Reader::new(reader.bytes(sizes[4])?);
+    let mut bbox_stream = Reader::new(reader.bytes(sizes[5])?);
+    let mut instruction_stream = Reader::new(reader.bytes(sizes[6])?);
+    let overlap_bitmap = if option_flags & 1 != 0 {
+        Some(reader.bytes(num_glyphs.div_ceil(8))?)
+    } else {
+        None
+    };
+
+    let bbox_bitmap = bbox_stream.bytes(4 * num_glyphs.div_ceil(32))?;
+    let has_bit = |bitmap: &[u8], i: usize| bitmap[i / 8] & (0x80 >> (i % 8)) != 0;
+
+    let mut glyf = Vec::new();
+    let mut offsets = Vec::with_capacity(num_glyphs + 1);
+    for glyph_i in 0..num_glyphs {
+        offsets.push(glyf.len());
+
+        let n_contours = n_contour_stream.i16()?;
+        let has_bbox = has_bit(bbox_bitmap, glyph_i);
+        if n_contours == 0 {
+            if has_bbox {
+                return Err("empty glyph with bounding box");
+            }
+            continue;
+        }
+
+        if n_contours < 0 {
+            // Composite glyph, components are copied as is
+            if !has_bbox {
+                return Err("composite glyph without bounding box");
+            }
+            let start = composite_stream.offset;
+            let mut have_instructions = false;
+            loop {
+                let flags = composite_stream.u16()?;
+                let mut len = 2;
+                len += if flags & ARG_1_AND_2_ARE_WORDS != 0 { 4 } else { 2 };
+                if flags & WE_HAVE_A_SCALE != 0 {
+                    len += 2;
+                } else if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+                    len += 4;
+                } else if flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+                    len += 8;
+                }
+                composite_stream.bytes(len)?;
+                have_instructions |= flags & WE_HAVE_INSTRUCTIONS != 0;
+                if flags & MORE_COMPONENTS == 0 {
+                    break;
+                }
+            }
+            let components = &composite_stream.data[start..composite_stream.offset];
+
+            push_u16(&mut glyf, n_contours as u16);
+            glyf.extend_from_slice(bbox_stream.bytes(8)?);
+            glyf.extend_from_slice(components);
+            if have_instructions {
+                let instruction_len = glyph_stream.uint_255_16()?;
+                push_u16(&mut glyf, instruction_len);
+                glyf.extend_from_slice(instruction_stream.bytes(instruction_len as usize)?);
+            }
+        } else {
+            // Simple glyph
+            let mut end_points = Vec::with_capacity(n_contours as usize);
+            let mut n_points = 0usize;
+            for _ in 0..n_contours {
+                n_points += n_points_stream.uint_255_16()? as usize;
+                if n_points > 0xFFFF {
+                    return Err("too many points in glyph");
+                }
+                let end_point = (n_points as u16).checked_sub(1).ok_or("contour without points")?;
+                end_points.push(end_point);
+            }
+
+            let mut points = Vec::with_capacity(n_points);
+            let (mut x, mut y) = (0i32, 0i32);
+            for _ in 0..n_points {
+                let flag = flag_stream.u8()?;
+                let (dx, dy) = triplet(flag, &mut glyph_stream)?;
+                x = x.checked_add(dx).ok_or("point coordinate overflow")?;
+                y = y.checked_add(dy).ok_or("point coordinate overflow")?;
+                points.push((x, y, flag & 0x80 == 0));
+            }
+            let instruction_len = glyph_stream.uint_255_16()?;
+
+            push_u16(&mut glyf, n_contours as u16);
+            if has_bbox {
+                glyf.extend_from_slice(bbox_stream.bytes(8)?);
+            } else {
+                let x_min = points.iter().map(|p| p.0).min().unwrap_or(0);
+                let y_min = points.iter().map(|p| p.1).min().unwrap_or(0);
+                let x_max = points.iter().map(|p| p.0).max().unwrap_or(0);
+                let y_max = points.iter().map(|p| p.1).max().unwrap_or(0);
+                for value in [x_min, y_min, x_max, y_max] {
+                    push_u16(&mut glyf, value as i16 as u16);
+                }
+            }
+            for end_point in end_points {
+                push_u16(&mut glyf, end_point);
+            }
+            push_u16(&mut glyf, instruction_len);
+            glyf.extend_from_slice(instruction_stream.bytes(instruction_len as usize)?);
+
+            let overlap = overlap_bitmap.is_some_and(|bitmap| has_bit(bitmap, glyph_i));
+            push_simple_points(&mut glyf, &points, overlap);
+        }
+
+        // Short offsets are divided by two, and so only need two byte alignment
+        if index_format == 0 {
+            if !glyf.len().is_multiple_of(2) {
+                glyf.push(0);
+            }
+        } else {
+            pad4(&mut glyf);
+        }
+        if glyf.len() > MAX_SFNT_SIZE {
+            return Err("font too large");
+        }
+    }
+    offsets.push(glyf.len());
+
+    let mut loca = Vec::new();
+    for offset in offsets {
+        if index_format == 0 {
+            if offset / 2 > 0xFFFF {
+                return Err("glyf too large for short loca format");
+            }
+            push_u16(&mut loca, (offset / 2) as u16);
+        } else {
+            push_u32(&mut loca, offset as u32);
+        }
+    }
+
+    Ok((glyf, loca))
+}
+
+// Reconstruct the hmtx table from the transformed hmtx table, left side bearings that were
+// removed are equal to the minimum x of the glyph
+fn reconstruct_hmtx(
+    data: &[u8],
+    hhea: &[u8],
+    maxp: &[u8],
+    glyf: &[u8],
+    loca: &[u8],
+) -> Result<Vec<u8>, &'static str> {
+    let num_h_metrics = Reader::new(hhea.get(34..).ok_or("invalid hhea")?).u16()? as usize;
+    let num_glyphs = Reader::new(maxp.get(4..).ok_or("invalid maxp")?).u16()? as usize;
+    if num_h_metrics == 0 || num_h_metrics > num_glyphs {
+        return Err("invalid number of horizontal metrics");
+    }
+
+    // loca has short offsets when it has two bytes for each glyph
+    let short_loca = loca.len() == 2 * (num_glyphs + 1);
+    let x_min = |glyph_i: usize| -> Result<i16, &'static str> {
+        let mut reader = Reader::new(loca);
+        let (start, end) = if short_loca {
+            reader.offset = glyph_i * 2;
+            (reader.u16()? as usize * 2, reader.u16()? as usize * 2)
+        } else {
+            reader.offset = glyph_i * 4;
+            (reader.u32()? as usize, reader.u32()? as usize)
+        };
+        if start == end {
+            return Ok(0);
+        }
+        Reader::new(glyf.get(start + 2..end).ok_or("invalid loca")?).i16()
+    };
+
+    let mut reader = Reader::new(data);
+    let flags = reader.u8()?;
+    let mut advances = Vec::with_capacity(num_h_metrics);
+    for _ in 0..num_h_metrics {
+        advances.push(reader.u16()?);
+    }
+    let mut lsbs = Vec::with_capacity(num_glyphs);
+    for glyph_i in 0..num_h_metrics {
+        lsbs.push(if flags & 1 != 0 { x_min(glyph_i)? } else { reader.i16()? });
+    }
+    for glyph_i in num_h_metrics..num_glyphs {
+        lsbs.push(if flags & 2 != 0 { x_min(glyph_i)? } else { reader.i16()? });
+    }
+
+    let mut hmtx = Vec::with_capacity(4 * num_h_metrics + 2 * (num_glyphs - num_h_metrics));
+    for (glyph_i, lsb) in lsbs.into_iter().enumerate() {
+        if glyph_i < num_h_metrics {
+            push_u16(&mut hmtx, advances[glyph_i]);
+        }
+        push_u16(&mut hmtx, lsb as u16);
+    }
+    Ok(hmtx)
+}
+
+#[test]
+fn test_woff_varints() {
+    assert_eq!(Reader::new(&[0x3F]).uint_base128(), Ok(63));
+    assert_eq!(Reader::new(&[0x81, 0x00]).uint_base128(), Ok(128));
+    assert!(Reader::new(&[0x80, 0x01]).uint_base128().is_err());
+    assert!(Reader::new(&[0xFF, 0xFF, 0xFF, 0xFF, 0x7F]).uint_base128().is_err());
+
+    assert_eq!(Reader::new(&[252]).uint_255_16(), Ok(252));
+    assert_eq!(Reader::new(&[255, 253]).uint_255_16(), Ok(506));
+    assert_eq!(Reader::new(&[254, 0]).uint_255_16(), Ok(506));
+    assert_eq!(Reader::new(&[253, 0x12, 0x34]).uint_255_16(), Ok(0x1234));
+}
+
+#[test]
+fn test_woff_empty_contour() {
+    // One simple glyph with a contour of zero points
+    let mut data = Vec::new();
+    for value in [0, 0, 1, 0] {
+        push_u16(&mut data, value);
+    }
+    for size in [2, 1, 0, 0, 0, 4, 0] {
+        push_u32(&mut data, size);
+    }
+    push_u16(&mut data, 1);
+    data.push(0);
+    data.extend_from_slice(&[0; 4]);
+    assert_eq!(reconstruct_glyf(&data), Err("contour without points"));
+}
+
+#[test]
+fn test_woff_point_overflow() {
+    // One simple glyph with the most points, each moving by the largest delta
+    let n_points = 0xFFFF;
+    let mut data = Vec::new();
+    for value in [0, 0, 1, 0] {
+        push_u16(&mut data, value);
+    }
+    for size in [2, 3, n_points, n_points * 4, 0, 4, 0] {
+        push_u32(&mut data, size);
+    }
+    push_u16(&mut data, 1);
+    data.extend_from_slice(&[253, 0xFF, 0xFF]);
+    data.resize(data.len() + n_points as usize, 127);
+    data.resize(data.len() + n_points as usize * 4, 0xFF);
+    data.extend_from_slice(&[0; 4]);
+    assert_eq!(reconstruct_glyf(&data), Err("point coordinate overflow"));
+}
+
+#[test]
+fn test_decode_woff() {
+    use ttf_parser::{Face, OutlineBuilder, Tag};
+
+    // Records outlines for comparison
+    struct Outline(String);
+
+    impl OutlineBuilder for Outline {
+        fn move_to(&mut self, x: f32, y: f32) {
+            self.0 += &format!("M{},{} ", x, y);
+        }
+
+        fn line_to(&mut self, x: f32, y: f32) {
+            self.0 += &format!("L{},{} ", x, y);
+        }
+
+        fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+            self.0 += &format!("Q{},{},{},{} ", x1, y1, x, y);
+        }
+
+        fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+            self.0 += &format!("C{},{},{},{},{},{} ", x1, y1, x2, y2, x, y);
+        }
+
+        fn close(&mut self) {
+            self.0 += "Z ";
+        }
+    }
+
+    fn outline(face: &Face, glyph_id: u16) -> String {
+        let mut outline = Outline(String::new());
+        let bbox_opt = face.outline_glyph(ttf_parser::GlyphId(glyph_id), &mut outline);
+        format!("{:?} {}", bbox_opt, outline.0)
+    }
+
+    let ttf = include_bytes!("../../fonts/DejaVuSansMono-Subset.ttf");
+    let ttf_face = Face::from_slice(ttf, 0).unwrap();
+    assert!(!is_woff(ttf));
+
+    // The WOFF2 file has transformed glyf, loca, and hmtx tables
+    for woff in [
+        include_bytes!("../../fonts/DejaVuSansMono-Subset.woff").as_slice(),
+        include_bytes!("../../fonts/DejaVuSansMono-Subset.woff2").as_slice(),
+    ] {
+        assert!(is_woff(woff));
+        let sfnt = decode_woff(woff).unwrap();
+        let face = Face::from_slice(&sfnt, 0).unwrap();
+
+        // Tables other than glyf and loca are the same, glyf and loca may be encoded differently
+        let mut reader = Reader::new(ttf);
+        reader.offset = 4;
+        let num_tables = reader.u16().unwrap();
+        assert!(num_tables > 10);
+        for i in 0..num_tables as usize {
+            reader.offset = 12 + 16 * i;
+            let tag = Tag(reader.u32().unwrap());
+            if tag != Tag::from_bytes(b"glyf") && tag != Tag::from_bytes(b"loca") {
+                assert_eq!(face.table_data(tag), ttf_face.table_data(tag), "table {}", tag);
+            }
+        }
+
+        assert_eq!(face.number_of_glyphs(), ttf_face.number_of_glyphs());
+        for glyph_id in 0..ttf_face.number_of_glyphs() {
+            assert_eq!(outline(&face, glyph_id), outline(&ttf_face, glyph_id), "glyph {}", glyph_id);
+        }
+    }
+}
@@ -1,6 +1,6 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
-use crate::{CacheKey, Color};
+use crate::{CacheKey, Color, Decoration};
 
 /// A laid out glyph
 #[derive(Debug)]
@@ -23,6 +23,25 @@ pub struct LayoutGlyph {
     pub y_int: i32,
     /// Optional color override
     pub color_opt: Option<Color>,
+    /// Lines to draw under or through the glyph, see [Decoration]
+    pub decoration: Decoration,
+}
+
+/// A line drawn under or through laid out glyphs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LayoutDecoration {
+    /// Either [Decoration::UNDERLINE] or [Decoration::STRIKETHROUGH]
+    pub kind: Decoration,
+    /// X offset of the start of the line
+    pub x: f32,
+    /// Y offset of the top of the line, relative to the baseline and increasing downward
+    pub y: f32,
+    /// Width of the line
+    pub w: f32,
+    /// Thickness of the line
+    pub h: f32,
+    /// Optional color override
+    pub color_opt: Option<Color>,
 }
 
 /// A line of laid out glyphs
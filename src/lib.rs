@@ -57,6 +57,11 @@
 pub use self::attrs::*;
 mod attrs;
 
+#[cfg(feature = "atlas")]
+pub use self::atlas::*;
+#[cfg(feature = "atlas")]
+mod atlas;
+
 pub use self::buffer::*;
 mod buffer;
 
@@ -66,12 +71,25 @@ mod buffer_line;
 pub use self::cache::*;
 mod cache;
 
+#[cfg(feature = "color")]
+mod color;
+
+#[cfg(feature = "swash")]
+pub use self::canvas::*;
+#[cfg(feature = "swash")]
+mod canvas;
+
 pub use self::font::*;
 mod font;
 
 pub use self::layout::*;
 mod layout;
 
+#[cfg(feature = "swash")]
+pub use self::sdf::*;
+#[cfg(feature = "swash")]
+mod sdf;
+
 pub use self::shape::*;
 mod shape;
 
@@ -0,0 +1,391 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use swash::zeno::Placement;
+
+use crate::{PathCommand, SwashContent, SwashImage};
+
+/// Settings for rendering distance fields, see [crate::RenderFormat::Sdf]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct SdfSettings {
+    /// Distance from the outline in pixels where values reach 0 outside or 255 inside. The
+    /// outline itself is at 128.
+    pub spread: u32,
+    /// Pixels added around the outline bounds, usually at least the spread
+    pub padding: u32,
+    /// Font size in pixels that fields are rendered at for every font size, so that each glyph
+    /// is rendered once. Fields are drawn scaled by [SdfSettings::scale].
+    pub base_size: u32,
+}
+
+impl SdfSettings {
+    /// Create settings with a spread and padding of 4 pixels, and a base size of 32 pixels
+    pub const fn new() -> Self {
+        Self {
+            spread: 4,
+            padding: 4,
+            base_size: 32,
+        }
+    }
+
+    /// Get the scale from fields rendered at the base size to a font size
+    pub fn scale(&self, font_size: i32) -> f32 {
+        font_size as f32 / self.base_size.max(1) as f32
+    }
+}
+
+impl Default for SdfSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Channels of an edge in a multi-channel distance field
+const RED: u8 = 1 << 0;
+const GREEN: u8 = 1 << 1;
+const BLUE: u8 = 1 << 2;
+const WHITE: u8 = RED | GREEN | BLUE;
+const CYAN: u8 = GREEN | BLUE;
+const MAGENTA: u8 = RED | BLUE;
+const YELLOW: u8 = RED | GREEN;
+
+// Sine of the smallest angle between edges that is treated as a corner
+const CORNER_SIN: f32 = 0.14;
+
+type Point = (f32, f32);
+
+fn sub(a: Point, b: Point) -> Point {
+    (a.0 - b.0, a.1 - b.1)
+}
+
+fn dot(a: Point, b: Point) -> f32 {
+    a.0 * b.0 + a.1 * b.1
+}
+
+fn cross(a: Point, b: Point) -> f32 {
+    a.0 * b.1 - a.1 * b.0
+}
+
+fn normalize(a: Point) -> Point {
+    let len = dot(a, a).sqrt();
+    if len > 0.0 {
+        (a.0 / len, a.1 / len)
+    } else {
+        (0.0, 0.0)
+    }
+}
+
+fn lerp(a: Point, b: Point, t: f32) -> Point {
+    (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t)
+}
+
+// A segment of the outline from one command, flattened to a polyline
+struct Edge {
+    points: Vec<Point>,
+    color: u8,
+}
+
+impl Edge {
+    fn start_dir(&self) -> Point {
+        normalize(sub(self.points[1], self.points[0]))
+    }
+
+    fn end_dir(&self) -> Point {
+        let len = self.points.len();
+        normalize(sub(self.points[len - 1], self.points[len - 2]))
+    }
+
+    // True distance to the edge, and signed pseudo-distance, which extends the ends of the edge
+    // along their directions. The sign is positive on the left of the edge.
+    fn distance(&self, p: Point) -> (f32, f32, f32) {
+        let mut best = (f32::MAX, 0.0, 0.0);
+        let last = self.points.len() - 2;
+        for i in 0..=last {
+            let a = self.points[i];
+            let b = self.points[i + 1];
+            let ab = sub(b, a);
+            let ap = sub(p, a);
+            let len2 = dot(ab, ab);
+            let t = if len2 > 0.0 { dot(ap, ab) / len2 } else { 0.0 };
+            let closest = lerp(a, b, t.clamp(0.0, 1.0));
+            let dist = dot(sub(p, closest), sub(p, closest)).sqrt();
+            if dist < best.0 {
+                let dir = normalize(ab);
+                let side = cross(dir, ap);
+                // Beyond the ends, use the distance to the extended line if it is shorter
+                let pseudo = if (i == 0 && t < 0.0) || (i == last && t > 1.0) {
+                    side
+                } else if side < 0.0 {
+                    -dist
+                } else {
+                    dist
+                };
+                // Prefer edges where the point is in front of, rather than beside, the closest point
+                let orthogonality = if dist > 0.0 { side.abs() / dist } else { 1.0 };
+                best = (dist, pseudo, orthogonality);
+            }
+        }
+        best
+    }
+}
+
+fn flatten(commands: &[PathCommand]) -> Vec<Vec<Edge>> {
+    let mut contours: Vec<Vec<Edge>> = Vec::new();
+    let mut contour: Vec<Edge> = Vec::new();
+    let mut start = (0.0, 0.0);
+    let mut current = (0.0, 0.0);
+
+    let close = |contour: &mut Vec<Edge>, current: Point, start: Point| {
+        let d = sub(start, current);
+        if dot(d, d) > 1e-6 {
+            contour.push(Edge { points: vec![current, start], color: WHITE });
+        }
+    };
+    // Number of lines to flatten a curve into, about one per pixel of its control polygon
+    let steps = |points: &[Point]| -> usize {
+        let len: f32 = points.windows(2).map(|w| dot(sub(w[1], w[0]), sub(w[1], w[0])).sqrt()).sum();
+        (len.ceil() as usize).clamp(2, 32)
+    };
+
+    for command in commands.iter() {
+        match *command {
+            PathCommand::MoveTo(x, y) => {
+                if !contour.is_empty() {
+                    close(&mut contour, current, start);
+                    contours.push(std::mem::take(&mut contour));
+                }
+                start = (x, y);
+                current = start;
+            },
+            PathCommand::LineTo(x, y) => {
+                let p = (x, y);
+                let d = sub(p, current);
+                if dot(d, d) > 1e-6 {
+                    contour.push(Edge { points: vec![current, p], color: WHITE });
+                }
+                current = p;
+            },
+            PathCommand::QuadTo(x1, y1, x, y) => {
+                let (p0, p1, p2) = (current, (x1, y1), (x, y));
+                let n = steps(&[p0, p1, p2]);
+                let points = (0..=n).map(|i| {
+                    let t = i as f32 / n as f32;
+                    lerp(lerp(p0, p1, t), lerp(p1, p2, t), t)
+                }).collect();
+                contour.push(Edge { points, color: WHITE });
+                current = p2;
+            },
+            PathCommand::CurveTo(x1, y1, x2, y2, x, y) => {
+                let (p0, p1, p2, p3) = (current, (x1, y1), (x2, y2), (x, y));
+                let n = steps(&[p0, p1, p2, p3]);
+                let points = (0..=n).map(|i| {
+                    let t = i as f32 / n as f32;
+                    let a = lerp(lerp(p0, p1, t), lerp(p1, p2, t), t);
+                    let b = lerp(lerp(p1, p2, t), lerp(p2, p3, t), t);
+                    lerp(a, b, t)
+                }).collect();
+                contour.push(Edge { points, color: WHITE });
+                current = p3;
+            },
+            PathCommand::Close => {
+                close(&mut contour, current, start);
+                current = start;
+            },
+        }
+    }
+    if !contour.is_empty() {
+        close(&mut contour, current, start);
+        contours.push(contour);
+    }
+    contours
+}
+
+// Assign channels to edges, so that each corner is between edges with different channels
+fn color_edges(contour: &mut [Edge]) {
+    let n = contour.len();
+    let corners: Vec<usize> = (0..n).filter(|&i| {
+        let prev = contour[(i + n - 1) % n].end_dir();
+        let next = contour[i].start_dir();
+        dot(prev, next) <= 0.0 || cross(prev, next).abs() > CORNER_SIN
+    }).collect();
+
+    match corners.len() {
+        0 => for edge in contour.iter_mut() {
+            edge.color = WHITE;
+        },
+        1 => {
+            // Teardrop, split into three colors starting at the corner
+            let colors = [MAGENTA, WHITE, YELLOW];
+            for k in 0..n {
+                contour[(corners[0] + k) % n].color = colors[(3 * k / n).min(2)];
+            }
+        },
+        count => {
+            let colors = [CYAN, MAGENTA, YELLOW];
+            for (group, &corner) in corners.iter().enumerate() {
+                let mut color = colors[group % 3];
+                // The last group also meets the first group
+                if group == count - 1 && color == colors[0] {
+                    color = colors[1];
+                }
+                let end = corners[(group + 1) % count];
+                let mut i = corner;
+                loop {
+                    contour[i].color = color;
+                    i = (i + 1) % n;
+                    if i == end {
+                        break;
+                    }
+                }
+            }
+        },
+    }
+}
+
+// Check if a point is inside the outline, with the nonzero rule
+fn inside(contours: &[Vec<Edge>], p: Point) -> bool {
+    let mut winding = 0;
+    for edge in contours.iter().flatten() {
+        for w in edge.points.windows(2) {
+            let (a, b) = (w[0], w[1]);
+            let side = cross(sub(b, a), sub(p, a));
+            if a.1 <= p.1 {
+                if b.1 > p.1 && side > 0.0 {
+                    winding += 1;
+                }
+            } else if b.1 <= p.1 && side < 0.0 {
+                winding -= 1;
+            }
+        }
+    }
+    winding != 0
+}
+
+/// Render a distance field from outline commands, with coordinates like [crate::PathCommand]
+///
+/// Single-channel fields are [SwashContent::Mask] images of signed distance. Multi-channel fields
+/// are [SwashContent::SubpixelMask] images, with the distance in the median of the red, green,
+/// and blue channels, and the single-channel distance in alpha.
+pub(crate) fn sdf_image(commands: &[PathCommand], settings: SdfSettings, multi: bool) -> Option<SwashImage> {
+    let mut contours = flatten(commands);
+    if contours.is_empty() {
+        return None;
+    }
+
+    // Bounds of the outline
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (f32::MAX, f32::MAX, f32::MIN, f32::MIN);
+    // Twice the signed area, to find the orientation of outer contours
+    let mut area = 0.0;
+    for edge in contours.iter().flatten() {
+        for w in edge.points.windows(2) {
+            area += cross(w[0], w[1]);
+        }
+        for p in edge.points.iter() {
+            min_x = min_x.min(p.0);
+            min_y = min_y.min(p.1);
+            max_x = max_x.max(p.0);
+            max_y = max_y.max(p.1);
+        }
+    }
+    // Pseudo-distances are positive on the left of edges, flip them if that is outside
+    let orientation = if area < 0.0 { -1.0 } else { 1.0 };
+    if multi {
+        for contour in contours.iter_mut() {
+            color_edges(contour);
+        }
+    }
+
+    let padding = settings.padding as i32;
+    let left = min_x.floor() as i32 - padding;
+    let top = min_y.floor() as i32 - padding;
+    let width = (max_x.ceil() as i32 + padding - left).max(0) as u32;
+    let height = (max_y.ceil() as i32 + padding - top).max(0) as u32;
+    let spread = settings.spread.max(1) as f32;
+    let encode = |distance: f32| -> u8 {
+        (127.5 + distance / spread * 127.5).round().clamp(0.0, 255.0) as u8
+    };
+
+    let mut image = SwashImage::new();
+    image.placement = Placement { left, top: -top, width, height };
+    image.content = if multi { SwashContent::SubpixelMask } else { SwashContent::Mask };
+    let channels = if multi { 4 } else { 1 };
+    image.data = Vec::with_capacity(width as usize * height as usize * channels);
+
+    for y in 0..height {
+        for x in 0..width {
+            let p = ((left + x as i32) as f32 + 0.5, (top + y as i32) as f32 + 0.5);
+
+            let mut min_dist = f32::MAX;
+            // Closest edge for each channel, as distance, orthogonality, and pseudo-distance
+            let mut closest = [(f32::MAX, 0.0, 0.0); 3];
+            for edge in contours.iter().flatten() {
+                let (dist, pseudo, orthogonality) = edge.distance(p);
+                min_dist = min_dist.min(dist);
+                if multi {
+                    for (channel, best) in closest.iter_mut().enumerate() {
+                        if edge.color & (1 << channel) == 0 {
+                            continue;
+                        }
+                        if dist < best.0 - 1e-4 || (dist < best.0 + 1e-4 && orthogonality > best.1) {
+                            *best = (dist, orthogonality, pseudo);
+                        }
+                    }
+                }
+            }
+
+            let sdf = if inside(&contours, p) { min_dist } else { -min_dist };
+            if multi {
+                for best in closest.iter() {
+                    image.data.push(encode(best.2 * orientation));
+                }
+            }
+            image.data.push(encode(sdf));
+        }
+    }
+
+    Some(image)
+}
+
+#[test]
+fn test_sdf_image() {
+    // Square from 0 to 10, clockwise on screen
+    let square = PathCommand::rect(0.0, -10.0, 10.0, 10.0);
+    let settings = SdfSettings { spread: 4, padding: 2, ..SdfSettings::new() };
+
+    let image = sdf_image(&square, settings, false).unwrap();
+    assert_eq!(image.content, SwashContent::Mask);
+    let placement = image.placement;
+    assert_eq!((placement.left, placement.top, placement.width, placement.height), (-2, 12, 14, 14));
+    let value = |image: &SwashImage, x: usize, y: usize, channel: usize| {
+        let channels = image.data.len() / (14 * 14);
+        image.data[(y * 14 + x) * channels + channel]
+    };
+    // Center is inside by more than the spread, corner pixel is outside by about 2.1
+    assert_eq!(value(&image, 7, 7, 0), 255);
+    assert_eq!(value(&image, 0, 0, 0), 60);
+    // Pixels next to the edge, half a pixel inside and outside
+    assert_eq!(value(&image, 2, 7, 0), 143);
+    assert_eq!(value(&image, 1, 7, 0), 112);
+
+    // Counter-clockwise contours give the same field
+    let reversed = [
+        PathCommand::MoveTo(0.0, -10.0),
+        PathCommand::LineTo(0.0, 0.0),
+        PathCommand::LineTo(10.0, 0.0),
+        PathCommand::LineTo(10.0, -10.0),
+        PathCommand::Close,
+    ];
+    assert_eq!(sdf_image(&reversed, settings, false).unwrap().data, image.data);
+
+    let msdf = sdf_image(&square, settings, true).unwrap();
+    assert_eq!(msdf.content, SwashContent::SubpixelMask);
+    let median = |x, y| {
+        let mut channels = [value(&msdf, x, y, 0), value(&msdf, x, y, 1), value(&msdf, x, y, 2)];
+        channels.sort_unstable();
+        channels[1]
+    };
+    assert_eq!(value(&msdf, 0, 0, 3), 60);
+    assert!(median(7, 7) > 128 && median(2, 7) > 128 && median(1, 7) < 128);
+    // The corner stays sharp, a pixel diagonally outside it is outside
+    assert!(median(1, 1) < 128);
+    assert!(median(2, 2) > 128);
+}
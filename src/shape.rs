@@ -1,10 +1,40 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
+use std::ops::Range;
 use unicode_script::{Script, UnicodeScript};
 use unicode_segmentation::UnicodeSegmentation;
 
-use crate::{AttrsList, CacheKey, Color, Font, FontSystem, LayoutGlyph, LayoutLine};
-use crate::fallback::FontFallbackIter;
+use crate::{AttrsList, CacheKey, CacheKeyFlags, Color, Decoration, Font, FontSystem, LayoutGlyph, LayoutLine, Palette};
+use crate::cache::FAKE_BOLD_EM;
+use crate::fallback::{emoji_presentation, FontFallbackIter, FontFallbackKey, FONT_FALLBACK_CACHE_KEYS};
+
+/// Check if a script takes the script of neighbouring characters
+fn script_neutral(script: Script) -> bool {
+    matches!(script, Script::Common | Script::Inherited | Script::Unknown)
+}
+
+/// Scripts that are mixed in CJK text, and share fallback fonts
+const CJK_SCRIPTS: [Script; 5] = [
+    Script::Han,
+    Script::Hiragana,
+    Script::Katakana,
+    Script::Hangul,
+    Script::Bopomofo,
+];
+
+/// Get the scripts used to choose fallback fonts for a run, starting with the script of the run
+fn fallback_scripts(script: Script, latin_fallback: bool) -> Vec<Script> {
+    if CJK_SCRIPTS.contains(&script) {
+        let mut scripts = vec![script];
+        scripts.extend(CJK_SCRIPTS.iter().copied().filter(|cjk_script| *cjk_script != script));
+        scripts
+    } else if script_neutral(script) || (script == Script::Latin && !latin_fallback) {
+        // Latin only uses script fallbacks if configured
+        Vec::new()
+    } else {
+        vec![script]
+    }
+}
 
 fn shape_fallback(
     font: &Font,
@@ -12,18 +42,37 @@ fn shape_fallback(
     attrs_list: &AttrsList,
     start_run: usize,
     end_run: usize,
+    script: Script,
     span_rtl: bool,
-) -> (Vec<ShapeGlyph>, Vec<usize>) {
+) -> (Vec<ShapeGlyph>, Vec<Range<usize>>) {
     let run = &line[start_run..end_run];
 
     let font_scale = font.rustybuzz.units_per_em() as f32;
 
+    let attrs = attrs_list.get_span(start_run..end_run);
+
+    // Synthesize styles the font does not provide
+    let cache_key_flags = attrs.synthesis(font.info);
+
     let mut buffer = rustybuzz::UnicodeBuffer::new();
     buffer.set_direction(if span_rtl {
         rustybuzz::Direction::RightToLeft
     } else {
         rustybuzz::Direction::LeftToRight
     });
+    if let Some(language) = attrs.language_opt {
+        match language.parse() {
+            Ok(language) => buffer.set_language(language),
+            Err(err) => log::warn!("failed to parse language '{}': {}", language, err),
+        }
+    }
+    if !script_neutral(script) {
+        let tag = rustybuzz::Tag::from_bytes_lossy(script.short_name().as_bytes());
+        match rustybuzz::Script::from_iso15924_tag(tag) {
+            Some(script) => buffer.set_script(script),
+            None => log::warn!("failed to find shaping script for {:?}", script),
+        }
+    }
     buffer.push_str(run);
     buffer.guess_segment_properties();
 
@@ -38,10 +87,13 @@ fn shape_fallback(
     let glyph_infos = glyph_buffer.glyph_infos();
     let glyph_positions = glyph_buffer.glyph_positions();
 
-    let mut missing = Vec::new();
     let mut glyphs = Vec::with_capacity(glyph_infos.len());
     for (info, pos) in glyph_infos.iter().zip(glyph_positions.iter()) {
-        let x_advance = pos.x_advance as f32 / font_scale;
+        let mut x_advance = pos.x_advance as f32 / font_scale;
+        if x_advance != 0.0 && cache_key_flags.contains(CacheKeyFlags::FAKE_BOLD) {
+            // Make room for the emboldened outline
+            x_advance += FAKE_BOLD_EM;
+        }
         let y_advance = pos.y_advance as f32 / font_scale;
         let x_offset = pos.x_offset as f32 / font_scale;
         let y_offset = pos.y_offset as f32 / font_scale;
@@ -49,9 +101,6 @@ fn shape_fallback(
         let start_glyph = start_run + info.cluster as usize;
 
         //println!("  {:?} {:?}", info, pos);
-        if info.glyph_id == 0 {
-            missing.push(start_glyph);
-        }
 
         glyphs.push(ShapeGlyph {
             start: start_glyph,
@@ -62,7 +111,10 @@ fn shape_fallback(
             y_offset,
             font_id: font.info.id,
             glyph_id: info.glyph_id.try_into().unwrap(),
+            cache_key_flags,
             color_opt: None,
+            decoration: Decoration::empty(),
+            palette: Palette::Default,
         });
     }
 
@@ -91,37 +143,98 @@ fn shape_fallback(
         }
     }
 
-    // Set color
+    // Find clusters with missing glyphs
+    let mut missing: Vec<Range<usize>> = Vec::new();
+    for glyph in glyphs.iter().filter(|glyph| glyph.glyph_id == 0) {
+        add_range(&mut missing, glyph.start..glyph.end);
+    }
+
+    // Set color, decoration, and palette
     //TODO: these attributes should not be related to shaping
     for glyph in glyphs.iter_mut() {
         let attrs = attrs_list.get_span(glyph.start..glyph.end);
         glyph.color_opt = attrs.color_opt;
+        glyph.decoration = attrs.decoration;
+        glyph.palette = attrs.palette;
     }
 
     (glyphs, missing)
 }
 
+// Add a range to sorted ranges, merging it with overlapping and adjacent ranges
+fn add_range(ranges: &mut Vec<Range<usize>>, range: Range<usize>) {
+    let i = ranges.partition_point(|other| other.end < range.start);
+    let mut merged = range;
+    while i < ranges.len() && ranges[i].start <= merged.end {
+        let other = ranges.remove(i);
+        merged = merged.start.min(other.start)..merged.end.max(other.end);
+    }
+    ranges.insert(i, merged);
+}
+
+/// Check if a character is ignored when selecting a font by coverage
+pub(crate) fn fallback_ignored(c: char) -> bool {
+    matches!(
+        c,
+        // Zero width space, joiners, and directional marks
+        '\u{200B}'..='\u{200F}' |
+        // Word joiner and invisible operators
+        '\u{2060}'..='\u{206F}' |
+        // Variation selectors
+        '\u{FE00}'..='\u{FE0F}' |
+        '\u{E0100}'..='\u{E01EF}'
+    )
+}
+
+/// Resolve the script of each cluster of a word, Common and Inherited take the script of their
+/// neighbours, preferring the previous one
+fn cluster_scripts(word: &str, start_word: usize) -> Vec<(usize, usize, Script)> {
+    let mut egcs = Vec::new();
+    for (egc_i, egc) in word.grapheme_indices(true) {
+        let start_egc = start_word + egc_i;
+        let end_egc = start_egc + egc.len();
+        let script = egc.chars()
+            .map(|c| c.script())
+            .find(|script| !script_neutral(*script))
+            .unwrap_or(Script::Common);
+        egcs.push((start_egc, end_egc, script));
+    }
+    let mut prev_script_opt = None;
+    for egc in egcs.iter_mut() {
+        if script_neutral(egc.2) {
+            if let Some(prev_script) = prev_script_opt {
+                egc.2 = prev_script;
+            }
+        } else {
+            prev_script_opt = Some(egc.2);
+        }
+    }
+    let mut next_script_opt = None;
+    for egc in egcs.iter_mut().rev() {
+        if script_neutral(egc.2) {
+            if let Some(next_script) = next_script_opt {
+                egc.2 = next_script;
+            }
+        } else {
+            next_script_opt = Some(egc.2);
+        }
+    }
+    egcs
+}
+
 fn shape_run<'a>(
     font_system: &'a FontSystem<'a>,
     line: &str,
     attrs_list: &AttrsList<'a>,
     start_run: usize,
     end_run: usize,
+    run_script: Script,
     span_rtl: bool,
-) -> Vec<ShapeGlyph> {
+) -> (Vec<ShapeGlyph>, Vec<ShapeFallback>, Vec<Range<usize>>) {
+    let latin_fallback = font_system.fallback().has_script(Script::Latin);
+
     //TODO: use smallvec?
-    let mut scripts = Vec::new();
-    for c in line[start_run..end_run].chars() {
-        match c.script() {
-            Script::Common |
-            Script::Inherited |
-            Script::Latin |
-            Script::Unknown => (),
-            script => if ! scripts.contains(&script) {
-                scripts.push(script);
-            },
-        }
-    }
+    let scripts = fallback_scripts(run_script, latin_fallback);
 
     log::trace!(
         "      Run {:?}: '{}'",
@@ -133,95 +246,114 @@ fn shape_run<'a>(
 
     let font_matches = font_system.get_font_matches(attrs);
 
+    // The language of the text takes precedence over the system locale
+    let locale = attrs.language_opt.unwrap_or(font_matches.locale);
+
     let default_families = [font_matches.default_family.as_str()];
     let mut font_iter = FontFallbackIter::new(
         &font_matches.fonts,
         &default_families,
-        scripts,
-        font_matches.locale
-    );
-
-    let (mut glyphs, mut missing) = shape_fallback(
-        font_iter.next().unwrap(),
-        line,
-        attrs_list,
-        start_run,
-        end_run,
-        span_rtl,
+        scripts.clone(),
+        locale,
+        font_system.fallback(),
     );
 
-    //TODO: improve performance!
-    while !missing.is_empty() {
-        let font = match font_iter.next() {
-            Some(some) => some,
-            None => break,
-        };
-
-        log::trace!("Evaluating fallback with font '{}'", font.info.family);
-        let (mut fb_glyphs, fb_missing) = shape_fallback(
-            font,
-            line,
-            attrs_list,
-            start_run,
-            end_run,
-            span_rtl,
-        );
-
-        // Insert all matching glyphs
-        let mut fb_i = 0;
-        while fb_i < fb_glyphs.len() {
-            let start = fb_glyphs[fb_i].start;
-            let end = fb_glyphs[fb_i].end;
-
-            // Skip clusters that are not missing, or where the fallback font is missing
-            if !missing.contains(&start) || fb_missing.contains(&start) {
-                fb_i += 1;
-                continue;
-            }
+    // Select a font for each cluster by coverage, memoizing the font chosen for each character
+    let mut font_ranges: Vec<(usize, usize, fontdb::ID)> = Vec::new();
+    {
+        let mut font_fallback_cache = font_system.font_fallback_cache.lock().unwrap();
+        let key = FontFallbackKey::new(&attrs, scripts, locale);
+        if font_fallback_cache.len() >= FONT_FALLBACK_CACHE_KEYS && !font_fallback_cache.contains_key(&key) {
+            font_fallback_cache.clear();
+        }
+        let decisions = font_fallback_cache.entry(key).or_default();
 
-            let mut missing_i = 0;
-            while missing_i < missing.len() {
-                if missing[missing_i] >= start && missing[missing_i] < end {
-                    // println!("No longer missing {}", missing[missing_i]);
-                    missing.remove(missing_i);
-                } else {
-                    missing_i += 1;
-                }
-            }
+        for (egc_i, egc) in line[start_run..end_run].grapheme_indices(true) {
+            let start_egc = start_run + egc_i;
+            let end_egc = start_egc + egc.len();
 
-            // Find prior glyphs
-            let mut i = 0;
-            while i < glyphs.len() {
-                if glyphs[i].start >= start && glyphs[i].end <= end {
+            let chars: Vec<char> = egc.chars().filter(|c| !fallback_ignored(*c)).collect();
+
+            // Emoji clusters, including ZWJ sequences, select a font by their presentation
+            let presentation_opt = emoji_presentation(egc);
+
+            let mut font_id_opt = None;
+            for (i, c) in chars.iter().enumerate() {
+                let decision = *decisions.entry((*c, presentation_opt)).or_insert_with(|| {
+                    font_iter.find_presentation(&[*c], presentation_opt).map(|font| font.info.id)
+                });
+                if i == 0 {
+                    font_id_opt = decision;
+                } else if decision != font_id_opt {
+                    // Characters prefer different fonts, find one that covers the whole cluster
+                    if let Some(font) = font_iter.find_presentation(&chars, presentation_opt) {
+                        font_id_opt = Some(font.info.id);
+                    }
                     break;
-                } else {
-                    i += 1;
                 }
             }
 
-            // Remove prior glyphs
-            while i < glyphs.len() {
-                if glyphs[i].start >= start && glyphs[i].end <= end {
-                    let _glyph = glyphs.remove(i);
-                    // log::trace!("Removed {},{} from {}", _glyph.start, _glyph.end, i);
-                } else {
-                    break;
+            let font_id = match font_id_opt.or_else(|| font_iter.first().map(|font| font.info.id)) {
+                Some(some) => some,
+                None => {
+                    log::warn!("no fonts found for {:?}", attrs);
+                    let run_range = start_run..end_run;
+                    return (Vec::new(), Vec::new(), vec![run_range]);
                 }
-            }
+            };
 
-            while fb_i < fb_glyphs.len() {
-                if fb_glyphs[fb_i].start >= start && fb_glyphs[fb_i].end <= end {
-                    let fb_glyph = fb_glyphs.remove(fb_i);
-                    // log::trace!("Insert {},{} from font {} at {}", fb_glyph.start, fb_glyph.end, font_i, i);
-                    glyphs.insert(i, fb_glyph);
-                    i += 1;
-                } else {
-                    break;
-                }
+            match font_ranges.last_mut() {
+                Some(font_range) if font_range.2 == font_id => font_range.1 = end_egc,
+                _ => font_ranges.push((start_egc, end_egc, font_id)),
             }
         }
     }
 
+    // Shape each range with its selected font
+    let mut glyphs = Vec::new();
+    let mut fallbacks = Vec::new();
+    let mut missing = Vec::new();
+    for (start, end, font_id) in font_ranges {
+        let font = match font_system.get_font(font_id) {
+            Some(some) => some,
+            None => continue,
+        };
+
+        if font.info.family != font_matches.default_family {
+            fallbacks.push(ShapeFallback { range: start..end, font_id });
+        }
+
+        log::trace!("Shaping '{}' with font '{}'", &line[start..end], font.info.family);
+        let (mut range_glyphs, range_missing) = shape_fallback(
+            &font,
+            line,
+            attrs_list,
+            start,
+            end,
+            run_script,
+            span_rtl,
+        );
+        if !range_missing.is_empty() {
+            log::debug!(
+                "font '{}' covers '{}' but is missing glyphs at {:?}",
+                font.info.family,
+                &line[start..end],
+                range_missing
+            );
+        }
+        for range in range_missing {
+            add_range(&mut missing, range);
+        }
+
+        // Glyphs of RTL runs are in visual order
+        if span_rtl {
+            range_glyphs.append(&mut glyphs);
+            glyphs = range_glyphs;
+        } else {
+            glyphs.append(&mut range_glyphs);
+        }
+    }
+
     // Debug missing font fallbacks
     font_iter.check_missing(&line[start_run..end_run]);
 
@@ -231,7 +363,16 @@ fn shape_run<'a>(
     }
     */
 
-    glyphs
+    (glyphs, fallbacks, missing)
+}
+
+/// A range of a line that was shaped with a fallback font, see [ShapeLine::fallbacks]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ShapeFallback {
+    /// Range of the text in the line
+    pub range: Range<usize>,
+    /// Font used instead of the default family
+    pub font_id: fontdb::ID,
 }
 
 /// A shaped glyph
@@ -244,7 +385,10 @@ pub struct ShapeGlyph {
     pub y_offset: f32,
     pub font_id: fontdb::ID,
     pub glyph_id: u16,
+    pub cache_key_flags: CacheKeyFlags,
     pub color_opt: Option<Color>,
+    pub decoration: Decoration,
+    pub palette: Palette,
 }
 
 impl ShapeGlyph {
@@ -257,7 +401,9 @@ impl ShapeGlyph {
             self.font_id,
             self.glyph_id,
             font_size,
-            (x + x_offset, y - y_offset)
+            (x + x_offset, y - y_offset),
+            self.cache_key_flags,
+            self.palette,
         );
         LayoutGlyph {
             start: self.start,
@@ -269,6 +415,7 @@ impl ShapeGlyph {
             x_int,
             y_int,
             color_opt: self.color_opt,
+            decoration: self.decoration,
         }
     }
 }
@@ -277,6 +424,10 @@ impl ShapeGlyph {
 pub struct ShapeWord {
     pub blank: bool,
     pub glyphs: Vec<ShapeGlyph>,
+    /// Ranges shaped with fallback fonts
+    pub fallbacks: Vec<ShapeFallback>,
+    /// Ranges of clusters with missing glyphs
+    pub missing: Vec<Range<usize>>,
 }
 
 impl ShapeWord {
@@ -297,42 +448,59 @@ impl ShapeWord {
             word
         );
 
-        let mut glyphs = Vec::new();
+        let egcs = cluster_scripts(word, start_word);
 
+        // Split into runs of compatible attributes and a single script
+        let mut runs = Vec::new();
         let mut start_run = start_word;
         let mut attrs = attrs_list.defaults();
-        for (egc_i, egc) in word.grapheme_indices(true) {
-            let start_egc = start_word + egc_i;
-            let end_egc = start_egc + egc.len();
+        let mut script = egcs.first().map_or(Script::Common, |egc| egc.2);
+        for (start_egc, end_egc, script_egc) in egcs {
             let attrs_egc = attrs_list.get_span(start_egc..end_egc);
-            if ! attrs.compatible(&attrs_egc) {
-                //TODO: more efficient
-                glyphs.append(&mut shape_run(
-                    font_system,
-                    line,
-                    attrs_list,
-                    start_run,
-                    start_egc,
-                    span_rtl
-                ));
+            if ! attrs.compatible(&attrs_egc) || script != script_egc {
+                if start_run < start_egc {
+                    runs.push((start_run, start_egc, script));
+                }
 
                 start_run = start_egc;
                 attrs = attrs_egc;
+                script = script_egc;
             }
         }
         if start_run < end_word {
+            runs.push((start_run, end_word, script));
+        }
+
+        let mut glyphs = Vec::new();
+        let mut fallbacks = Vec::new();
+        let mut missing = Vec::new();
+        for (start_run, end_run, script) in runs {
             //TODO: more efficient
-            glyphs.append(&mut shape_run(
+            let (mut run_glyphs, mut run_fallbacks, run_missing) = shape_run(
                 font_system,
                 line,
                 attrs_list,
                 start_run,
-                end_word,
+                end_run,
+                script,
                 span_rtl
-            ));
+            );
+
+            // Glyphs of RTL runs are in visual order
+            if span_rtl {
+                run_glyphs.append(&mut glyphs);
+                glyphs = run_glyphs;
+            } else {
+                glyphs.append(&mut run_glyphs);
+            }
+
+            fallbacks.append(&mut run_fallbacks);
+            for range in run_missing {
+                add_range(&mut missing, range);
+            }
         }
 
-        Self { blank, glyphs }
+        Self { blank, glyphs, fallbacks, missing }
     }
 }
 
@@ -476,6 +644,42 @@ impl ShapeLine {
         Self { rtl, spans }
     }
 
+    /// Get the ranges that were shaped with fallback fonts, in text order
+    ///
+    /// Adjacent ranges using the same font, like in different words, are merged
+    pub fn fallbacks(&self) -> Vec<ShapeFallback> {
+        let mut word_fallbacks: Vec<&ShapeFallback> = self.spans.iter()
+            .flat_map(|span| span.words.iter())
+            .flat_map(|word| word.fallbacks.iter())
+            .collect();
+        word_fallbacks.sort_by_key(|fallback| fallback.range.start);
+
+        let mut fallbacks: Vec<ShapeFallback> = Vec::with_capacity(word_fallbacks.len());
+        for fallback in word_fallbacks {
+            match fallbacks.last_mut() {
+                Some(last) if last.font_id == fallback.font_id && last.range.end == fallback.range.start => {
+                    last.range.end = fallback.range.end;
+                },
+                _ => fallbacks.push(fallback.clone()),
+            }
+        }
+        fallbacks
+    }
+
+    /// Get the ranges of clusters that have missing glyphs, in text order
+    ///
+    /// These are rendered with the missing glyph of a font, usually an empty box, because no
+    /// font covers them or the font covering them does not have a glyph for them.
+    pub fn missing(&self) -> Vec<Range<usize>> {
+        let mut missing = Vec::new();
+        for word in self.spans.iter().flat_map(|span| span.words.iter()) {
+            for range in word.missing.iter() {
+                add_range(&mut missing, range.clone());
+            }
+        }
+        missing
+    }
+
     pub fn layout(
         &self,
         font_size: i32,
@@ -672,3 +876,43 @@ impl ShapeLine {
         }
     }
 }
+
+#[test]
+fn test_add_range() {
+    let mut ranges = Vec::new();
+    add_range(&mut ranges, 10..12);
+    add_range(&mut ranges, 0..2);
+    add_range(&mut ranges, 4..6);
+    assert_eq!(ranges, [0..2, 4..6, 10..12]);
+    add_range(&mut ranges, 2..4);
+    assert_eq!(ranges, [0..6, 10..12]);
+    add_range(&mut ranges, 5..11);
+    assert_eq!(ranges.len(), 1);
+    assert_eq!(ranges[0], 0..12);
+}
+
+#[test]
+fn test_cluster_scripts() {
+    // Each CJK cluster keeps its own script, neutral clusters take the previous script
+    let scripts: Vec<Script> = cluster_scripts("한국、漢字", 0).into_iter().map(|egc| egc.2).collect();
+    assert_eq!(scripts, [Script::Hangul, Script::Hangul, Script::Hangul, Script::Han, Script::Han]);
+    let egcs = cluster_scripts("「漢한", 3);
+    assert_eq!(egcs, [(3, 6, Script::Han), (6, 9, Script::Han), (9, 12, Script::Hangul)]);
+}
+
+#[test]
+fn test_fallback_scripts() {
+    // CJK runs keep their own script first, and share the fallback fonts of the other CJK scripts
+    assert_eq!(
+        fallback_scripts('한'.script(), false),
+        [Script::Hangul, Script::Han, Script::Hiragana, Script::Katakana, Script::Bopomofo]
+    );
+    assert_eq!(
+        fallback_scripts('漢'.script(), false),
+        [Script::Han, Script::Hiragana, Script::Katakana, Script::Hangul, Script::Bopomofo]
+    );
+    assert_eq!(fallback_scripts('α'.script(), false), [Script::Greek]);
+    assert!(fallback_scripts('a'.script(), false).is_empty());
+    assert_eq!(fallback_scripts('a'.script(), true), [Script::Latin]);
+    assert!(fallback_scripts(Script::Common, true).is_empty());
+}
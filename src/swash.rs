@@ -1,15 +1,355 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
-use std::collections::HashMap;
-use swash::scale::{ScaleContext, image::Content};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use swash::scale::{ScaleContext, Scaler, image::Content};
+use swash::Usability;
 use swash::scale::{Render, Source, StrikeWith};
-use swash::zeno::{Format, Vector};
+use swash::zeno::{Angle, Command, Format, PathData, Placement, Transform, Vector};
 
-use crate::{CacheKey, Color, FontSystem};
+use crate::{CacheKey, CacheKeyFlags, Color, Font, FontSystem, Palette, SdfSettings, SubpixelBin};
+use crate::canvas::mul;
+use crate::sdf::sdf_image;
+use crate::cache::{FAKE_BOLD_EM, FAKE_ITALIC_DEGREES};
 
 pub use swash::scale::image::{Content as SwashContent, Image as SwashImage};
 
-fn swash_image<'a>(font_system: &'a FontSystem<'a>, context: &mut ScaleContext, cache_key: CacheKey) -> Option<SwashImage> {
+/// Layout of the color subpixels of a display
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SubpixelOrder {
+    /// Horizontal stripes, red on the left
+    Rgb,
+    /// Horizontal stripes, blue on the left
+    Bgr,
+    /// Vertical stripes, red on the top
+    Vrgb,
+    /// Vertical stripes, blue on the top
+    Vbgr,
+}
+
+impl SubpixelOrder {
+    fn vertical(self) -> bool {
+        matches!(self, Self::Vrgb | Self::Vbgr)
+    }
+
+    // Index of the color channel (red, green, blue) for each subpixel, in display order
+    fn channels(self) -> [usize; 3] {
+        match self {
+            Self::Rgb | Self::Vrgb => [0, 1, 2],
+            Self::Bgr | Self::Vbgr => [2, 1, 0],
+        }
+    }
+}
+
+/// Format of glyph masks rendered by [SwashCache]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum RenderFormat {
+    /// Grayscale antialiasing, with one coverage value per pixel
+    #[default]
+    Alpha,
+    /// Subpixel (LCD) antialiasing, with coverage for each color channel of a pixel
+    Subpixel(SubpixelOrder),
+    /// Single-channel signed distance field, as [SwashContent::Mask] images of distances
+    ///
+    /// Fields are rendered from unhinted outlines at [SdfSettings::base_size], whatever the font
+    /// size of the [CacheKey], and are drawn scaled by [SwashCache::image_scale]. Color glyphs are
+    /// rendered as bitmaps at the base size too.
+    Sdf(SdfSettings),
+    /// Multi-channel signed distance field, which keeps corners sharp when scaled
+    ///
+    /// Images are [SwashContent::SubpixelMask], with the distance in the median of the red,
+    /// green, and blue channels, and the single-channel distance in alpha. Color glyphs are
+    /// rendered as bitmaps.
+    Msdf(SdfSettings),
+}
+
+/// Weights of the filter applied to subpixel coverage, to reduce color fringing
+///
+/// Each subpixel is the weighted sum of itself and the two subpixels on each side. Weights are
+/// relative to their sum.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct SubpixelFilter(pub [u8; 5]);
+
+impl SubpixelFilter {
+    /// Filter used by FreeType by default, with little fringing and some blur
+    pub const DEFAULT: Self = Self([0x08, 0x4D, 0x56, 0x4D, 0x08]);
+    /// Sharper filter, with more fringing
+    pub const LIGHT: Self = Self([0x00, 0x55, 0x56, 0x55, 0x00]);
+    /// No filtering, with the most fringing
+    pub const NONE: Self = Self([0x00, 0x00, 0xFF, 0x00, 0x00]);
+}
+
+impl Default for SubpixelFilter {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Brightness of text, used to adjust coverage for how it appears on its background
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum TextBrightness {
+    /// Dark text on a light background, which appears bolder
+    #[default]
+    Dark,
+    /// Light text on a dark background, which appears thinner
+    Light,
+}
+
+impl TextBrightness {
+    /// Get the brightness of text with a color, from its luminance
+    pub fn from_color(color: Color) -> Self {
+        let luminance = 0.2126 * color.r() as f32 + 0.7152 * color.g() as f32 + 0.0722 * color.b() as f32;
+        if luminance >= 127.5 {
+            Self::Light
+        } else {
+            Self::Dark
+        }
+    }
+}
+
+/// Settings for rasterizing glyphs with [SwashCache]
+///
+/// The settings are part of the key of cached images, so glyphs are rendered again after
+/// changing them.
+#[derive(Clone, Copy, Debug)]
+pub struct RenderSettings {
+    /// Format of rendered masks
+    pub format: RenderFormat,
+    /// Filter used for [RenderFormat::Subpixel]
+    pub subpixel_filter: SubpixelFilter,
+    /// Hint outlines to the pixel grid
+    pub hinting: bool,
+    /// Gamma used to correct coverage, 1.0 for none. Higher values make light text bolder and
+    /// dark text thinner.
+    pub gamma: f32,
+    /// Contrast enhancement of coverage, from 0.0 for none to 1.0 for the most
+    pub contrast: f32,
+    /// Brightness of the text that glyphs will be drawn with
+    pub brightness: TextBrightness,
+}
+
+impl RenderSettings {
+    /// Create the default settings, with hinting and without coverage adjustment
+    pub const fn new() -> Self {
+        Self {
+            format: RenderFormat::Alpha,
+            subpixel_filter: SubpixelFilter::DEFAULT,
+            hinting: true,
+            gamma: 1.0,
+            contrast: 0.0,
+            brightness: TextBrightness::Dark,
+        }
+    }
+
+    // Table of adjusted coverage, if coverage is adjusted
+    fn coverage_table(&self) -> Option<[u8; 256]> {
+        if self.gamma == 1.0 && self.contrast == 0.0 {
+            return None;
+        }
+
+        let exponent = match self.brightness {
+            TextBrightness::Dark => self.gamma,
+            TextBrightness::Light => 1.0 / self.gamma,
+        };
+        let contrast = self.contrast.clamp(0.0, 1.0);
+        let mut table = [0; 256];
+        for (i, value) in table.iter_mut().enumerate() {
+            let mut coverage = i as f32 / 255.0;
+            // Steepen the curve around half coverage
+            coverage = ((coverage - 0.5) * (1.0 + contrast) + 0.5).clamp(0.0, 1.0);
+            coverage = coverage.powf(exponent);
+            *value = (coverage * 255.0).round() as u8;
+        }
+        Some(table)
+    }
+
+    // Adjust the coverage of a rendered mask
+    fn adjust(&self, image: &mut SwashImage) {
+        let table = match self.coverage_table() {
+            Some(some) => some,
+            None => return,
+        };
+        match image.content {
+            Content::Mask => for value in image.data.iter_mut() {
+                *value = table[*value as usize];
+            },
+            Content::SubpixelMask => for pixel in image.data.chunks_exact_mut(4) {
+                let mut total = 0;
+                for value in pixel[..3].iter_mut() {
+                    *value = table[*value as usize];
+                    total += *value as u32;
+                }
+                pixel[3] = ((total + 1) / 3) as u8;
+            },
+            Content::Color => (),
+        }
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Floats are compared and hashed by bits, so equal settings always find the same cached images
+impl PartialEq for RenderSettings {
+    fn eq(&self, other: &Self) -> bool {
+        self.format == other.format
+            && self.subpixel_filter == other.subpixel_filter
+            && self.hinting == other.hinting
+            && self.gamma.to_bits() == other.gamma.to_bits()
+            && self.contrast.to_bits() == other.contrast.to_bits()
+            && self.brightness == other.brightness
+    }
+}
+
+impl Eq for RenderSettings {}
+
+impl Hash for RenderSettings {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.format.hash(state);
+        self.subpixel_filter.hash(state);
+        self.hinting.hash(state);
+        self.gamma.to_bits().hash(state);
+        self.contrast.to_bits().hash(state);
+        self.brightness.hash(state);
+    }
+}
+
+// Convert a mask rendered at three times the resolution in one direction into a subpixel mask.
+// Each pixel of a subpixel mask has red, green, blue, and average coverage.
+fn subpixel_filter(mask: &SwashImage, order: SubpixelOrder, filter: SubpixelFilter) -> SwashImage {
+    let vertical = order.vertical();
+    let channels = order.channels();
+    let weight_sum = filter.0.iter().map(|&w| w as u32).sum::<u32>().max(1);
+
+    let mask_w = mask.placement.width as i32;
+    let mask_h = mask.placement.height as i32;
+    // Position of the first subpixel of the mask, counted right and down
+    let (start, len) = if vertical {
+        (-mask.placement.top, mask_h)
+    } else {
+        (mask.placement.left, mask_w)
+    };
+    let coverage = |sub: i32, other: i32| -> u32 {
+        let i = sub - start;
+        if i < 0 || i >= len {
+            0
+        } else if vertical {
+            mask.data[(i * mask_w + other) as usize] as u32
+        } else {
+            mask.data[(other * mask_w + i) as usize] as u32
+        }
+    };
+
+    // The filter spreads coverage by two subpixels on each side
+    let first = (start - 2).div_euclid(3);
+    let last = (start + len + 1).div_euclid(3);
+    let (width, height, other_len) = if vertical {
+        (mask_w, last - first + 1, mask_w)
+    } else {
+        (last - first + 1, mask_h, mask_h)
+    };
+
+    let mut image = SwashImage::new();
+    image.source = mask.source;
+    image.content = Content::SubpixelMask;
+    image.placement = if vertical {
+        Placement { left: mask.placement.left, top: -first, width: width as u32, height: height as u32 }
+    } else {
+        Placement { left: first, top: mask.placement.top, width: width as u32, height: height as u32 }
+    };
+    image.data.resize((width * height * 4) as usize, 0);
+    if len == 0 {
+        return image;
+    }
+
+    for other in 0..other_len {
+        for pixel in first..=last {
+            let (x, y) = if vertical { (other, pixel - first) } else { (pixel - first, other) };
+            let i = ((y * width + x) * 4) as usize;
+            let mut total = 0;
+            for (sub, &channel) in channels.iter().enumerate() {
+                let center = pixel * 3 + sub as i32;
+                let mut sum = 0;
+                for (k, &weight) in filter.0.iter().enumerate() {
+                    sum += weight as u32 * coverage(center + k as i32 - 2, other);
+                }
+                let value = ((sum + weight_sum / 2) / weight_sum).min(255);
+                image.data[i + channel] = value as u8;
+                total += value;
+            }
+            image.data[i + 3] = ((total + 1) / 3) as u8;
+        }
+    }
+
+    image
+}
+
+/// Command of a glyph outline path, with coordinates in pixels increasing right and down
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PathCommand {
+    /// Start a new subpath at a point
+    MoveTo(f32, f32),
+    /// Line to a point
+    LineTo(f32, f32),
+    /// Quadratic curve with a control point, to a point
+    QuadTo(f32, f32, f32, f32),
+    /// Cubic curve with two control points, to a point
+    CurveTo(f32, f32, f32, f32, f32, f32),
+    /// Close the subpath with a line to its start
+    Close,
+}
+
+impl PathCommand {
+    /// Move the command by an offset
+    pub fn translate(self, dx: f32, dy: f32) -> Self {
+        match self {
+            Self::MoveTo(x, y) => Self::MoveTo(x + dx, y + dy),
+            Self::LineTo(x, y) => Self::LineTo(x + dx, y + dy),
+            Self::QuadTo(x1, y1, x, y) => Self::QuadTo(x1 + dx, y1 + dy, x + dx, y + dy),
+            Self::CurveTo(x1, y1, x2, y2, x, y) => Self::CurveTo(
+                x1 + dx, y1 + dy,
+                x2 + dx, y2 + dy,
+                x + dx, y + dy,
+            ),
+            Self::Close => Self::Close,
+        }
+    }
+
+    /// Get the commands of a closed rectangle path
+    pub fn rect(x: f32, y: f32, w: f32, h: f32) -> [Self; 5] {
+        [
+            Self::MoveTo(x, y),
+            Self::LineTo(x + w, y),
+            Self::LineTo(x + w, y + h),
+            Self::LineTo(x, y + h),
+            Self::Close,
+        ]
+    }
+}
+
+// Amount to embolden outlines and transform to skew them, for synthesized styles
+fn synthesis(cache_key: CacheKey) -> (f32, Option<Transform>) {
+    let embolden = if cache_key.flags.contains(CacheKeyFlags::FAKE_BOLD) {
+        cache_key.font_size as f32 * FAKE_BOLD_EM
+    } else {
+        0.0
+    };
+    let transform = if cache_key.flags.contains(CacheKeyFlags::FAKE_ITALIC) {
+        Some(Transform::skew(Angle::from_degrees(FAKE_ITALIC_DEGREES), Angle::from_degrees(0.0)))
+    } else {
+        None
+    };
+    (embolden, transform)
+}
+
+fn swash_outline<'a>(
+    font_system: &'a FontSystem<'a>,
+    context: &mut ScaleContext,
+    cache_key: CacheKey,
+) -> Option<Vec<PathCommand>> {
     let font = match font_system.get_font(cache_key.font_id) {
         Some(some) => some,
         None => {
@@ -18,11 +358,87 @@ fn swash_image<'a>(font_system: &'a FontSystem<'a>, context: &mut ScaleContext,
         },
     };
 
-    // Build the scaler
+    // Outlines are not hinted, so they stay accurate when scaled
+    let variations = font.instance_opt.iter().flat_map(|instance| {
+        instance.variations.iter().map(|variation| swash::Setting {
+            tag: variation.tag.0,
+            value: variation.value,
+        })
+    });
     let mut scaler = context
         .builder(font.as_swash())
         .size(cache_key.font_size as f32)
-        .hint(true)
+        .variations(variations)
+        .build();
+    outline_commands(&mut scaler, cache_key)
+}
+
+fn outline_commands(scaler: &mut Scaler, cache_key: CacheKey) -> Option<Vec<PathCommand>> {
+    let mut outline = scaler.scale_outline(cache_key.glyph_id)?;
+
+    let (embolden, transform_opt) = synthesis(cache_key);
+    if embolden != 0.0 {
+        outline.embolden(embolden, embolden);
+    }
+    if let Some(transform) = transform_opt {
+        outline.transform(&transform);
+    }
+
+    // Apply the fractional offset like images, and flip to increase downward
+    let dx = cache_key.x_bin.as_float();
+    let dy = cache_key.y_bin.as_float();
+    let point = |p: Vector| (p.x + dx, -(p.y + dy));
+    let commands = outline.path().commands().map(|command| match command {
+        Command::MoveTo(p) => {
+            let (x, y) = point(p);
+            PathCommand::MoveTo(x, y)
+        },
+        Command::LineTo(p) => {
+            let (x, y) = point(p);
+            PathCommand::LineTo(x, y)
+        },
+        Command::QuadTo(p1, p) => {
+            let (x1, y1) = point(p1);
+            let (x, y) = point(p);
+            PathCommand::QuadTo(x1, y1, x, y)
+        },
+        Command::CurveTo(p1, p2, p) => {
+            let (x1, y1) = point(p1);
+            let (x2, y2) = point(p2);
+            let (x, y) = point(p);
+            PathCommand::CurveTo(x1, y1, x2, y2, x, y)
+        },
+        Command::Close => PathCommand::Close,
+    }).collect();
+    Some(commands)
+}
+
+fn swash_image<'a>(
+    font_system: &'a FontSystem<'a>,
+    context: &mut ScaleContext,
+    cache_key: CacheKey,
+    settings: &RenderSettings,
+) -> Option<SwashImage> {
+    let font = match font_system.get_font(cache_key.font_id) {
+        Some(some) => some,
+        None => {
+            log::warn!("did not find font {:?}", cache_key.font_id);
+            return None;
+        },
+    };
+
+    // Build the scaler, using the axis values of named instances
+    let variations = font.instance_opt.iter().flat_map(|instance| {
+        instance.variations.iter().map(|variation| swash::Setting {
+            tag: variation.tag.0,
+            value: variation.value,
+        })
+    });
+    let mut scaler = context
+        .builder(font.as_swash())
+        .size(cache_key.font_size as f32)
+        .variations(variations)
+        .hint(settings.hinting && matches!(settings.format, RenderFormat::Alpha | RenderFormat::Subpixel(_)))
         .build();
 
     // Compute the fractional offset-- you'll likely want to quantize this
@@ -30,104 +446,720 @@ fn swash_image<'a>(font_system: &'a FontSystem<'a>, context: &mut ScaleContext,
     let offset =
         Vector::new(cache_key.x_bin.as_float(), cache_key.y_bin.as_float());
 
-    // Select our source order
-    Render::new(&[
-        // Color outline with the first palette
-        Source::ColorOutline(0),
+    // Synthesize missing styles
+    let (embolden, transform) = synthesis(cache_key);
+
+    // Color glyphs are rendered as they are in every format
+    let color_opt = color_image(&font, &mut scaler, cache_key, offset, transform, settings);
+    if color_opt.is_some() {
+        return color_opt;
+    }
+
+    let mut image = match settings.format {
+        RenderFormat::Alpha => {
+            // Select our source order
+            Render::new(&[
+                // Standard scalable outline
+                Source::Outline,
+            ])
+            // Select a subpixel format
+            .format(Format::Alpha)
+            // Apply the fractional offset
+            .offset(offset)
+            // Apply synthetic bold and italic
+            .embolden(embolden)
+            .transform(transform)
+            // Render the image
+            .render(&mut scaler, cache_key.glyph_id)?
+        },
+        RenderFormat::Subpixel(order) => subpixel_image(
+            &mut scaler,
+            cache_key.glyph_id,
+            offset,
+            embolden,
+            transform,
+            order,
+            settings.subpixel_filter
+        )?,
+        RenderFormat::Sdf(sdf) | RenderFormat::Msdf(sdf) => {
+            // Distances are not coverage, so they are not adjusted
+            let commands = outline_commands(&mut scaler, cache_key)?;
+            let multi = matches!(settings.format, RenderFormat::Msdf(_));
+            return sdf_image(&commands, sdf, multi);
+        },
+    };
+    settings.adjust(&mut image);
+    Some(image)
+}
+
+// Index of the palette to use from the CPAL table of a font
+fn palette_index(font: &swash::FontRef, palette: Palette) -> u16 {
+    let find = |usability: Usability| {
+        font.color_palettes()
+            .find(|palette| matches!(palette.usability(), Some(some) if some == usability || some == Usability::Both))
+            .map_or(0, |palette| palette.index())
+    };
+    match palette {
+        Palette::Default => 0,
+        Palette::Index(index) => {
+            if (index as usize) < font.color_palettes().count() {
+                index
+            } else {
+                0
+            }
+        },
+        Palette::Light => find(Usability::Light),
+        Palette::Dark => find(Usability::Dark),
+    }
+}
+
+// Render a glyph from color outlines, bitmaps, or SVG documents, if it has them
+#[cfg_attr(not(feature = "color"), allow(unused_variables))]
+fn color_image(
+    font: &Font,
+    scaler: &mut Scaler,
+    cache_key: CacheKey,
+    offset: Vector,
+    transform: Option<Transform>,
+    settings: &RenderSettings,
+) -> Option<SwashImage> {
+    let palette = palette_index(&font.as_swash(), cache_key.palette);
+
+    // Gradients and other paints of COLRv1 are not supported by swash
+    #[cfg(feature = "color")]
+    if let Some(image) = crate::color::colr_image(font, cache_key, offset, transform, palette, settings.brightness) {
+        return Some(image);
+    }
+
+    let image_opt = Render::new(&[
+        // Color outline with the selected palette
+        Source::ColorOutline(palette),
         // Color bitmap with best fit selection mode
         Source::ColorBitmap(StrikeWith::BestFit),
-        // Standard scalable outline
-        Source::Outline,
     ])
-    // Select a subpixel format
-    .format(Format::Alpha)
-    // Apply the fractional offset
     .offset(offset)
-    // Render the image
-    .render(&mut scaler, cache_key.glyph_id)
+    .transform(transform)
+    .render(scaler, cache_key.glyph_id);
+
+    // SVG documents are not supported by swash
+    #[cfg(feature = "color")]
+    if image_opt.is_none() {
+        return crate::color::svg_image(font, cache_key, offset, transform, palette, settings.brightness);
+    }
+
+    image_opt
+}
+
+fn subpixel_image(
+    scaler: &mut Scaler,
+    glyph_id: u16,
+    offset: Vector,
+    embolden: f32,
+    transform: Option<Transform>,
+    order: SubpixelOrder,
+    filter: SubpixelFilter,
+) -> Option<SwashImage> {
+    // Render the outline at three times the resolution across the subpixels, then filter it
+    let (sx, sy) = if order.vertical() { (1.0, 3.0) } else { (3.0, 1.0) };
+    let offset = Vector::new(offset.x * sx, offset.y * sy);
+    // Scaled by hand, as Transform::then swaps the skew factors
+    let transform = match transform {
+        Some(t) => Transform::new(t.xx * sx, t.xy * sy, t.yx * sx, t.yy * sy, t.x * sx, t.y * sy),
+        None => Transform::scale(sx, sy),
+    };
+    let mask = Render::new(&[Source::Outline])
+        .format(Format::Alpha)
+        .offset(offset)
+        .embolden(embolden)
+        .transform(Some(transform))
+        .render(scaler, glyph_id)?;
+    Some(subpixel_filter(&mask, order, filter))
+}
+
+/// Limits on the images cached by [SwashCache]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct CacheBudget {
+    /// Maximum bytes of image data, unlimited if `None`
+    pub max_bytes_opt: Option<usize>,
+    /// Maximum number of images, unlimited if `None`
+    pub max_images_opt: Option<usize>,
+}
+
+/// Statistics of the images cached by [SwashCache]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct CacheStats {
+    /// Number of images found in the cache
+    pub hits: u64,
+    /// Number of images rendered because they were not in the cache
+    pub misses: u64,
+    /// Number of images evicted to stay within the budget
+    pub evictions: u64,
+    /// Number of cached images
+    pub images: usize,
+    /// Bytes of cached image data
+    pub bytes: usize,
+}
+
+type ImageKey = (CacheKey, RenderSettings);
+
+struct CachedImage {
+    image_opt: Option<SwashImage>,
+    // Access counter value when last used, the key of the LRU order
+    tick: u64,
+    // Frame when last used
+    frame: u64,
+}
+
+impl CachedImage {
+    fn bytes(&self) -> usize {
+        self.image_opt.as_ref().map_or(0, |image| image.data.len())
+    }
+}
+
+/// Alpha of the colors passed to pixel callbacks
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum AlphaMode {
+    /// Color components are independent of alpha
+    #[default]
+    Straight,
+    /// Color components are multiplied by alpha
+    Premultiplied,
+}
+
+/// How the base color applies to color glyphs, like emoji
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum ColorGlyphBlend {
+    /// Use the colors of the image, ignoring the base color
+    Ignore,
+    /// Multiply the alpha of the image by the alpha of the base color, fading color glyphs with
+    /// the text around them
+    #[default]
+    Opacity,
+    /// Multiply the colors and alpha of the image by the base color
+    Tint,
+}
+
+impl ColorGlyphBlend {
+    // Combine a straight RGBA pixel of a color image with the base color
+    #[inline]
+    pub(crate) fn apply(self, pixel: &[u8], base: Color) -> [u8; 4] {
+        match self {
+            Self::Ignore => [pixel[0], pixel[1], pixel[2], pixel[3]],
+            Self::Opacity => [pixel[0], pixel[1], pixel[2], mul(pixel[3], base.a())],
+            Self::Tint => [
+                mul(pixel[0], base.r()),
+                mul(pixel[1], base.g()),
+                mul(pixel[2], base.b()),
+                mul(pixel[3], base.a()),
+            ],
+        }
+    }
+}
+
+/// Settings for compositing glyph images with a base color, see [SwashCache::with_pixels]
+///
+/// Mask coverage is always multiplied by the alpha of the base color.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct BlendSettings {
+    /// Alpha of the output colors
+    pub alpha: AlphaMode,
+    /// How the base color applies to color glyphs
+    pub color_glyphs: ColorGlyphBlend,
+}
+
+impl BlendSettings {
+    /// Straight alpha, with color glyphs faded by the alpha of the base color
+    pub const fn new() -> Self {
+        Self {
+            alpha: AlphaMode::Straight,
+            color_glyphs: ColorGlyphBlend::Opacity,
+        }
+    }
+
+    // Convert a straight color to the output alpha mode
+    #[inline]
+    pub(crate) fn output(&self, r: u8, g: u8, b: u8, a: u8) -> Color {
+        match self.alpha {
+            AlphaMode::Straight => Color::rgba(r, g, b, a),
+            AlphaMode::Premultiplied => Color::rgba(mul(r, a), mul(g, a), mul(b, a), a),
+        }
+    }
+}
+
+/// Enumerate pixels in an image, relative to the glyph origin, see [SwashCache::with_pixels]
+pub fn image_pixels<F: FnMut(i32, i32, Color)>(image: &SwashImage, base: Color, blend: &BlendSettings, mut f: F) {
+    let x = image.placement.left;
+    let y = -image.placement.top;
+    let (r, g, b) = (base.r(), base.g(), base.b());
+
+    let mut i = 0;
+    for off_y in 0..image.placement.height as i32 {
+        for off_x in 0..image.placement.width as i32 {
+            let color = match image.content {
+                Content::Mask => {
+                    let a = mul(image.data[i], base.a());
+                    i += 1;
+                    blend.output(r, g, b, a)
+                }
+                Content::SubpixelMask => {
+                    let a = mul(image.data[i + 3], base.a());
+                    i += 4;
+                    blend.output(r, g, b, a)
+                }
+                Content::Color => {
+                    let [r, g, b, a] = blend.color_glyphs.apply(&image.data[i..i + 4], base);
+                    i += 4;
+                    blend.output(r, g, b, a)
+                }
+            };
+            f(x + off_x, y + off_y, color);
+        }
+    }
 }
 
 /// Cache for rasterizing with the swash scaler
 pub struct SwashCache<'a> {
     font_system: &'a FontSystem<'a>,
+    font_generation: usize,
     context: ScaleContext,
-    pub image_cache: HashMap<CacheKey, Option<SwashImage>>,
+    /// Settings used to rasterize glyphs
+    pub settings: RenderSettings,
+    /// Settings used to composite glyph pixels with a base color
+    pub blend: BlendSettings,
+    image_cache: HashMap<ImageKey, CachedImage>,
+    lru: BTreeMap<u64, ImageKey>,
+    tick: u64,
+    frame: u64,
+    budget: CacheBudget,
+    stats: CacheStats,
 }
 
 impl<'a> SwashCache<'a> {
-    /// Create a new swash cache
+    /// Create a new swash cache, with an unlimited budget
     pub fn new(font_system: &'a FontSystem<'a>) -> Self {
         Self {
             font_system: font_system,
+            font_generation: font_system.generation(),
             context: ScaleContext::new(),
-            image_cache: HashMap::new()
+            settings: RenderSettings::new(),
+            blend: BlendSettings::new(),
+            image_cache: HashMap::new(),
+            lru: BTreeMap::new(),
+            tick: 0,
+            frame: 0,
+            budget: CacheBudget::default(),
+            stats: CacheStats::default(),
         }
     }
 
     /// Create a swash Image from a cache key, without caching results
     pub fn get_image_uncached(&mut self, cache_key: CacheKey) -> Option<SwashImage> {
-        swash_image(self.font_system, &mut self.context, cache_key)
+        let cache_key = self.image_key(cache_key);
+        swash_image(self.font_system, &mut self.context, cache_key, &self.settings)
+    }
+
+    /// Get the scale to draw the image of a glyph at, which is 1.0 except for distance fields
+    ///
+    /// Distance fields are rendered at [SdfSettings::base_size], and are drawn scaled to the font
+    /// size of the cache key, including the placement of the image.
+    pub fn image_scale(&self, cache_key: CacheKey) -> f32 {
+        match self.settings.format {
+            RenderFormat::Sdf(sdf) | RenderFormat::Msdf(sdf) => sdf.scale(cache_key.font_size),
+            _ => 1.0,
+        }
+    }
+
+    // Key of the image rendered for a glyph, distance fields are the same for every font size and
+    // subpixel offset
+    pub(crate) fn image_key(&self, cache_key: CacheKey) -> CacheKey {
+        match self.settings.format {
+            RenderFormat::Sdf(sdf) | RenderFormat::Msdf(sdf) => CacheKey {
+                font_size: sdf.base_size.max(1) as i32,
+                x_bin: SubpixelBin::Zero,
+                y_bin: SubpixelBin::Zero,
+                ..cache_key
+            },
+            _ => cache_key,
+        }
     }
 
     /// Create a swash Image from a cache key, caching results
+    ///
+    /// Images are cached for each [RenderSettings] they were rendered with. When over the budget,
+    /// the least recently used images are evicted, see [SwashCache::begin_frame]. Cached images
+    /// are cleared if fonts were reloaded with [FontSystem::reload].
     pub fn get_image(&mut self, cache_key: CacheKey) -> &Option<SwashImage> {
-        self.image_cache.entry(cache_key).or_insert_with(|| {
-            swash_image(self.font_system, &mut self.context, cache_key)
-        })
+        let font_generation = self.font_system.generation();
+        if font_generation != self.font_generation {
+            self.clear();
+            self.font_generation = font_generation;
+        }
+
+        let cache_key = self.image_key(cache_key);
+        let key = (cache_key, self.settings);
+        self.tick += 1;
+        if let Some(cached) = self.image_cache.get_mut(&key) {
+            self.lru.remove(&cached.tick);
+            cached.tick = self.tick;
+            cached.frame = self.frame;
+            self.stats.hits += 1;
+        } else {
+            let cached = CachedImage {
+                image_opt: swash_image(self.font_system, &mut self.context, cache_key, &self.settings),
+                tick: self.tick,
+                frame: self.frame,
+            };
+            self.stats.misses += 1;
+            self.stats.images += 1;
+            self.stats.bytes += cached.bytes();
+            self.image_cache.insert(key, cached);
+        }
+        self.lru.insert(self.tick, key);
+
+        self.evict(self.budget);
+        &self.image_cache[&key].image_opt
+    }
+
+    /// Get the outline of a glyph as path commands, without caching results
+    ///
+    /// Coordinates are relative to the glyph position, like images. Outlines are not hinted, and
+    /// glyphs that only have bitmaps, like some emoji, return `None`.
+    pub fn get_outline(&mut self, cache_key: CacheKey) -> Option<Vec<PathCommand>> {
+        swash_outline(self.font_system, &mut self.context, cache_key)
+    }
+
+    /// Get the limits on cached images
+    pub fn budget(&self) -> CacheBudget {
+        self.budget
+    }
+
+    /// Set the limits on cached images, evicting images if over the new budget
+    pub fn set_budget(&mut self, budget: CacheBudget) {
+        self.budget = budget;
+        self.evict(budget);
+    }
+
+    /// Get statistics of cached images
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Start a new frame
+    ///
+    /// Images used since the start of the current frame are never evicted, so that images
+    /// returned while drawing a frame stay cached until it is done, even if that exceeds the
+    /// budget. Images from earlier frames are evicted as needed when the frame starts. If frames
+    /// are not used, any image but the one just returned may be evicted.
+    pub fn begin_frame(&mut self) {
+        self.frame += 1;
+        self.evict(self.budget);
     }
 
-    /// Enumerate pixels in an Image, use `with_image` for better performance
+    /// Evict least recently used images until cached image data is at most `max_bytes`
+    ///
+    /// Like eviction to stay within the budget, this keeps images used in the current frame.
+    pub fn trim(&mut self, max_bytes: usize) {
+        self.evict(CacheBudget {
+            max_bytes_opt: Some(max_bytes),
+            max_images_opt: None,
+        });
+    }
+
+    /// Remove all cached images
+    pub fn clear(&mut self) {
+        self.image_cache.clear();
+        self.lru.clear();
+        self.stats.images = 0;
+        self.stats.bytes = 0;
+    }
+
+    // Evict least recently used images until within the budget
+    fn evict(&mut self, budget: CacheBudget) {
+        let max_bytes = budget.max_bytes_opt.unwrap_or(usize::MAX);
+        let max_images = budget.max_images_opt.unwrap_or(usize::MAX);
+        while self.stats.bytes > max_bytes || self.stats.images > max_images {
+            let (tick, key) = match self.lru.iter().next() {
+                Some((&tick, &key)) => (tick, key),
+                None => break,
+            };
+            // Images are ordered by last use, so the rest were used in the current frame too
+            let cached = &self.image_cache[&key];
+            if tick == self.tick || (self.frame > 0 && cached.frame == self.frame) {
+                break;
+            }
+
+            self.stats.bytes -= cached.bytes();
+            self.stats.images -= 1;
+            self.stats.evictions += 1;
+            self.image_cache.remove(&key);
+            self.lru.remove(&tick);
+        }
+    }
+
+    /// Enumerate pixels in an Image, use [SwashCache::get_image] for better performance
+    ///
+    /// Colors are composited with [SwashCache::blend], mask coverage is multiplied by the alpha of
+    /// `base`. Subpixel masks are enumerated with their average coverage, use
+    /// [SwashCache::with_subpixels] to blend each color channel separately.
     pub fn with_pixels<F: FnMut(i32, i32, Color)>(
+        &mut self,
+        cache_key: CacheKey,
+        base: Color,
+        f: F
+    ) {
+        let blend = self.blend;
+        if let Some(image) = self.get_image(cache_key) {
+            image_pixels(image, base, &blend, f);
+        }
+    }
+
+    /// Enumerate pixels in an Image, with the coverage of each color channel
+    ///
+    /// The callback receives a color and the red, green, and blue coverage of the pixel. Each
+    /// channel of the destination should be blended with the color using the color alpha times
+    /// the channel coverage. The color is the base color for masks, and the pixel color for color
+    /// images, which have full coverage. Grayscale masks have the same coverage in each channel.
+    /// Colors are composited with [SwashCache::blend].
+    pub fn with_subpixels<F: FnMut(i32, i32, Color, [u8; 3])>(
         &mut self,
         cache_key: CacheKey,
         base: Color,
         mut f: F
     ) {
+        let blend = self.blend;
         if let Some(image) = self.get_image(cache_key) {
+            let base_color = blend.output(base.r(), base.g(), base.b(), base.a());
             let x = image.placement.left;
             let y = -image.placement.top;
 
-            match image.content {
-                Content::Mask => {
-                    let mut i = 0;
-                    for off_y in 0..image.placement.height as i32 {
-                        for off_x in 0..image.placement.width as i32 {
-                            //TODO: blend base alpha?
-                            f(
-                                x + off_x,
-                                y + off_y,
-                                Color(
-                                    ((image.data[i] as u32) << 24) |
-                                    base.0 & 0xFFFFFF
-                                )
-                            );
+            let mut i = 0;
+            for off_y in 0..image.placement.height as i32 {
+                for off_x in 0..image.placement.width as i32 {
+                    match image.content {
+                        Content::Mask => {
+                            let a = image.data[i];
+                            f(x + off_x, y + off_y, base_color, [a, a, a]);
                             i += 1;
                         }
-                    }
-                }
-                Content::Color => {
-                    let mut i = 0;
-                    for off_y in 0..image.placement.height as i32 {
-                        for off_x in 0..image.placement.width as i32 {
-                            //TODO: blend base alpha?
-                            f(
-                                x + off_x,
-                                y + off_y,
-                                Color::rgba(
-                                    image.data[i],
-                                    image.data[i + 1],
-                                    image.data[i + 2],
-                                    image.data[i + 3]
-                                )
-                            );
+                        Content::SubpixelMask => {
+                            let coverage = [image.data[i], image.data[i + 1], image.data[i + 2]];
+                            f(x + off_x, y + off_y, base_color, coverage);
+                            i += 4;
+                        }
+                        Content::Color => {
+                            let [r, g, b, a] = blend.color_glyphs.apply(&image.data[i..i + 4], base);
+                            f(x + off_x, y + off_y, blend.output(r, g, b, a), [0xFF; 3]);
                             i += 4;
                         }
                     }
                 }
-                Content::SubpixelMask => {
-                    log::warn!("TODO: SubpixelMask");
-                }
             }
         }
     }
 }
+
+#[test]
+fn test_image_pixels() {
+    let pixels = |image: &SwashImage, base: Color, blend: BlendSettings| {
+        let mut pixels = Vec::new();
+        image_pixels(image, base, &blend, |x, y, color| pixels.push((x, y, color.0)));
+        pixels
+    };
+
+    // Mask coverage is multiplied by the base alpha
+    let mut image = SwashImage::new();
+    image.placement = Placement { left: 1, top: 2, width: 2, height: 1 };
+    image.data = vec![0xFF, 0x80];
+    let base = Color::rgba(0xFF, 0, 0, 0x80);
+    let mut blend = BlendSettings::new();
+    assert_eq!(pixels(&image, base, blend), [(1, -2, 0x80FF0000), (2, -2, 0x40FF0000)]);
+    blend.alpha = AlphaMode::Premultiplied;
+    assert_eq!(pixels(&image, base, blend), [(1, -2, 0x80800000), (2, -2, 0x40400000)]);
+
+    // Color images are combined with the base color as selected
+    image.content = Content::Color;
+    image.placement.width = 1;
+    image.data = vec![0x40, 0x80, 0xFF, 0xFF];
+    let base = Color::rgba(0x80, 0xFF, 0, 0x80);
+    let mut blend = BlendSettings::new();
+    assert_eq!(pixels(&image, base, blend), [(1, -2, 0x804080FF)]);
+    blend.color_glyphs = ColorGlyphBlend::Ignore;
+    assert_eq!(pixels(&image, base, blend), [(1, -2, 0xFF4080FF)]);
+    blend.color_glyphs = ColorGlyphBlend::Tint;
+    assert_eq!(pixels(&image, base, blend), [(1, -2, 0x80208000)]);
+    blend.color_glyphs = ColorGlyphBlend::Opacity;
+    blend.alpha = AlphaMode::Premultiplied;
+    assert_eq!(pixels(&image, base, blend), [(1, -2, 0x80204080)]);
+}
+
+#[test]
+fn test_subpixel_filter() {
+    // One pixel wide vertical line, covering the subpixels of the second pixel
+    let mut mask = SwashImage::new();
+    mask.placement = Placement { left: 3, top: 1, width: 3, height: 1 };
+    mask.data = vec![0xFF; 3];
+
+    let image = subpixel_filter(&mask, SubpixelOrder::Rgb, SubpixelFilter::NONE);
+    assert_eq!(image.content, Content::SubpixelMask);
+    assert_eq!((image.placement.left, image.placement.width), (0, 3));
+    assert_eq!(image.data, [
+        0, 0, 0, 0,
+        0xFF, 0xFF, 0xFF, 0xFF,
+        0, 0, 0, 0,
+    ]);
+
+    // Filtering spreads coverage into the neighboring subpixels
+    let image = subpixel_filter(&mask, SubpixelOrder::Rgb, SubpixelFilter::DEFAULT);
+    assert_eq!(&image.data[..4], [0, 8, 85, 31]);
+    assert_eq!(&image.data[4..8], [170, 239, 170, 193]);
+    assert_eq!(&image.data[8..], [85, 8, 0, 31]);
+
+    // Subpixel order swaps red and blue
+    let image = subpixel_filter(&mask, SubpixelOrder::Bgr, SubpixelFilter::DEFAULT);
+    assert_eq!(&image.data[..4], [85, 8, 0, 31]);
+
+    // Vertical subpixels
+    mask.placement = Placement { left: 0, top: -3, width: 1, height: 3 };
+    let image = subpixel_filter(&mask, SubpixelOrder::Vrgb, SubpixelFilter::DEFAULT);
+    assert_eq!((image.placement.top, image.placement.height), (0, 3));
+    assert_eq!(&image.data[4..8], [170, 239, 170, 193]);
+}
+
+#[cfg(feature = "test-fonts")]
+#[test]
+fn test_subpixel_fake_italic() {
+    let font_system = FontSystem::new_test();
+    let mut cache_key = crate::buffer::test_cache_keys(&font_system, 32, "l")[0];
+    cache_key.flags = CacheKeyFlags::FAKE_ITALIC;
+
+    // Subpixel images are slanted as much as grayscale ones
+    let mut cache = SwashCache::new(&font_system);
+    let alpha = cache.get_image(cache_key).clone().unwrap();
+    cache.settings.format = RenderFormat::Subpixel(SubpixelOrder::Rgb);
+    let subpixel = cache.get_image(cache_key).clone().unwrap();
+    assert_eq!(subpixel.content, Content::SubpixelMask);
+    assert!(alpha.placement.width > 8);
+    assert!(subpixel.placement.width.abs_diff(alpha.placement.width) <= 2);
+}
+
+#[test]
+fn test_coverage_table() {
+    assert_eq!(RenderSettings::new().coverage_table(), None);
+
+    // Gamma makes light text bolder and dark text thinner, keeping no and full coverage
+    let mut settings = RenderSettings { gamma: 1.8, ..RenderSettings::new() };
+    let dark = settings.coverage_table().unwrap();
+    settings.brightness = TextBrightness::Light;
+    let light = settings.coverage_table().unwrap();
+    assert_eq!((dark[0], dark[255], light[0], light[255]), (0, 255, 0, 255));
+    assert!(dark[128] < 128 && light[128] > 128);
+
+    // Contrast pushes coverage away from half
+    let settings = RenderSettings { contrast: 0.5, ..RenderSettings::new() };
+    let table = settings.coverage_table().unwrap();
+    assert!(table[64] < 64 && table[192] > 192);
+    assert_eq!(table[0], 0);
+
+    assert_eq!(TextBrightness::from_color(Color::rgb(0xFF, 0xFF, 0xFF)), TextBrightness::Light);
+    assert_eq!(TextBrightness::from_color(Color::rgb(0x20, 0x20, 0x80)), TextBrightness::Dark);
+}
+
+#[test]
+fn test_render_settings_key() {
+    use std::collections::hash_map::DefaultHasher;
+
+    fn hash(settings: &RenderSettings) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        settings.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    // Equal settings hash equally, so a NaN setting still finds its cached images
+    let nan = RenderSettings { gamma: f32::NAN, ..RenderSettings::new() };
+    assert_eq!(nan, nan);
+    assert_eq!(hash(&nan), hash(&nan));
+    let zero = RenderSettings { contrast: 0.0, ..RenderSettings::new() };
+    let negative_zero = RenderSettings { contrast: -0.0, ..RenderSettings::new() };
+    assert_ne!(zero, negative_zero);
+    assert_eq!(zero, RenderSettings::new());
+    assert_eq!(hash(&zero), hash(&RenderSettings::new()));
+}
+
+#[cfg(feature = "test-fonts")]
+#[test]
+fn test_image_cache_budget() {
+    let font_system = FontSystem::new_test();
+    let cache_keys = crate::buffer::test_cache_keys(&font_system, 16, "abcdef");
+    assert_eq!(cache_keys.len(), 6);
+
+    let mut cache = SwashCache::new(&font_system);
+    cache.set_budget(CacheBudget { max_bytes_opt: None, max_images_opt: Some(4) });
+    for &cache_key in cache_keys.iter() {
+        assert!(cache.get_image(cache_key).is_some());
+    }
+    cache.get_image(cache_keys[5]);
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses, stats.evictions, stats.images), (1, 6, 2, 4));
+
+    // The least recently used images were evicted
+    cache.get_image(cache_keys[0]);
+    assert_eq!(cache.stats().misses, 7);
+    cache.get_image(cache_keys[5]);
+    assert_eq!(cache.stats().hits, 2);
+
+    // Images used in the current frame are kept over budget, until the next frame
+    cache.begin_frame();
+    for &cache_key in cache_keys.iter() {
+        cache.get_image(cache_key);
+    }
+    assert_eq!(cache.stats().images, 6);
+    cache.begin_frame();
+    assert_eq!(cache.stats().images, 4);
+
+    let bytes = cache.stats().bytes;
+    assert!(bytes > 0);
+    cache.begin_frame();
+    cache.trim(bytes / 2);
+    assert!(cache.stats().bytes <= bytes / 2);
+    cache.clear();
+    assert_eq!((cache.stats().images, cache.stats().bytes), (0, 0));
+}
+
+#[cfg(feature = "test-fonts")]
+#[test]
+fn test_sdf_format() {
+    let font_system = FontSystem::new_test();
+    let cache_keys = crate::buffer::test_cache_keys(&font_system, 32, "A💁");
+
+    let mut cache = SwashCache::new(&font_system);
+    let sdf = SdfSettings { spread: 4, padding: 6, ..SdfSettings::new() };
+    cache.settings.format = RenderFormat::Msdf(sdf);
+    let image = cache.get_image(cache_keys[0]).clone().unwrap();
+    assert_eq!(image.content, Content::SubpixelMask);
+    // Padding surrounds the outline, so edge pixels are outside
+    assert!(image.placement.width > 12 && image.placement.height > 12);
+    assert!(image.data[..4].iter().all(|&value| value < 128));
+
+    // Color glyphs fall back to bitmaps
+    let image = cache.get_image(cache_keys[1]).clone().unwrap();
+    assert_eq!(image.content, Content::Color);
+
+    cache.settings.format = RenderFormat::Sdf(sdf);
+    let image = cache.get_image(cache_keys[0]).clone().unwrap();
+    assert_eq!(image.content, Content::Mask);
+    assert_eq!(image.data.len(), (image.placement.width * image.placement.height) as usize);
+
+    // Fields are rendered once at the base size, and scaled to other font sizes
+    let large_keys = crate::buffer::test_cache_keys(&font_system, 64, "A");
+    let misses = cache.stats().misses;
+    let cached = cache.get_image(large_keys[0]).as_ref().unwrap();
+    assert_eq!((cached.placement.width, cached.placement.top), (image.placement.width, image.placement.top));
+    assert_eq!(cached.data, image.data);
+    assert_eq!(cache.stats().misses, misses);
+    assert_eq!(cache.image_scale(large_keys[0]), 2.0);
+    assert_eq!(cache.image_scale(cache_keys[0]), 1.0);
+    cache.settings.format = RenderFormat::Sdf(SdfSettings { base_size: 64, ..sdf });
+    let large = cache.get_image(cache_keys[0]).clone().unwrap();
+    assert!(large.placement.width > image.placement.width);
+    assert_eq!(cache.image_scale(cache_keys[0]), 0.5);
+}
@@ -0,0 +1,526 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Golden tests of shaping, wrapping, bidi, hit testing, and cursor movement, using the bundled
+//! test fonts. Run with `UPDATE_GOLDEN=1` to update the files in `tests/golden` after intended
+//! layout changes, and review the differences.
+
+use std::{
+    fmt::Write,
+    fs,
+    path::PathBuf,
+};
+
+use cosmic_text::{Attrs, AttrsList, Decoration, Family, FontSystem, TextAction, TextBuffer, TextMetrics};
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden").join(format!("{}.txt", name))
+}
+
+// Compare output with its golden file, or update the golden file if UPDATE_GOLDEN is set
+fn check_golden(name: &str, output: &str) {
+    let path = golden_path(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, output).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&path).unwrap_or_else(|err| {
+        panic!("failed to read '{}', run with UPDATE_GOLDEN=1 to create it: {}", path.display(), err)
+    });
+    if output != expected {
+        for (i, (output_line, expected_line)) in output.lines().zip(expected.lines()).enumerate() {
+            if output_line != expected_line {
+                panic!(
+                    "'{}' differs at line {}:\nexpected: {}\n  output: {}",
+                    path.display(),
+                    i + 1,
+                    expected_line,
+                    output_line
+                );
+            }
+        }
+        panic!(
+            "'{}' differs in length: expected {} lines, output {} lines",
+            path.display(),
+            expected.lines().count(),
+            output.lines().count()
+        );
+    }
+}
+
+// Write the visible layout runs and their glyphs
+fn dump_layout(font_system: &FontSystem, buffer: &TextBuffer) -> String {
+    let mut output = String::new();
+    for run in buffer.layout_runs() {
+        writeln!(output, "run line {} y {} rtl {}", run.line_i, run.line_y, run.rtl).unwrap();
+        for glyph in run.glyphs.iter() {
+            let font = font_system.face(glyph.cache_key.font_id)
+                .map_or("?", |face| face.post_script_name.as_str());
+            writeln!(
+                output,
+                "  {}..{} {:?} {} #{} x {:.2} w {:.2}{}",
+                glyph.start,
+                glyph.end,
+                &run.text[glyph.start..glyph.end],
+                font,
+                glyph.cache_key.glyph_id,
+                glyph.x,
+                glyph.w,
+                if glyph.rtl { " rtl" } else { "" }
+            ).unwrap();
+        }
+    }
+    output
+}
+
+fn layout_test(name: &str, text: &str, attrs: Attrs, width: i32) {
+    layout_test_locale(name, text, attrs, width, "en-US");
+}
+
+fn layout_test_locale(name: &str, text: &str, attrs: Attrs, width: i32, locale: &str) {
+    let mut font_system = FontSystem::new_test();
+    font_system.locale = locale.to_string();
+    let mut buffer = TextBuffer::new(&font_system, TextMetrics::new(16, 20));
+    buffer.set_size(width, 1000);
+    buffer.set_text(text, attrs);
+    check_golden(name, &dump_layout(&font_system, &buffer));
+}
+
+#[test]
+fn test_shaping() {
+    layout_test(
+        "shaping",
+        "Hello, world!\nAVAWAY office waffle\nÀ la carte, naïve façade",
+        Attrs::new(),
+        1000
+    );
+}
+
+#[test]
+fn test_shaping_monospace() {
+    layout_test(
+        "shaping_monospace",
+        "fn main() {\n    println!(\"Hello\");\n}",
+        Attrs::new().family(Family::Monospace),
+        1000
+    );
+}
+
+#[test]
+fn test_fallback() {
+    layout_test(
+        "fallback",
+        "Hello 안녕하세요 세계\nEmoji 💁🏻\u{200D}♂\u{FE0F} end",
+        Attrs::new(),
+        1000
+    );
+}
+
+#[test]
+fn test_fallback_default_family() {
+    use cosmic_text::{FallbackMode, FontFallbackConfig};
+    use unicode_script::Script;
+
+    // Fonts used for each glyph of the first line, with a fallback configuration
+    fn glyph_fonts<F: FnOnce(&mut FontFallbackConfig)>(configure: F, family: &str, text: &str) -> Vec<String> {
+        let mut font_system = FontSystem::new_test();
+        configure(font_system.fallback_mut());
+        let mut buffer = TextBuffer::new(&font_system, TextMetrics::new(16, 20));
+        buffer.set_size(1000, 1000);
+        buffer.set_text(text, Attrs::new().family(Family::Name(family)));
+        let run = buffer.layout_runs().next().unwrap();
+        run.glyphs.iter().map(|glyph| {
+            font_system.face(glyph.cache_key.font_id).unwrap().post_script_name.clone()
+        }).collect()
+    }
+
+    // A family prepended for Han comes before the requested family
+    let fonts = glyph_fonts(|fallback| {
+        fallback.set_script(Script::Han, &["Test Han JP"], FallbackMode::Prepend);
+    }, "Test Han SC", "漢字");
+    assert_eq!(fonts, ["TestHanJP-Regular", "TestHanJP-Regular"]);
+
+    // Families appended for Han come after it
+    let fonts = glyph_fonts(|fallback| {
+        fallback.set_script(Script::Han, &["Test Han JP"], FallbackMode::Append);
+    }, "Test Han SC", "漢字");
+    assert_eq!(fonts, ["TestHanSC-Regular", "TestHanSC-Regular"]);
+
+    // A forbidden requested family is not used, even if it covers the text
+    let fonts = glyph_fonts(|fallback| fallback.add_forbidden("Test Han SC"), "Test Han SC", "漢字");
+    assert_eq!(fonts, ["TestHanJP-Regular", "TestHanJP-Regular"]);
+}
+
+#[test]
+fn test_fallback_diagnostics() {
+    let font_system = FontSystem::new_test();
+    let mut buffer = TextBuffer::new(&font_system, TextMetrics::new(16, 20));
+    buffer.set_size(1000, 1000);
+    buffer.set_text("Hello 안녕 \u{10FFFD}\nשלום\n\u{0D9A}", Attrs::new());
+
+    let face_name = |font_id| font_system.face(font_id).unwrap().post_script_name.as_str();
+    let fallbacks: Vec<(usize, std::ops::Range<usize>, &str)> = buffer.fallbacks().into_iter()
+        .map(|(line_i, fallback)| (line_i, fallback.range, face_name(fallback.font_id)))
+        .collect();
+    assert_eq!(fallbacks, [(0, 6..12, "TestCJK-Regular")]);
+    assert_eq!(buffer.missing(), [(0, 13..17), (2, 0..3)]);
+
+    let font_names = |text| -> Vec<String> {
+        font_system.fonts_for_text(text).iter().map(|font| font.info.post_script_name.clone()).collect()
+    };
+    assert_eq!(font_names("안녕"), ["TestCJK-Regular"]);
+    assert_eq!(font_names("Hello\n"), ["DejaVuSans", "DejaVuSansMono"]);
+    assert_eq!(font_names("שלום"), ["DejaVuSans"]);
+    assert!(font_names("\u{10FFFD}").is_empty());
+}
+
+#[test]
+fn test_emoji_presentation() {
+    // Text presentation by default, emoji presentation with VS16 or in sequences
+    layout_test(
+        "emoji_presentation",
+        "♂ ♂\u{FE0F} 💁 💁\u{FE0E} 💁🏻 💁\u{200D}♂\u{FE0F}",
+        Attrs::new(),
+        1000
+    );
+    layout_test(
+        "emoji_presentation_bold",
+        "Bold ♂\u{FE0F} 💁🏻",
+        Attrs::new().weight(cosmic_text::Weight::BOLD),
+        1000
+    );
+}
+
+#[test]
+fn test_wrapping() {
+    layout_test(
+        "wrapping",
+        "The quick brown fox jumps over the lazy dog, then naps in the afternoon sun.\n\nAVeryLongWordThatDoesNotFitOnOneLine and more",
+        Attrs::new(),
+        120
+    );
+}
+
+#[test]
+fn test_bidi() {
+    layout_test(
+        "bidi",
+        "Hello שלום world\nשלום עולם 123\nمرحبا بالعالم\nLatin مرحبا Latin",
+        Attrs::new(),
+        1000
+    );
+}
+
+#[test]
+fn test_bidi_wrapping() {
+    layout_test(
+        "bidi_wrapping",
+        "مرحبا بالعالم، هذا نص عربي طويل\nשלום עולם, זהו טקסט עברי ארוך",
+        Attrs::new(),
+        100
+    );
+}
+
+#[test]
+fn test_han_unification() {
+    // The same Han characters use the Japanese font only for a Japanese locale, kana always do
+    let text = "日本語と中文：漢字、国と國。";
+    layout_test_locale("han_unification_zh", text, Attrs::new(), 1000, "zh-CN");
+    layout_test_locale("han_unification_ja", text, Attrs::new(), 1000, "ja-JP");
+}
+
+#[test]
+fn test_language_span() {
+    // A Japanese quote in Chinese text uses the Japanese font, and is shaped as Japanese, which
+    // substitutes the Japanese form of 漢 in the test fonts
+    let text = "中文「漢字」漢字";
+    let quote_start = text.find('「').unwrap();
+    let quote_end = text.find('」').unwrap() + '」'.len_utf8();
+
+    let mut font_system = FontSystem::new_test();
+    font_system.locale = "zh-CN".to_string();
+    let mut buffer = TextBuffer::new(&font_system, TextMetrics::new(16, 20));
+    buffer.set_size(1000, 1000);
+    buffer.set_text(text, Attrs::new());
+    let mut attrs_list = AttrsList::new(Attrs::new());
+    attrs_list.add_span(quote_start..quote_end, Attrs::new().language("ja"));
+    buffer.lines[0].set_attrs_list(attrs_list);
+    buffer.shape_until_scroll();
+    check_golden("language_span", &dump_layout(&font_system, &buffer));
+}
+
+#[test]
+fn test_cjk_itemization() {
+    // Han, kana, and Hangul share a run, with fallback for each script
+    layout_test(
+        "cjk_itemization",
+        "日本語のテキストと한국어 텍스트 and English\n漢字カタカナひらがな",
+        Attrs::new(),
+        1000
+    );
+}
+
+#[test]
+fn test_cjk_wrapping() {
+    // Lines break between ideographs without spaces, but not before closing punctuation
+    layout_test_locale(
+        "cjk_wrapping",
+        "今日は雨です。私は電車で駅に行きます。先生と学生は本を読みます。",
+        Attrs::new(),
+        100,
+        "ja-JP"
+    );
+}
+
+#[test]
+fn test_hit() {
+    let font_system = FontSystem::new_test();
+    let mut buffer = TextBuffer::new(&font_system, TextMetrics::new(16, 20));
+    buffer.set_size(1000, 1000);
+    buffer.set_text("Hello שלום world\nمرحبا Latin", Attrs::new());
+
+    let mut output = String::new();
+    for y in [10, 30, 50] {
+        for x in (-5..200).step_by(5) {
+            writeln!(output, "{} {}: {:?}", x, y, buffer.hit(x, y)).unwrap();
+        }
+    }
+    check_golden("hit", &output);
+}
+
+#[test]
+fn test_cursor_actions() {
+    let font_system = FontSystem::new_test();
+    let mut buffer = TextBuffer::new(&font_system, TextMetrics::new(16, 20));
+    buffer.set_size(100, 1000);
+    buffer.set_text("Hello שלום world\nThe second line wraps around\nمرحبا", Attrs::new());
+
+    let actions = [
+        TextAction::End,
+        TextAction::Left,
+        TextAction::Left,
+        TextAction::Previous,
+        TextAction::Previous,
+        TextAction::Previous,
+        TextAction::Previous,
+        TextAction::Previous,
+        TextAction::Home,
+        TextAction::Next,
+        TextAction::Down,
+        TextAction::Down,
+        TextAction::End,
+        TextAction::Down,
+        TextAction::Right,
+        TextAction::Up,
+        TextAction::Insert('x'),
+        TextAction::Insert('y'),
+        TextAction::Backspace,
+        TextAction::Enter,
+        TextAction::Delete,
+        TextAction::Click { x: 40, y: 10 },
+        TextAction::Drag { x: 80, y: 30 },
+        TextAction::Click { x: 500, y: 50 },
+    ];
+
+    let mut output = String::new();
+    for action in actions {
+        buffer.action(action);
+        let cursor = buffer.cursor();
+        writeln!(
+            output,
+            "{:?}: line {} index {} text {:?}",
+            action,
+            cursor.line,
+            cursor.index,
+            buffer.lines[cursor.line].text()
+        ).unwrap();
+    }
+    check_golden("cursor_actions", &output);
+}
+
+#[test]
+fn test_decorations() {
+    let font_system = FontSystem::new_test();
+    let mut buffer = TextBuffer::new(&font_system, TextMetrics::new(16, 20));
+    buffer.set_size(1000, 1000);
+    buffer.set_text("Hello, world!", Attrs::new());
+    let mut attrs_list = AttrsList::new(Attrs::new());
+    attrs_list.add_span(0..5, Attrs::new().decoration(Decoration::UNDERLINE));
+    attrs_list.add_span(7..12, Attrs::new().decoration(Decoration::UNDERLINE | Decoration::STRIKETHROUGH));
+    buffer.lines[0].set_attrs_list(attrs_list);
+    buffer.shape_until_scroll();
+
+    let mut output = String::new();
+    for run in buffer.layout_runs() {
+        for decoration in run.decorations(&font_system) {
+            writeln!(
+                output,
+                "{:?} x {:.2} y {:.2} w {:.2} h {:.2}",
+                decoration.kind,
+                decoration.x,
+                decoration.y,
+                decoration.w,
+                decoration.h
+            ).unwrap();
+        }
+    }
+    check_golden("decorations", &output);
+}
+
+#[cfg(feature = "swash")]
+#[test]
+fn test_outlines() {
+    use cosmic_text::{Color, PathCommand, SwashCache};
+
+    let font_system = FontSystem::new_test();
+    let mut buffer = TextBuffer::new(&font_system, TextMetrics::new(16, 20));
+    buffer.set_size(1000, 1000);
+    buffer.set_text("Hi o", Attrs::new().decoration(Decoration::UNDERLINE));
+    let mut cache = SwashCache::new(&font_system);
+
+    let mut paths = Vec::new();
+    buffer.outlines(&mut cache, Color::rgb(0, 0, 0), |path, _color| paths.push(path.to_vec()));
+
+    // Glyphs without outlines, like spaces, have no path. The underline is one rectangle.
+    assert_eq!(paths.len(), 4);
+    for path in paths.iter() {
+        assert!(matches!(path.first(), Some(PathCommand::MoveTo(..))));
+        assert_eq!(path.last(), Some(&PathCommand::Close));
+    }
+    // Outlines are positioned on the baseline of the first line
+    let baseline = buffer.layout_runs().next().unwrap().line_y as f32;
+    for command in paths[0].iter() {
+        if let PathCommand::LineTo(x, y) = command {
+            assert!(*x >= 0.0 && *x < 16.0);
+            assert!(*y > baseline - 16.0 && *y <= baseline);
+        }
+    }
+    // The 'o' has curves
+    assert!(paths[2].iter().any(|command| matches!(command, PathCommand::QuadTo(..))));
+    assert!(paths[3].len() == 5);
+}
+
+#[cfg(feature = "swash")]
+#[test]
+fn test_render() {
+    use cosmic_text::{Canvas, Color, PixelFormat, SwashCache};
+
+    let font_system = FontSystem::new_test();
+    let mut buffer = TextBuffer::new(&font_system, TextMetrics::new(16, 20));
+    buffer.set_size(40, 20);
+    buffer.set_text("Hello, world!", Attrs::new());
+    let mut cache = SwashCache::new(&font_system);
+
+    // Compare with drawing pixels one at a time, and check clipping to the buffer size
+    let (width, height) = (64, 32);
+    let mut expected = vec![0u32; width * height];
+    buffer.draw(&mut cache, Color::rgb(0, 0, 0), |x, y, w, h, color| {
+        for y in y..y + h as i32 {
+            for x in x..x + w as i32 {
+                let (x, y) = (x + 4, y + 6);
+                if (4..44).contains(&x) && (6..26).contains(&y) {
+                    let pixel = &mut expected[y as usize * width + x as usize];
+                    // Glyph pixels do not overlap in this text
+                    *pixel = (*pixel).max(color.a() as u32);
+                }
+            }
+        }
+    });
+
+    let mut data = vec![0; width * height * 4];
+    let mut canvas = Canvas::new(&mut data, width as u32, height as u32, PixelFormat::Rgba8Premultiplied);
+    buffer.render(&mut cache, Color::rgb(0, 0, 0), &mut canvas, 4, 6);
+    let alpha: Vec<u32> = data.chunks(4).map(|pixel| pixel[3] as u32).collect();
+    assert_eq!(alpha, expected);
+    assert!(alpha.contains(&0xFF));
+    assert!(data.chunks(4).all(|pixel| pixel[..3] == [0, 0, 0]));
+}
+
+#[cfg(feature = "swash")]
+#[test]
+fn test_draw_commands() {
+    use cosmic_text::{Color, DrawCommand, SwashCache};
+
+    let font_system = FontSystem::new_test();
+    let mut buffer = TextBuffer::new(&font_system, TextMetrics::new(16, 20));
+    buffer.set_size(200, 20);
+    buffer.set_text("Hi there", Attrs::new().decoration(Decoration::UNDERLINE));
+    let mut cache = SwashCache::new(&font_system);
+
+    let mut rects = Vec::new();
+    let mut images = Vec::new();
+    buffer.draw_commands(&mut cache, Color::rgb(0, 0, 0), |command| match command {
+        DrawCommand::Rect { x, y, w, h, .. } => rects.push((x, y, w, h)),
+        DrawCommand::Image { cache_key, x, y, image, .. } => {
+            assert!(!image.data.is_empty());
+            images.push((cache_key, x, y));
+        },
+    });
+
+    // One image per glyph except the space, at the glyph origin
+    let run = buffer.layout_runs().next().unwrap();
+    let expected: Vec<_> = run.glyphs.iter()
+        .filter(|glyph| glyph.start != 2)
+        .map(|glyph| (glyph.cache_key, glyph.x_int, run.line_y + glyph.y_int))
+        .collect();
+    assert_eq!(images, expected);
+
+    // The cursor, then one merged underline
+    assert_eq!(rects.len(), 2);
+    assert_eq!(rects[0].2, 1);
+    assert!(rects[1].2 > 40);
+}
+
+#[cfg(feature = "color")]
+#[test]
+fn test_color_glyphs() {
+    use cosmic_text::{Color, DrawCommand, Palette, SwashCache, SwashContent};
+
+    let font_system = FontSystem::new_test();
+    let mut cache = SwashCache::new(&font_system);
+
+    // Opaque pixels of the images of each glyph, from left to right
+    let mut images = |palette: Palette| {
+        let mut buffer = TextBuffer::new(&font_system, TextMetrics::new(32, 40));
+        buffer.set_size(400, 40);
+        buffer.set_text("ABCDST", Attrs::new().family(Family::Name("Test Color")).palette(palette));
+        let mut images = Vec::new();
+        buffer.draw_commands(&mut cache, Color::rgb(0x80, 0x80, 0x80), |command| {
+            if let DrawCommand::Image { image, .. } = command {
+                assert_eq!(image.content, SwashContent::Color);
+                let pixels: Vec<[u8; 3]> = image.data.chunks(4)
+                    .filter(|pixel| pixel[3] == 0xFF)
+                    .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+                    .collect();
+                assert!(!pixels.is_empty());
+                images.push(pixels);
+            }
+        });
+        assert_eq!(images.len(), 6);
+        images
+    };
+
+    let light = images(Palette::Default);
+    // COLRv1 linear gradient from the first to the second palette entry
+    let (first, last) = (light[0].first().unwrap(), light[0].last().unwrap());
+    assert!(first[0] > 0x80 && first[2] < 0x80, "{:?}", first);
+    assert!(last[0] < 0x80 && last[2] > 0x80, "{:?}", last);
+    // Solid palette entry, and radial gradient with a transform
+    assert!(light[1].iter().all(|pixel| *pixel == [0, 0x80, 0]));
+    assert!(light[2].iter().all(|pixel| pixel[1] == 0 && pixel[0] + pixel[2] >= 0xFE));
+    assert!(light[2].iter().any(|pixel| pixel[0] > 0x40 && pixel[2] > 0x40));
+    // Text color of dark text
+    assert!(light[3].iter().all(|pixel| *pixel == [0, 0, 0]));
+    // SVG documents with palette colors, gradients, and compression
+    assert!(light[4].contains(&[0xFF, 0, 0]));
+    assert!(light[4].iter().any(|pixel| pixel[2] > pixel[0]));
+    assert!(light[5].iter().all(|pixel| *pixel == [0, 0x80, 0xFF]));
+
+    // The second palette of the font is for dark backgrounds
+    let dark = images(Palette::Dark);
+    assert_eq!(images(Palette::Index(1)), dark);
+    assert_eq!(images(Palette::Light), light);
+    assert!(dark[1].iter().all(|pixel| *pixel == [0, 0xFF, 0]));
+    assert!(dark[4].contains(&[0xFF, 0xFF, 0]));
+    assert_eq!(dark[5], light[5]);
+}